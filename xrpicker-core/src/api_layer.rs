@@ -0,0 +1,111 @@
+// Copyright 2022-2024, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! API layer manifests, which we enumerate and describe but do not otherwise manage.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    manifest::{read_manifest, ApiLayerManifestContents, GenericManifest},
+    Error,
+};
+
+/// Whether an API layer is enabled automatically or must be requested by the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApiLayerKind {
+    Implicit,
+    Explicit,
+}
+
+impl ApiLayerKind {
+    /// Name of the directory holding this kind of manifest, on platforms that use directories.
+    pub(crate) fn directory_name(&self) -> &'static str {
+        match self {
+            ApiLayerKind::Implicit => "implicit.d",
+            ApiLayerKind::Explicit => "explicit.d",
+        }
+    }
+
+    /// Name of the registry key holding this kind of manifest, on platforms that use the registry.
+    pub(crate) fn registry_key_name(&self) -> &'static str {
+        match self {
+            ApiLayerKind::Implicit => "Implicit",
+            ApiLayerKind::Explicit => "Explicit",
+        }
+    }
+}
+
+impl Display for ApiLayerKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.registry_key_name())
+    }
+}
+
+/// The path and parsed data of an API layer manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiLayerManifest {
+    manifest_path: PathBuf,
+    kind: ApiLayerKind,
+    manifest: ApiLayerManifestContents,
+}
+
+impl ApiLayerManifest {
+    /// Create from a manifest path.
+    ///
+    /// Does not check whether the library is valid, just whether we can load and parse the JSON
+    /// according to our schema.
+    pub(crate) fn new(manifest_path: &Path, kind: ApiLayerKind) -> Result<Self, Error> {
+        let manifest: ApiLayerManifestContents = read_manifest(manifest_path)?;
+        if !manifest.is_file_format_version_ok() {
+            return Err(Error::ManifestVersionMismatch);
+        }
+        Ok(Self {
+            manifest_path: manifest_path.to_owned(),
+            kind,
+            manifest,
+        })
+    }
+
+    /// Get the path to our manifest
+    pub fn get_manifest_path(&self) -> &Path {
+        &self.manifest_path
+    }
+
+    /// Is this an implicit or explicit layer?
+    pub fn get_kind(&self) -> ApiLayerKind {
+        self.kind
+    }
+
+    /// Get the layer name, as used by applications to enable it.
+    pub fn get_name(&self) -> &str {
+        &self.manifest.api_layer.name
+    }
+
+    /// Get the OpenXR API version the layer was written against
+    pub fn get_api_version(&self) -> &str {
+        &self.manifest.api_layer.api_version
+    }
+
+    /// Get the version of the layer itself
+    pub fn get_implementation_version(&self) -> &str {
+        &self.manifest.api_layer.implementation_version
+    }
+
+    /// Get the human-readable description, if any
+    pub fn get_description(&self) -> Option<&str> {
+        self.manifest.api_layer.description.as_deref()
+    }
+
+    /// Get the environment variable that disables this layer, if any (implicit layers only)
+    pub fn get_disable_environment(&self) -> Option<&str> {
+        self.manifest.api_layer.disable_environment.as_deref()
+    }
+
+    /// Describe this layer using the manifest path and library path
+    pub fn describe(&self) -> String {
+        self.manifest.describe_manifest(&self.manifest_path)
+    }
+}
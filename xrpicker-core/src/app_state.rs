@@ -1,20 +1,173 @@
 // Copyright 2022-2023, Collabora, Ltd.
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use std::{iter, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    iter,
+    path::PathBuf,
+    time::SystemTime,
+};
 
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
-use crate::{platform::PlatformRuntime, Error, ManifestError, Platform};
+use crate::{
+    path_util::normalize_path,
+    platform::{
+        ActiveRuntimeDiagnosis, ConfigLocation, ConfigSource, MakeActiveOptions, PlatformRuntime,
+        SearchedDirectory, DEFAULT_MAX_BACKUPS,
+    },
+    ApiLayerManifest, Error, ManifestError, Platform,
+};
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+/// Which color theme the GUI should use.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ThemePreference {
+    /// Follow the system's preference
+    System,
+    Light,
+    #[default]
+    Dark,
+}
+
+impl ThemePreference {
+    /// The next preference, for cycling through them with a single button.
+    pub fn next(self) -> Self {
+        match self {
+            ThemePreference::System => ThemePreference::Light,
+            ThemePreference::Light => ThemePreference::Dark,
+            ThemePreference::Dark => ThemePreference::System,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct PersistentAppState {
-    /// The extra paths provided by the user: the only thing we really want to serialize
+    /// The extra paths provided by the user
     pub extra_paths: Vec<PathBuf>,
+
+    /// How many backups of the previous active runtime to keep, 0 for all.
+    #[serde(default = "default_max_backups")]
+    pub max_backups: usize,
+
+    /// Whether to write architecture-specific active runtimes where supported.
+    #[serde(default)]
+    pub arch_decorated: bool,
+
+    /// Whether to link to the manifest when making a runtime active, rather than copying it.
+    #[serde(default = "default_use_symlink")]
+    pub use_symlink: bool,
+
+    /// The inner size of the window when last closed, if known.
+    #[serde(default)]
+    pub window_size: Option<[f32; 2]>,
+
+    /// The outer position of the window when last closed, if known.
+    #[serde(default)]
+    pub window_pos: Option<[f32; 2]>,
+
+    /// The color theme to use.
+    #[serde(default)]
+    pub theme: ThemePreference,
+
+    /// The `uniqueness_key()` of runtimes in the order the user arranged them.
+    /// Runtimes not listed go after these.
+    #[serde(default)]
+    pub runtime_order: Vec<Vec<PathBuf>>,
+
+    /// Names the user gave runtimes, by `uniqueness_key()`, to show instead of their own.
+    #[serde(default)]
+    pub custom_names: HashMap<Vec<PathBuf>, String>,
+
+    /// Extra directories to search for runtime manifests, where supported.
+    #[serde(default)]
+    pub extra_search_dirs: Vec<PathBuf>,
+
+    /// When each runtime was last made active from here, by `uniqueness_key()`.
+    #[serde(default)]
+    pub last_activated: HashMap<Vec<PathBuf>, SystemTime>,
+}
+
+fn default_use_symlink() -> bool {
+    true
+}
+
+fn default_max_backups() -> usize {
+    DEFAULT_MAX_BACKUPS
+}
+
+impl Default for PersistentAppState {
+    fn default() -> Self {
+        Self {
+            extra_paths: Default::default(),
+            max_backups: default_max_backups(),
+            arch_decorated: false,
+            use_symlink: default_use_symlink(),
+            window_size: None,
+            window_pos: None,
+            theme: ThemePreference::default(),
+            runtime_order: Vec::new(),
+            custom_names: HashMap::new(),
+            extra_search_dirs: Vec::new(),
+            last_activated: HashMap::new(),
+        }
+    }
 }
 
 impl PersistentAppState {
+    /// Get the options to pass to `PlatformRuntime::make_active_with_options`
+    pub fn make_active_options(&self) -> MakeActiveOptions {
+        MakeActiveOptions {
+            max_backups: self.max_backups,
+            arch_decorated: self.arch_decorated,
+            use_symlink: self.use_symlink,
+            ..Default::default()
+        }
+    }
+
+    /// The name to show for a runtime: the user's name for it if any, otherwise its own.
+    pub fn display_name<R: PlatformRuntime>(&self, runtime: &R) -> String {
+        self.custom_names
+            .get(&runtime.uniqueness_key())
+            .cloned()
+            .unwrap_or_else(|| runtime.get_runtime_name())
+    }
+
+    /// Set the user's name for the runtime with the given `uniqueness_key()`.
+    /// A blank name goes back to the runtime's own name.
+    pub fn set_custom_name(&mut self, key: Vec<PathBuf>, name: &str) {
+        let name = name.trim();
+        if name.is_empty() {
+            self.custom_names.remove(&key);
+        } else {
+            self.custom_names.insert(key, name.to_owned());
+        }
+    }
+
+    /// Remember that the runtime with the given `uniqueness_key()` was just made active.
+    ///
+    /// Making a runtime active does not have access to this state, so call this after it succeeds.
+    pub fn record_activation(&mut self, key: Vec<PathBuf>) {
+        self.last_activated.insert(key, SystemTime::now());
+    }
+
+    /// When the runtime was last made active from here, if ever.
+    pub fn last_activated<R: PlatformRuntime>(&self, runtime: &R) -> Option<SystemTime> {
+        self.last_activated.get(&runtime.uniqueness_key()).copied()
+    }
+
+    /// Keep custom names and activation times attached to their runtimes when the runtimes'
+    /// keys change.
+    ///
+    /// An entry whose runtime is gone moves to a runtime sharing a path with its old key (e.g. one
+    /// that gained a manifest for another architecture), if there is exactly one. Otherwise the
+    /// entry is kept, in case the runtime comes back.
+    pub fn migrate_runtime_keys<R: PlatformRuntime>(&mut self, runtimes: &[R]) {
+        let keys: Vec<Vec<PathBuf>> = runtimes.iter().map(|r| r.uniqueness_key()).collect();
+        migrate_keys(&mut self.custom_names, &keys);
+        migrate_keys(&mut self.last_activated, &keys);
+    }
+
     pub fn append_new_extra_paths(&mut self, new_extra_paths: Vec<PathBuf>) {
         if !new_extra_paths.is_empty() {
             let old_extra_paths = std::mem::take(&mut self.extra_paths);
@@ -22,6 +175,54 @@ impl PersistentAppState {
                 .extend(old_extra_paths.into_iter().chain(new_extra_paths).unique());
         }
     }
+
+    /// Like `append_new_extra_paths`, but drops any path that resolves (e.g. through a symlink)
+    /// to a manifest of one of the `discovered` runtimes, or to an existing extra path.
+    ///
+    /// Those would be found anyway, and platforms differ in how well they hide the duplicates.
+    pub fn append_new_extra_paths_except_known<R: PlatformRuntime>(
+        &mut self,
+        new_extra_paths: Vec<PathBuf>,
+        discovered: &[R],
+    ) {
+        let mut known: HashSet<PathBuf> = discovered
+            .iter()
+            .flat_map(|r| r.get_manifests())
+            .map(normalize_path)
+            .chain(self.extra_paths.iter().map(|p| normalize_path(p)))
+            .collect();
+        let new_extra_paths = new_extra_paths
+            .into_iter()
+            .filter(|p| {
+                let is_new = known.insert(normalize_path(p));
+                if !is_new {
+                    log::debug!("Not remembering {}: it is already known", p.display());
+                }
+                is_new
+            })
+            .collect();
+        self.append_new_extra_paths(new_extra_paths);
+    }
+}
+
+/// Move the entries of `map` whose keys are not in `keys` to the single key sharing a path with
+/// theirs, if there is exactly one without an entry already.
+fn migrate_keys<V>(map: &mut HashMap<Vec<PathBuf>, V>, keys: &[Vec<PathBuf>]) {
+    let orphaned: Vec<Vec<PathBuf>> = map.keys().filter(|k| !keys.contains(k)).cloned().collect();
+    for old_key in orphaned {
+        let Ok(new_key) = keys
+            .iter()
+            .filter(|k| !map.contains_key(*k))
+            .filter(|k| k.iter().any(|p| old_key.contains(p)))
+            .exactly_one()
+        else {
+            continue;
+        };
+        if let Some(value) = map.remove(&old_key) {
+            log::debug!("Moving saved runtime data to a runtime with a new key");
+            map.insert(new_key.clone(), value);
+        }
+    }
 }
 
 trait IterateExtraPaths {
@@ -58,18 +259,65 @@ pub struct AppState<T: Platform> {
     pub runtimes: Vec<T::PlatformRuntimeType>,
     pub nonfatal_errors: Vec<ManifestError>,
     pub active_data: T::PlatformActiveData,
+    /// API layers, which are only listed for information.
+    pub api_layers: Vec<ApiLayerManifest>,
+    /// Whether the active runtime reference is broken, checked when loading.
+    pub active_diagnosis: ActiveRuntimeDiagnosis,
+    /// The active runtime settings the loader would consider, in its order of precedence.
+    pub active_candidates: Vec<(ConfigLocation, ConfigSource)>,
+    /// The directories searched for runtimes, and what was found in each.
+    pub search_summary: Vec<SearchedDirectory>,
+}
+
+/// Work out the architecture of each runtime now, which involves reading its libraries,
+/// so that displaying it later is cheap.
+fn resolve_arch_descriptions<R: PlatformRuntime>(runtimes: &[R]) {
+    for runtime in runtimes {
+        runtime.get_arch_description();
+        runtime.is_compatible_with_host();
+    }
+}
+
+/// Put runtimes in the order the user arranged them, with any others after, in their existing order.
+fn apply_saved_order<R: PlatformRuntime>(runtimes: &mut [R], runtime_order: &[Vec<PathBuf>]) {
+    if runtime_order.is_empty() {
+        return;
+    }
+    // Stable sort, so unknown runtimes keep their relative order
+    runtimes.sort_by_cached_key(|r| {
+        let key = r.uniqueness_key();
+        runtime_order
+            .iter()
+            .position(|k| *k == key)
+            .unwrap_or(usize::MAX)
+    });
 }
 
 impl<T: Platform> AppState<T> {
     /// Try creating state from scratch
     pub fn new(platform: &T) -> Result<Self, Error> {
-        let (runtimes, nonfatal_errors) =
+        let state = Self::new_lazy(platform)?;
+        resolve_arch_descriptions(&state.runtimes);
+        Ok(state)
+    }
+
+    /// Try creating state from scratch, without inspecting runtime libraries yet.
+    ///
+    /// Call `resolve_next_arch_description()` until it returns false to finish the job.
+    pub fn new_lazy(platform: &T) -> Result<Self, Error> {
+        let (runtimes, mut nonfatal_errors) =
             platform.find_available_runtimes(Box::new(iter::empty()))?;
+        let (api_layers, api_layer_errors) = platform.find_available_api_layers()?;
+        nonfatal_errors.extend(api_layer_errors);
         let active_data = platform.get_active_data();
         Ok(Self {
             runtimes,
             nonfatal_errors,
             active_data,
+            api_layers,
+            active_diagnosis: platform.diagnose_active_runtime(),
+            active_candidates: platform.get_active_runtime_candidates(),
+            search_summary: platform.get_loader_search_summary(),
         })
     }
 
@@ -77,13 +325,33 @@ impl<T: Platform> AppState<T> {
         platform: &T,
         persistent_state: &PersistentAppState,
     ) -> Result<Self, Error> {
-        let (runtimes, nonfatal_errors) =
+        let state = Self::new_lazy_with_persistent_state(platform, persistent_state)?;
+        resolve_arch_descriptions(&state.runtimes);
+        Ok(state)
+    }
+
+    /// Like `new_with_persistent_state()`, but without inspecting runtime libraries yet.
+    ///
+    /// Call `resolve_next_arch_description()` until it returns false to finish the job.
+    pub fn new_lazy_with_persistent_state(
+        platform: &T,
+        persistent_state: &PersistentAppState,
+    ) -> Result<Self, Error> {
+        platform.set_extra_search_dirs(&persistent_state.extra_search_dirs);
+        let (mut runtimes, mut nonfatal_errors) =
             platform.find_available_runtimes(persistent_state.iterate_extra_paths())?;
+        let (api_layers, api_layer_errors) = platform.find_available_api_layers()?;
+        nonfatal_errors.extend(api_layer_errors);
         let active_data = platform.get_active_data();
+        apply_saved_order(&mut runtimes, &persistent_state.runtime_order);
         Ok(Self {
             runtimes,
             nonfatal_errors,
             active_data,
+            api_layers,
+            active_diagnosis: platform.diagnose_active_runtime(),
+            active_candidates: platform.get_active_runtime_candidates(),
+            search_summary: platform.get_loader_search_summary(),
         })
     }
 
@@ -94,30 +362,252 @@ impl<T: Platform> AppState<T> {
         platform: &T,
         persistent_state: Option<&PersistentAppState>,
     ) -> Result<Self, Error> {
-        let (new_runtimes, new_nonfatal_errors) =
+        if let Some(persistent_state) = persistent_state {
+            platform.set_extra_search_dirs(&persistent_state.extra_search_dirs);
+        }
+        let (new_runtimes, mut new_nonfatal_errors) =
             platform.find_available_runtimes(persistent_state.iterate_extra_paths())?;
+        let (api_layers, api_layer_errors) = platform.find_available_api_layers()?;
+        new_nonfatal_errors.extend(api_layer_errors);
 
         let active_data = platform.get_active_data();
 
         // start with existing runtimes
-        let runtimes = self
+        let mut runtimes = self
             .runtimes
             .into_iter()
             // chain on the new ones
             .chain(new_runtimes)
             // only keep the unique ones, preferring the earlier ones
-            .unique_by(|r| {
-                // compare by the list of manifests used
-                r.get_manifests()
-                    .into_iter()
-                    .map(|p| p.to_owned())
-                    .collect::<Vec<_>>()
-            })
-            .collect();
+            .unique_by(|r| r.uniqueness_key())
+            .collect_vec();
+        resolve_arch_descriptions(&runtimes);
+        if let Some(persistent_state) = persistent_state {
+            apply_saved_order(&mut runtimes, &persistent_state.runtime_order);
+        }
         Ok(Self {
             runtimes,
             nonfatal_errors: new_nonfatal_errors,
             active_data,
+            api_layers,
+            active_diagnosis: platform.diagnose_active_runtime(),
+            active_candidates: platform.get_active_runtime_candidates(),
+            search_summary: platform.get_loader_search_summary(),
         })
     }
+
+    /// Re-read just the active runtime settings if they have changed, without re-enumerating.
+    ///
+    /// Returns false if that is not enough, and a full `refresh()` is needed: when a runtime
+    /// that is not listed became active, or an unusable active runtime is listed.
+    pub fn refresh_active_state(&mut self, platform: &T) -> bool {
+        if !platform.active_data_is_stale(&self.active_data) {
+            return true;
+        }
+        self.active_data = platform.get_active_data();
+        self.active_diagnosis = platform.diagnose_active_runtime();
+        self.active_candidates = platform.get_active_runtime_candidates();
+        let listed_manifests: HashSet<PathBuf> = self
+            .runtimes
+            .iter()
+            .flat_map(|r| r.get_manifests())
+            .map(normalize_path)
+            .collect();
+        let active_is_listed = platform
+            .get_active_runtime_manifests()
+            .iter()
+            .all(|m| listed_manifests.contains(&normalize_path(m)));
+        active_is_listed
+            && !self
+                .nonfatal_errors
+                .iter()
+                .any(|e| e.is_for_active_runtime())
+    }
+
+    /// Inspect the libraries of the next runtime whose architecture is not known yet.
+    ///
+    /// Returns true if there may be more to do, so this can be spread across frames.
+    pub fn resolve_next_arch_description(&self) -> bool {
+        match self
+            .runtimes
+            .iter()
+            .find(|r| !r.is_arch_description_resolved())
+        {
+            Some(runtime) => {
+                runtime.get_arch_description();
+                runtime.is_compatible_with_host();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move the runtime at index `from` so it ends up at index `to`, shifting the others.
+    ///
+    /// Out of range indices are ignored. Save `runtime_order()` to keep this order across restarts.
+    pub fn move_runtime(&mut self, from: usize, to: usize) {
+        if from >= self.runtimes.len() || to >= self.runtimes.len() {
+            return;
+        }
+        let runtime = self.runtimes.remove(from);
+        self.runtimes.insert(to, runtime);
+    }
+
+    /// Add a manifest chosen by the user, remembering it in `persistent_state` only if it
+    /// can actually be loaded as a runtime.
+    ///
+    /// On error, nothing is changed, so a bad choice does not turn into an error on every launch.
+    pub fn try_add_extra_manifest(
+        &mut self,
+        platform: &T,
+        persistent_state: &mut PersistentAppState,
+        path: PathBuf,
+    ) -> Result<(), Error> {
+        let runtime = platform.load_extra_runtime(&path)?;
+        persistent_state.append_new_extra_paths_except_known(vec![path], &self.runtimes);
+        let key = runtime.uniqueness_key();
+        if !self.runtimes.iter().any(|r| r.uniqueness_key() == key) {
+            runtime.get_arch_description();
+            runtime.is_compatible_with_host();
+            self.runtimes.push(runtime);
+        }
+        Ok(())
+    }
+
+    /// The current order of the runtimes, suitable for `PersistentAppState::runtime_order`.
+    pub fn runtime_order(&self) -> Vec<Vec<PathBuf>> {
+        self.runtimes.iter().map(|r| r.uniqueness_key()).collect()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{linux::LinuxPlatform, test_util::write_manifest};
+    use std::{fs, path::Path};
+    use tempfile::TempDir;
+
+    /// A platform whose config directories are all in a temporary directory.
+    fn make_platform(root: &Path) -> LinuxPlatform {
+        LinuxPlatform::with_roots(root.join("home"), vec![], root.join("etc"))
+    }
+
+    const LIBRARY: &str = "/usr/lib/libopenxr_example.so";
+
+    #[test]
+    fn invalid_extra_manifest_is_not_persisted() {
+        let root = TempDir::new().unwrap();
+        let platform = make_platform(root.path());
+        let mut persistent = PersistentAppState::default();
+        let mut state = AppState::new_with_persistent_state(&platform, &persistent).unwrap();
+        assert!(state.runtimes.is_empty());
+
+        let missing = root.path().join("missing.json");
+        assert!(state
+            .try_add_extra_manifest(&platform, &mut persistent, missing)
+            .is_err());
+        let broken = root.path().join("broken.json");
+        fs::write(&broken, "{ not json").unwrap();
+        assert!(state
+            .try_add_extra_manifest(&platform, &mut persistent, broken)
+            .is_err());
+        assert!(persistent.extra_paths.is_empty());
+        assert!(state.runtimes.is_empty());
+
+        let valid = write_manifest(root.path(), "valid.json", LIBRARY, None);
+        state
+            .try_add_extra_manifest(&platform, &mut persistent, valid.clone())
+            .unwrap();
+        assert_eq!(persistent.extra_paths, vec![valid]);
+        assert_eq!(state.runtimes.len(), 1);
+    }
+
+    #[test]
+    fn symlinked_duplicate_extra_path_is_dropped() {
+        let root = TempDir::new().unwrap();
+        let platform = make_platform(root.path());
+        let system = write_manifest(
+            &root.path().join("home/openxr/1"),
+            "system.json",
+            LIBRARY,
+            None,
+        );
+        let state = AppState::new(&platform).unwrap();
+        assert_eq!(state.runtimes.len(), 1);
+
+        let link = root.path().join("link.json");
+        std::os::unix::fs::symlink(&system, &link).unwrap();
+        let other = write_manifest(root.path(), "other.json", LIBRARY, None);
+        let other_link = root.path().join("other-link.json");
+        std::os::unix::fs::symlink(&other, &other_link).unwrap();
+
+        let mut persistent = PersistentAppState::default();
+        persistent.append_new_extra_paths_except_known(
+            vec![link, system, other.clone()],
+            &state.runtimes,
+        );
+        assert_eq!(persistent.extra_paths, vec![other.clone()]);
+
+        // A link to an existing extra path is a duplicate too
+        persistent.append_new_extra_paths_except_known(vec![other_link], &state.runtimes);
+        assert_eq!(persistent.extra_paths, vec![other]);
+    }
+
+    #[test]
+    fn refresh_lists_symlinked_extra_path_once() {
+        let root = TempDir::new().unwrap();
+        let platform = make_platform(root.path());
+        let system = write_manifest(
+            &root.path().join("home/openxr/1"),
+            "system.json",
+            LIBRARY,
+            None,
+        );
+        let other = write_manifest(root.path(), "other.json", "/opt/other/libother.so", None);
+        let link = root.path().join("link.json");
+        std::os::unix::fs::symlink(&system, &link).unwrap();
+        let other_link = root.path().join("other-link.json");
+        std::os::unix::fs::symlink(&other, &other_link).unwrap();
+        let state = AppState::new(&platform).unwrap();
+        assert_eq!(state.runtimes.len(), 1);
+
+        // As if saved by an older version, which did not drop these duplicates
+        let persistent = PersistentAppState {
+            extra_paths: vec![link, other_link, other],
+            ..Default::default()
+        };
+        let state = state.refresh(&platform, Some(&persistent)).unwrap();
+        assert_eq!(state.runtimes.len(), 2);
+    }
+
+    #[test]
+    fn names_and_activation_times_follow_changed_keys() {
+        let root = TempDir::new().unwrap();
+        let platform = make_platform(root.path());
+        write_manifest(
+            &root.path().join("home/openxr/1"),
+            "runtime.json",
+            LIBRARY,
+            None,
+        );
+        let gone = root.path().join("gone.json");
+        let state = AppState::new(&platform).unwrap();
+        let key = state.runtimes[0].uniqueness_key();
+
+        // As if the runtime used to have another manifest
+        let old_key = vec![gone.clone(), key[0].clone()];
+        let mut persistent = PersistentAppState::default();
+        persistent.set_custom_name(old_key.clone(), "Mine");
+        persistent.record_activation(old_key);
+        persistent.set_custom_name(vec![gone.clone()], "Unrelated");
+        persistent.record_activation(vec![gone.clone()]);
+
+        persistent.migrate_runtime_keys(&state.runtimes);
+        assert_eq!(persistent.display_name(&state.runtimes[0]), "Mine");
+        assert!(persistent.last_activated(&state.runtimes[0]).is_some());
+        // Entries for runtimes that are just gone are kept
+        assert!(persistent.custom_names.contains_key(&vec![gone.clone()]));
+        assert!(persistent.last_activated.contains_key(&vec![gone]));
+        assert_eq!(persistent.last_activated.len(), 2);
+    }
 }
@@ -0,0 +1,303 @@
+// Copyright 2024, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Architecture/ABI identifiers, as used by the OpenXR loader to decorate active runtime
+//! file names (e.g. `active_runtime.x86_64.json`).
+
+use std::{cmp::Reverse, fmt::Display};
+
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::ACTIVE_RUNTIME_FILENAME;
+
+/// An architecture/ABI combination known to the OpenXR loader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter)]
+pub enum RuntimeArchAbi {
+    X86_64,
+    I686,
+    Aarch64,
+    Armv7aVfp,
+    Armv5te,
+    Mips64,
+    Mips,
+    Ppc64,
+    Ppc64el,
+    S390x,
+    Hppa,
+    Alpha,
+    Ia64,
+    M68k,
+    Riscv64,
+    Sparc64,
+}
+
+impl RuntimeArchAbi {
+    /// The identifier the loader uses for this architecture/ABI
+    pub fn identifier(self) -> &'static str {
+        match self {
+            RuntimeArchAbi::X86_64 => "x86_64",
+            RuntimeArchAbi::I686 => "i686",
+            RuntimeArchAbi::Aarch64 => "aarch64",
+            RuntimeArchAbi::Armv7aVfp => "armv7a-vfp",
+            RuntimeArchAbi::Armv5te => "armv5te",
+            RuntimeArchAbi::Mips64 => "mips64",
+            RuntimeArchAbi::Mips => "mips",
+            RuntimeArchAbi::Ppc64 => "ppc64",
+            RuntimeArchAbi::Ppc64el => "ppc64el",
+            RuntimeArchAbi::S390x => "s390x",
+            RuntimeArchAbi::Hppa => "hppa",
+            RuntimeArchAbi::Alpha => "alpha",
+            RuntimeArchAbi::Ia64 => "ia64",
+            RuntimeArchAbi::M68k => "m68k",
+            RuntimeArchAbi::Riscv64 => "riscv64",
+            RuntimeArchAbi::Sparc64 => "sparc64",
+        }
+    }
+
+    /// The pointer width of this architecture/ABI, in bits.
+    pub fn bit_width(self) -> u32 {
+        use RuntimeArchAbi::*;
+        match self {
+            I686 | Armv7aVfp | Armv5te | Mips | Hppa | M68k => 32,
+            X86_64 | Aarch64 | Mips64 | Ppc64 | Ppc64el | S390x | Alpha | Ia64 | Riscv64
+            | Sparc64 => 64,
+        }
+    }
+
+    /// A key to sort by so that each family stays together (x86, ARM, MIPS, PowerPC, then the
+    /// rest), with 64-bit before 32-bit, and newer ABIs first within that.
+    ///
+    /// This is also the order of `Ord`.
+    pub fn sort_key(self) -> (u8, Reverse<u32>, u8) {
+        use RuntimeArchAbi::*;
+        let family = match self {
+            X86_64 | I686 => 0,
+            Aarch64 | Armv7aVfp | Armv5te => 1,
+            Mips64 | Mips => 2,
+            Ppc64 | Ppc64el => 3,
+            S390x | Hppa | Alpha | Ia64 | M68k | Riscv64 | Sparc64 => 4,
+        };
+        // Declaration order breaks ties
+        (family, Reverse(self.bit_width()), self as u8)
+    }
+
+    /// The suffix to replace `.json` with in a decorated file name, e.g. `.x86_64.json`
+    pub fn filename_suffix(self) -> String {
+        format!(".{}.json", self.identifier())
+    }
+
+    /// Work out the decoration of a file name like `active_runtime.x86_64.json` by matching
+    /// its ending against `filename_suffix()` for each architecture/ABI.
+    ///
+    /// Anything without a known architecture before `.json` is `Unspecified`.
+    pub fn from_filename(name: &str) -> ManifestArchDecoration {
+        Self::iter()
+            .find(|arch| name.ends_with(&arch.filename_suffix()))
+            .map_or(ManifestArchDecoration::Unspecified, |arch| {
+                ManifestArchDecoration::Specified(arch)
+            })
+    }
+
+    /// The architecture/ABI of this build, if it is one the loader knows about.
+    ///
+    /// Anything that depends on the host architecture should accept it as a parameter
+    /// rather than calling this directly, so it can be exercised for other architectures.
+    pub fn get_current_arch() -> Option<Self> {
+        if cfg!(target_arch = "x86_64") {
+            Some(RuntimeArchAbi::X86_64)
+        } else if cfg!(target_arch = "x86") {
+            Some(RuntimeArchAbi::I686)
+        } else if cfg!(target_arch = "aarch64") {
+            Some(RuntimeArchAbi::Aarch64)
+        } else if cfg!(all(target_arch = "arm", target_feature = "v7")) {
+            Some(RuntimeArchAbi::Armv7aVfp)
+        } else if cfg!(target_arch = "arm") {
+            Some(RuntimeArchAbi::Armv5te)
+        } else if cfg!(target_arch = "mips64") {
+            Some(RuntimeArchAbi::Mips64)
+        } else if cfg!(target_arch = "mips") {
+            Some(RuntimeArchAbi::Mips)
+        } else if cfg!(all(target_arch = "powerpc64", target_endian = "little")) {
+            Some(RuntimeArchAbi::Ppc64el)
+        } else if cfg!(target_arch = "powerpc64") {
+            Some(RuntimeArchAbi::Ppc64)
+        } else if cfg!(target_arch = "s390x") {
+            Some(RuntimeArchAbi::S390x)
+        } else if cfg!(target_arch = "m68k") {
+            Some(RuntimeArchAbi::M68k)
+        } else if cfg!(target_arch = "riscv64") {
+            Some(RuntimeArchAbi::Riscv64)
+        } else if cfg!(target_arch = "sparc64") {
+            Some(RuntimeArchAbi::Sparc64)
+        } else {
+            None
+        }
+    }
+
+    /// Can apps on the given host architecture load a runtime built for this architecture/ABI?
+    ///
+    /// A host also counts as running the older or 32-bit members of its own family
+    /// (e.g. x86_64 runs i686), since apps for those may be running there too.
+    pub fn is_compatible_with(self, host: RuntimeArchAbi) -> bool {
+        use RuntimeArchAbi::*;
+        self == host
+            || matches!(
+                (host, self),
+                (X86_64, I686)
+                    | (Aarch64, Armv7aVfp)
+                    | (Aarch64, Armv5te)
+                    | (Armv7aVfp, Armv5te)
+                    | (Mips64, Mips)
+            )
+    }
+
+    /// Can apps on this computer load a runtime built for this architecture/ABI?
+    ///
+    /// If this build's architecture is not one the loader knows about, we can't tell,
+    /// so this assumes they can.
+    pub fn is_compatible_with_host(self) -> bool {
+        match Self::get_current_arch() {
+            Some(host) => self.is_compatible_with(host),
+            None => true,
+        }
+    }
+}
+
+impl Ord for RuntimeArchAbi {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+impl PartialOrd for RuntimeArchAbi {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for RuntimeArchAbi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.identifier())
+    }
+}
+
+/// Whether a manifest/active runtime file name is decorated with an architecture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ManifestArchDecoration {
+    /// Plain file name like `active_runtime.json`
+    Unspecified,
+    /// Decorated file name like `active_runtime.x86_64.json`
+    Specified(RuntimeArchAbi),
+}
+
+impl ManifestArchDecoration {
+    /// The decoration the loader would look for first on the given host architecture.
+    pub fn for_host(host_arch: Option<RuntimeArchAbi>) -> Self {
+        match host_arch {
+            Some(arch) => ManifestArchDecoration::Specified(arch),
+            None => ManifestArchDecoration::Unspecified,
+        }
+    }
+
+    /// The decoration the loader would look for first on this build's architecture.
+    pub fn for_current_arch() -> Self {
+        Self::for_host(RuntimeArchAbi::get_current_arch())
+    }
+
+    /// The suffix to use in place of `.json`: either `.json` or something like `.x86_64.json`
+    pub fn filename_suffix(&self) -> String {
+        match self {
+            ManifestArchDecoration::Unspecified => ".json".to_owned(),
+            ManifestArchDecoration::Specified(arch) => arch.filename_suffix(),
+        }
+    }
+
+    /// The active runtime file name with this decoration, e.g. `active_runtime.x86_64.json`
+    pub fn active_runtime_filename(&self) -> String {
+        let stem = ACTIVE_RUNTIME_FILENAME
+            .strip_suffix(".json")
+            .expect("active runtime filename ends in .json");
+        format!("{}{}", stem, self.filename_suffix())
+    }
+}
+
+/// The active runtime file names the loader checks on the given host architecture,
+/// in the order it checks them: the decorated name first (if the arch is known), then the plain one.
+pub fn active_runtime_filenames_for_host(host_arch: Option<RuntimeArchAbi>) -> Vec<String> {
+    let decorated = ManifestArchDecoration::for_host(host_arch);
+    let mut names = vec![decorated.active_runtime_filename()];
+    if decorated != ManifestArchDecoration::Unspecified {
+        names.push(ManifestArchDecoration::Unspecified.active_runtime_filename());
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    #[test]
+    fn grouped_order() {
+        use RuntimeArchAbi::*;
+        let sorted: Vec<RuntimeArchAbi> = RuntimeArchAbi::iter().sorted().collect();
+        assert_eq!(
+            sorted,
+            [
+                X86_64, I686, Aarch64, Armv7aVfp, Armv5te, Mips64, Mips, Ppc64, Ppc64el, S390x,
+                Alpha, Ia64, Riscv64, Sparc64, Hppa, M68k,
+            ]
+        );
+    }
+
+    #[test]
+    fn decoration_from_filename() {
+        use ManifestArchDecoration::*;
+        assert_eq!(
+            RuntimeArchAbi::from_filename("active_runtime.x86_64.json"),
+            Specified(RuntimeArchAbi::X86_64)
+        );
+        assert_eq!(
+            RuntimeArchAbi::from_filename("active_runtime.aarch64.json"),
+            Specified(RuntimeArchAbi::Aarch64)
+        );
+        assert_eq!(
+            RuntimeArchAbi::from_filename("active_runtime.json"),
+            Unspecified
+        );
+        // One identifier being a prefix of another must not confuse them
+        assert_eq!(
+            RuntimeArchAbi::from_filename("foo.mips64.json"),
+            Specified(RuntimeArchAbi::Mips64)
+        );
+        assert_eq!(
+            RuntimeArchAbi::from_filename("foo.mips.json"),
+            Specified(RuntimeArchAbi::Mips)
+        );
+        assert_eq!(RuntimeArchAbi::from_filename("foomips.json"), Unspecified);
+    }
+
+    #[test]
+    fn decoration_for_host() {
+        let aarch64 = ManifestArchDecoration::for_host(Some(RuntimeArchAbi::Aarch64));
+        assert_eq!(aarch64.filename_suffix(), ".aarch64.json");
+        assert_eq!(
+            aarch64.active_runtime_filename(),
+            "active_runtime.aarch64.json"
+        );
+        assert_eq!(
+            active_runtime_filenames_for_host(Some(RuntimeArchAbi::Aarch64)),
+            ["active_runtime.aarch64.json", "active_runtime.json"]
+        );
+
+        // Without a known host architecture, only the plain name is checked
+        assert_eq!(
+            ManifestArchDecoration::for_host(None),
+            ManifestArchDecoration::Unspecified
+        );
+        assert_eq!(
+            active_runtime_filenames_for_host(None),
+            ["active_runtime.json"]
+        );
+    }
+}
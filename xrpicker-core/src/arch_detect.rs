@@ -1,61 +1,274 @@
 // Copyright 2022-2023, Collabora, Ltd.
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-//! Not actually windows specific, but not yet used on Linux.
+//! Inspecting runtime binaries to figure out what they can be loaded by.
 
-use crate::{runtime::BaseRuntime, Error, ManifestError};
-use object::{self, read::Object};
-use std::{fs, path::Path};
+use crate::{
+    manifest::GenericManifest, runtime::BaseRuntime, Error, ManifestError, RuntimeArchAbi,
+};
+use itertools::Itertools;
+use object::{
+    self,
+    read::{
+        macho::{FatArch, MachOFatFile32, MachOFatFile64},
+        Object,
+    },
+    Architecture, FileKind,
+};
+use serde::Serialize;
+use std::{fmt::Display, fs, path::Path};
 
 /// A single manifest may only be one of these values.
-pub(crate) enum RuntimeBitness {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RuntimeBitness {
     /// Uses shared library search path to find the right binary per arch
+    #[serde(rename = "universal")]
     Universal,
     /// Points to a 32-bit runtime
+    #[serde(rename = "32-bit")]
     BitWidth32,
     /// Points to a 64-bit runtime
+    #[serde(rename = "64-bit")]
     BitWidth64,
 }
 
+impl Display for RuntimeBitness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeBitness::Universal => write!(f, "universal (search path)"),
+            RuntimeBitness::BitWidth32 => write!(f, "32-bit"),
+            RuntimeBitness::BitWidth64 => write!(f, "64-bit"),
+        }
+    }
+}
+
 /// Investigate a manifest and the runtime binary to which it refers, to identify whether it is
 /// 32-bit, 64-bit, or universal (using shared library search path)
-pub(crate) fn get_runtime_bitness(manifest_path: &Path) -> Result<RuntimeBitness, ManifestError> {
+pub fn get_runtime_bitness(manifest_path: &Path) -> Result<RuntimeBitness, ManifestError> {
     let runtime =
         BaseRuntime::new(manifest_path).map_err(|e| ManifestError(manifest_path.to_owned(), e))?;
+    get_library_bitness(&runtime)
+}
+
+/// What a runtime manifest's binary can be loaded by: more specific than `RuntimeBitness`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeArch {
+    /// Uses shared library search path to find the right binary per arch
+    Universal,
+    /// Points to a binary for a single architecture/ABI
+    Specific(RuntimeArchAbi),
+    /// Points to a "fat" binary (Mach-O) containing code for each of these architectures
+    Fat(Vec<RuntimeArchAbi>),
+}
+
+impl Display for RuntimeArch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeArch::Universal => write!(f, "universal (search path)"),
+            RuntimeArch::Specific(abi) => write!(f, "{}", abi),
+            RuntimeArch::Fat(abis) => write!(f, "universal ({})", abis.iter().join(", ")),
+        }
+    }
+}
+
+/// Investigate a manifest and the runtime binary to which it refers, to identify the
+/// architecture/ABI it was built for, or whether it is universal (using shared library search path
+/// or a fat binary)
+pub fn get_runtime_arch(manifest_path: &Path) -> Result<RuntimeArch, ManifestError> {
+    let runtime =
+        BaseRuntime::new(manifest_path).map_err(|e| ManifestError(manifest_path.to_owned(), e))?;
+    get_library_arch(&runtime)
+}
+
+/// Describe the architecture of an already-loaded runtime's binary, for display.
+///
+/// Use `BaseRuntime::describe_arch`, which also works without the `arch-detect` feature.
+pub(crate) fn describe_runtime_arch(runtime: &BaseRuntime) -> String {
+    get_library_arch(runtime)
+        .map(|arch| arch.to_string())
+        .unwrap_or_else(|_| "unknown".to_owned())
+}
+
+fn get_library_bitness(runtime: &BaseRuntime) -> Result<RuntimeBitness, ManifestError> {
+    Ok(match inspect_library(runtime)? {
+        LibraryInfo::SearchPath | LibraryInfo::Fat(_) => RuntimeBitness::Universal,
+        LibraryInfo::Single { is_64: true, .. } => RuntimeBitness::BitWidth64,
+        LibraryInfo::Single { is_64: false, .. } => RuntimeBitness::BitWidth32,
+    })
+}
+
+pub(crate) fn get_library_arch(runtime: &BaseRuntime) -> Result<RuntimeArch, ManifestError> {
+    match inspect_library(runtime)? {
+        LibraryInfo::SearchPath => Ok(RuntimeArch::Universal),
+        LibraryInfo::Single { abi: Some(abi), .. } => Ok(RuntimeArch::Specific(abi)),
+        LibraryInfo::Single { abi: None, .. } => {
+            Err(make_binary_load_error(&runtime.resolve_library_path()))
+        }
+        LibraryInfo::Fat(abis) => Ok(RuntimeArch::Fat(abis)),
+    }
+}
+
+/// Map the machine type of a binary onto the architectures the loader knows about.
+fn arch_abi_for(architecture: Architecture, little_endian: bool) -> Option<RuntimeArchAbi> {
+    match architecture {
+        Architecture::X86_64 => Some(RuntimeArchAbi::X86_64),
+        Architecture::I386 => Some(RuntimeArchAbi::I686),
+        Architecture::Aarch64 => Some(RuntimeArchAbi::Aarch64),
+        // Can't tell armv5te from armv7a-vfp without digging through attributes: assume the common one
+        Architecture::Arm => Some(RuntimeArchAbi::Armv7aVfp),
+        Architecture::Mips64 => Some(RuntimeArchAbi::Mips64),
+        Architecture::Mips => Some(RuntimeArchAbi::Mips),
+        Architecture::PowerPc64 if little_endian => Some(RuntimeArchAbi::Ppc64el),
+        Architecture::PowerPc64 => Some(RuntimeArchAbi::Ppc64),
+        Architecture::S390x => Some(RuntimeArchAbi::S390x),
+        Architecture::Riscv64 => Some(RuntimeArchAbi::Riscv64),
+        Architecture::Sparc64 => Some(RuntimeArchAbi::Sparc64),
+        _ => None,
+    }
+}
+
+/// What we could learn about a runtime binary
+enum LibraryInfo {
+    /// Found using the shared library search path, so we can't inspect a single binary
+    SearchPath,
+    /// A binary for a single architecture, which we may or may not recognize
+    Single {
+        is_64: bool,
+        abi: Option<RuntimeArchAbi>,
+    },
+    /// A fat binary, with the architectures we recognize in it
+    Fat(Vec<RuntimeArchAbi>),
+}
+
+fn make_binary_load_error(library_path: &Path) -> ManifestError {
+    ManifestError(
+        library_path.to_owned(),
+        Error::RuntimeBinaryLoadError(library_path.display().to_string()),
+    )
+}
+
+/// Load and inspect the binary an already-loaded runtime manifest refers to.
+fn inspect_library(runtime: &BaseRuntime) -> Result<LibraryInfo, ManifestError> {
+    if runtime.uses_search_path() {
+        // The search path picks the right binary per arch
+        return Ok(LibraryInfo::SearchPath);
+    }
     let library_path = runtime.resolve_library_path();
     if !library_path.is_absolute() {
         // If we can't resolve it, it must be universal
-        return Ok(RuntimeBitness::Universal);
+        return Ok(LibraryInfo::SearchPath);
     }
 
-    let make_err = || {
-        ManifestError(
-            library_path.clone(),
-            Error::RuntimeBinaryLoadError(library_path.display().to_string()),
-        )
-    };
+    let make_err = || make_binary_load_error(&library_path);
 
     let bin_data = fs::read(&library_path).map_err(|_| make_err())?;
-    let obj_file = object::File::parse(&*bin_data).map_err(|_| make_err())?;
-    if obj_file.is_64() {
-        Ok(RuntimeBitness::BitWidth64)
-    } else {
-        Ok(RuntimeBitness::BitWidth32)
-    }
+    let fat_arches = match FileKind::parse(&*bin_data).map_err(|_| make_err())? {
+        FileKind::MachOFat32 => MachOFatFile32::parse(&*bin_data)
+            .map(|fat| fat.arches().iter().map(|a| a.architecture()).collect_vec()),
+        FileKind::MachOFat64 => MachOFatFile64::parse(&*bin_data)
+            .map(|fat| fat.arches().iter().map(|a| a.architecture()).collect_vec()),
+        _ => {
+            let obj_file = object::File::parse(&*bin_data).map_err(|_| make_err())?;
+            return Ok(LibraryInfo::Single {
+                is_64: obj_file.is_64(),
+                abi: arch_abi_for(obj_file.architecture(), obj_file.is_little_endian()),
+            });
+        }
+    };
+    let abis = fat_arches
+        .map_err(|_| make_err())?
+        .into_iter()
+        // Mach-O is only big-endian on PowerPC, which is the only place it matters
+        .filter_map(|a| arch_abi_for(a, false))
+        .collect();
+    Ok(LibraryInfo::Fat(abis))
 }
 
-pub(crate) trait PushUnique<T> {
-    fn push_unique(&mut self, val: T);
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{write_library, write_manifest, EM_AARCH64, EM_X86_64};
+    use tempfile::TempDir;
+
+    /// Write a library with the given ELF machine type, and a manifest for it.
+    fn manifest_for_elf(dir: &Path, machine: u16) -> std::path::PathBuf {
+        let library = dir.join("libruntime.so");
+        write_library(&library, machine);
+        write_manifest(dir, "runtime.json", &library.to_string_lossy(), None)
+    }
+
+    #[test]
+    fn elf_machine_types() {
+        let temp = TempDir::new().unwrap();
+        let x86_64 = manifest_for_elf(&temp.path().join("x86_64"), EM_X86_64);
+        assert_eq!(
+            get_runtime_arch(&x86_64).unwrap(),
+            RuntimeArch::Specific(RuntimeArchAbi::X86_64)
+        );
+        assert_eq!(
+            get_runtime_bitness(&x86_64).unwrap(),
+            RuntimeBitness::BitWidth64
+        );
+
+        let aarch64 = manifest_for_elf(&temp.path().join("aarch64"), EM_AARCH64);
+        assert_eq!(
+            get_runtime_arch(&aarch64).unwrap(),
+            RuntimeArch::Specific(RuntimeArchAbi::Aarch64)
+        );
+    }
 
-impl<T> PushUnique<T> for Vec<T>
-where
-    T: Eq,
-{
-    fn push_unique(&mut self, val: T) {
-        let contains = self.contains(&val);
-        if !contains {
-            self.push(val);
+    #[test]
+    fn search_path_library_is_universal() {
+        let temp = TempDir::new().unwrap();
+        // Not read at all, so it need not exist
+        let manifest = write_manifest(temp.path(), "runtime.json", "libruntime.so", None);
+        assert_eq!(get_runtime_arch(&manifest).unwrap(), RuntimeArch::Universal);
+        assert_eq!(
+            get_runtime_bitness(&manifest).unwrap(),
+            RuntimeBitness::Universal
+        );
+    }
+
+    #[test]
+    fn fat_binary_lists_its_architectures() {
+        const FAT_MAGIC: u32 = 0xcafe_babe;
+        const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+        const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+        // A fat header and two fat_arch entries, all big-endian, without the slices themselves
+        let mut header = Vec::new();
+        for word in [FAT_MAGIC, 2] {
+            header.extend_from_slice(&word.to_be_bytes());
+        }
+        for cpu_type in [CPU_TYPE_X86_64, CPU_TYPE_ARM64] {
+            // cputype, cpusubtype, offset, size, align
+            for word in [cpu_type, 0, 0, 0, 0] {
+                header.extend_from_slice(&word.to_be_bytes());
+            }
         }
+        let temp = TempDir::new().unwrap();
+        let library = temp.path().join("libruntime.dylib");
+        std::fs::write(&library, header).unwrap();
+        let manifest = write_manifest(
+            temp.path(),
+            "runtime.json",
+            &library.to_string_lossy(),
+            None,
+        );
+
+        assert_eq!(
+            get_runtime_arch(&manifest).unwrap(),
+            RuntimeArch::Fat(vec![RuntimeArchAbi::X86_64, RuntimeArchAbi::Aarch64])
+        );
+        assert_eq!(
+            get_runtime_bitness(&manifest).unwrap(),
+            RuntimeBitness::Universal
+        );
+    }
+
+    #[test]
+    fn unreadable_library_is_an_error() {
+        let temp = TempDir::new().unwrap();
+        let manifest = write_manifest(temp.path(), "runtime.json", "./libmissing.so", None);
+        assert!(get_runtime_arch(&manifest).is_err());
     }
 }
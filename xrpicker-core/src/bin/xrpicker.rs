@@ -1,16 +1,138 @@
 // Copyright 2022-2023, Collabora, Ltd.
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use std::iter;
+use std::{
+    ffi::OsString,
+    fs, iter,
+    path::{Path, PathBuf},
+};
 
-use xrpicker::{make_platform, platform::PlatformRuntime, Platform};
+use itertools::Itertools;
+use serde::Serialize;
+#[cfg(feature = "arch-detect")]
+use xrpicker::{get_runtime_bitness, RuntimeBitness};
+use xrpicker::{
+    make_platform,
+    platform::{ActiveRuntimeDiagnosis, MakeActiveOptions, PlatformRuntime},
+    ActiveState, Platform, VerifyResult,
+};
 
-fn main() {
-    let platform = make_platform();
+const HELP: &str = "\
+Usage: xrpicker [OPTIONS] [COMMAND]
+
+Commands:
+  (none)   List the available runtimes and the active runtime
+  paths    Print the locations that would be searched (without searching them)
+           and where making a runtime active writes to
+  list     List the available runtimes with stable indices, sorted by manifest path
+  set (--name NAME | --manifest PATH | --index N) [--arch-decorated] [--copy] [--user] [--dry-run]
+           Make a runtime active, selected by name, manifest path, or index
+           from \"list\". With --arch-decorated, only make it active for apps
+           of the runtime's architecture, where supported. With --copy, copy
+           the manifest instead of linking to it, where supported. With
+           --user, only make it active for the current user (Windows). With
+           --dry-run, print what would be changed without changing it.
+  verify [--static]
+           Check that each runtime's library exists and provides the OpenXR
+           negotiate function. With --static, only read the libraries, never
+           load them, even in builds that normally do.
+  prune --keep N
+           Delete all but the newest N backups of previous active runtimes
+           (Linux only)
+  cleanup  Remove registrations of runtimes whose manifest no longer exists
+           (Windows only)
+  export (--name NAME | --manifest PATH | --index N) --to DIR
+           Write an active_runtime.json for a runtime into DIR, e.g. to use
+           with a different XDG_CONFIG_HOME, without changing the active runtime
+  run (--name NAME | --manifest PATH | --index N) -- PROGRAM [ARGS...]
+           Run a program with a runtime active for that process only,
+           using XR_RUNTIME_JSON (on Windows, only 64-bit programs)
+
+Options:
+  --json   List the available runtimes as JSON instead
+  --unset  Clear the active runtime, returning to the loader's defaults
+";
+
+/// The JSON representation of a runtime
+#[derive(Serialize)]
+struct RuntimeJson {
+    name: String,
+    manifest_paths: Vec<PathBuf>,
+    library_paths: Vec<PathBuf>,
+    active_state: ActiveState,
+    /// The negotiate function name, if the manifest renames it
+    negotiate_function: Option<String>,
+    /// Bitness for each manifest, in the same order, or null if it could not be determined
+    #[cfg(feature = "arch-detect")]
+    arch: Vec<Option<RuntimeBitness>>,
+}
+
+impl RuntimeJson {
+    fn new<T: Platform>(
+        platform: &T,
+        runtime: &T::PlatformRuntimeType,
+        active_data: &T::PlatformActiveData,
+    ) -> Self {
+        let manifest_paths: Vec<PathBuf> = runtime
+            .get_manifests()
+            .into_iter()
+            .map(|p| p.to_owned())
+            .collect();
+        #[cfg(feature = "arch-detect")]
+        let arch = manifest_paths
+            .iter()
+            .map(|p| get_runtime_bitness(p).ok())
+            .collect();
+        Self {
+            name: runtime.get_runtime_name(),
+            manifest_paths,
+            library_paths: runtime.get_libraries(),
+            active_state: platform.get_runtime_active_state(runtime, active_data),
+            negotiate_function: runtime.get_negotiate_function_name(),
+            #[cfg(feature = "arch-detect")]
+            arch,
+        }
+    }
+}
+
+/// Print the runtimes as JSON, returning the exit code.
+fn list_runtimes_json<T: Platform>(platform: &T) -> i32 {
     let active_data = platform.get_active_data();
-    let (runtimes, nonfatal_errors) = platform
-        .find_available_runtimes(Box::new(iter::empty()))
-        .unwrap();
+    let runtimes = match platform.find_available_runtimes(Box::new(iter::empty())) {
+        Ok((runtimes, _nonfatal_errors)) => runtimes,
+        Err(e) => {
+            eprintln!("Could not enumerate runtimes: {}", e);
+            return 1;
+        }
+    };
+    let runtimes: Vec<RuntimeJson> = runtimes
+        .iter()
+        .map(|r| RuntimeJson::new(platform, r, &active_data))
+        .collect();
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&runtimes).expect("serializing runtimes cannot fail")
+    );
+    0
+}
+
+/// Print the runtimes and the active runtime in detail, returning the exit code.
+fn list_runtimes<T: Platform>(platform: &T) -> i32
+where
+    T::PlatformRuntimeType: std::fmt::Debug,
+{
+    if let Some(warning) = platform.get_sandbox_warning() {
+        println!("{}", warning);
+    }
+    let active_data = platform.get_active_data();
+    let (runtimes, nonfatal_errors) =
+        match platform.find_available_runtimes(Box::new(iter::empty())) {
+            Ok(found) => found,
+            Err(e) => {
+                eprintln!("Could not enumerate runtimes: {}", e);
+                return 1;
+            }
+        };
     println!("\nRuntimes:");
     for runtime in runtimes {
         println!(
@@ -19,6 +141,9 @@ fn main() {
             platform.get_runtime_active_state(&runtime, &active_data),
             runtime
         );
+        if let Some(reason) = platform.describe_active_reason(&runtime, &active_data) {
+            println!("  {}", reason);
+        }
     }
 
     if !nonfatal_errors.is_empty() {
@@ -33,4 +158,431 @@ fn main() {
     for path in platform.get_active_runtime_manifests() {
         println!("- {}", path.display());
     }
+
+    match platform.get_active_runtime() {
+        Ok(Some(runtime)) => println!("\nCurrently active: {}", runtime.get_runtime_name()),
+        Ok(None) => println!("\nNo runtime is currently active"),
+        Err(e) => println!("\nCould not load the active runtime: {}", e),
+    }
+    match platform.diagnose_active_runtime() {
+        ActiveRuntimeDiagnosis::Dangling { link, target } => println!(
+            "Active runtime link is broken: {} points at {}, which no longer exists.",
+            link,
+            target.display()
+        ),
+        ActiveRuntimeDiagnosis::SymlinkLoop { link } => println!(
+            "Active runtime link is broken: {} is a symlink that leads back to itself.",
+            link.display()
+        ),
+        _ => {}
+    }
+    0
+}
+
+fn print_search_locations<T: Platform>(platform: &T) {
+    println!("Search locations, in order:");
+    for location in platform.get_search_locations() {
+        println!("- {}", location);
+    }
+    if let Some(path) = platform.get_active_runtime_write_path() {
+        println!("Making a runtime active writes to: {}", path.display());
+    }
+    if let Some(warning) = platform.get_sandbox_warning() {
+        println!("{}", warning);
+    }
+}
+
+/// Find the available runtimes, sorted by canonical manifest path so that indices are
+/// stable across runs given the same filesystem/registry contents.
+fn find_sorted_runtimes<T: Platform>(
+    platform: &T,
+    extra_paths: Vec<PathBuf>,
+) -> Result<Vec<T::PlatformRuntimeType>, xrpicker::Error> {
+    let (runtimes, _nonfatal_errors) =
+        platform.find_available_runtimes(Box::new(extra_paths.into_iter()))?;
+    Ok(runtimes
+        .into_iter()
+        .sorted_by_cached_key(|r| {
+            r.get_manifests()
+                .iter()
+                .map(|m| fs::canonicalize(m).unwrap_or_else(|_| m.to_path_buf()))
+                .collect_vec()
+        })
+        .collect())
+}
+
+/// Print the runtimes one per line, with the indices `set` and `run` accept, returning the exit code.
+fn list_runtimes_indexed<T: Platform>(platform: &T) -> i32 {
+    let active_data = platform.get_active_data();
+    let runtimes = match find_sorted_runtimes(platform, vec![]) {
+        Ok(runtimes) => runtimes,
+        Err(e) => {
+            eprintln!("Could not enumerate runtimes: {}", e);
+            return 1;
+        }
+    };
+    for (i, runtime) in runtimes.iter().enumerate() {
+        let state = platform.get_runtime_active_state(runtime, &active_data);
+        println!(
+            "{}\t{}\t{}\t{}",
+            i,
+            runtime.get_runtime_name(),
+            if state.is_active() { "active" } else { "-" },
+            runtime
+                .get_manifests()
+                .iter()
+                .map(|p| p.display())
+                .join(", ")
+        );
+    }
+    0
+}
+
+/// Check each runtime's library, returning the exit code: nonzero if any has a problem.
+///
+/// With `static_only`, libraries are only read, never loaded, whatever features are enabled.
+fn verify_runtimes<T: Platform>(platform: &T, static_only: bool) -> i32 {
+    let runtimes = match find_sorted_runtimes(platform, vec![]) {
+        Ok(runtimes) => runtimes,
+        Err(e) => {
+            eprintln!("Could not enumerate runtimes: {}", e);
+            return 1;
+        }
+    };
+    let mut all_ok = true;
+    for runtime in &runtimes {
+        let result = if static_only {
+            runtime.open_negotiate_check()
+        } else {
+            runtime.verify()
+        };
+        // Not being able to check is not a failure
+        all_ok &= result.is_ok() || matches!(result, VerifyResult::Unverifiable(_));
+        println!("{}\t{}", runtime.get_runtime_name(), result);
+    }
+    if all_ok {
+        0
+    } else {
+        1
+    }
+}
+
+/// How a single runtime was chosen on the command line.
+enum RuntimeSelector {
+    Name(String),
+    /// A manifest path, which need not be one we would find on our own.
+    Manifest(PathBuf),
+    /// An index in the output of `list`
+    Index(usize),
+}
+
+impl RuntimeSelector {
+    fn from_args(args: &mut pico_args::Arguments) -> Result<Self, pico_args::Error> {
+        if let Some(path) = args.opt_value_from_os_str("--manifest", |s| {
+            Ok::<_, std::convert::Infallible>(PathBuf::from(s))
+        })? {
+            return Ok(Self::Manifest(path));
+        }
+        if let Some(index) = args.opt_value_from_str("--index")? {
+            return Ok(Self::Index(index));
+        }
+        args.value_from_str("--name").map(Self::Name)
+    }
+
+    /// Find the selected runtime, printing an error if we cannot.
+    fn find<T: Platform>(&self, platform: &T) -> Option<T::PlatformRuntimeType> {
+        let extra_paths: Vec<PathBuf> = match self {
+            RuntimeSelector::Manifest(path) => vec![path.clone()],
+            _ => vec![],
+        };
+        let runtimes = match find_sorted_runtimes(platform, extra_paths) {
+            Ok(runtimes) => runtimes,
+            Err(e) => {
+                eprintln!("Could not enumerate runtimes: {}", e);
+                return None;
+            }
+        };
+        let found = match self {
+            RuntimeSelector::Name(name) => {
+                runtimes.into_iter().find(|r| r.get_runtime_name() == *name)
+            }
+            RuntimeSelector::Manifest(path) => {
+                let wanted = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+                runtimes.into_iter().find(|r| {
+                    r.get_manifests()
+                        .iter()
+                        .any(|m| *m == wanted || fs::canonicalize(m).ok() == Some(wanted.clone()))
+                })
+            }
+            RuntimeSelector::Index(index) => runtimes.into_iter().nth(*index),
+        };
+        if found.is_none() {
+            match self {
+                RuntimeSelector::Name(name) => eprintln!("No runtime named {} found", name),
+                RuntimeSelector::Index(index) => eprintln!("No runtime with index {} found", index),
+                RuntimeSelector::Manifest(path) => {
+                    eprintln!("Could not load a runtime from {}", path.display())
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Make the selected runtime active (or just describe what that would do), returning the exit code.
+fn set_runtime<T: Platform>(
+    platform: &T,
+    selector: &RuntimeSelector,
+    options: &MakeActiveOptions,
+    dry_run: bool,
+) -> i32 {
+    let Some(runtime) = selector.find(platform) else {
+        return 1;
+    };
+    if dry_run {
+        return match runtime.preview_make_active_with_options(options) {
+            Ok(plan) => {
+                println!("Making {} active would:", runtime.get_runtime_name());
+                for step in &plan.steps {
+                    println!("- {}", step);
+                }
+                0
+            }
+            Err(e) => {
+                eprintln!(
+                    "Could not work out how to make {} active: {}",
+                    runtime.get_runtime_name(),
+                    e
+                );
+                1
+            }
+        };
+    }
+    match runtime.make_active_with_options(options) {
+        Ok(()) => {
+            match runtime.get_active_runtime_write_path(options) {
+                Some(path) => println!(
+                    "Made {} active by writing {}",
+                    runtime.get_runtime_name(),
+                    path.display()
+                ),
+                None => println!("Made {} active", runtime.get_runtime_name()),
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!(
+                "Could not make {} active: {}",
+                runtime.get_runtime_name(),
+                e
+            );
+            1
+        }
+    }
+}
+
+/// Clear the active runtime, returning the exit code.
+fn unset_runtime<T: Platform>(platform: &T) -> i32 {
+    match platform.clear_active_runtime() {
+        Ok(true) => println!("Cleared the active runtime"),
+        Ok(false) => println!("There was no active runtime to clear"),
+        Err(e) => {
+            eprintln!("Could not clear the active runtime: {}", e);
+            return 1;
+        }
+    }
+    // We can only clear what we can write to, so mention anything that remains.
+    for manifest in platform.get_active_runtime_manifests() {
+        println!(
+            "Note: {} is still active from a system-wide setting",
+            manifest.display()
+        );
+    }
+    0
+}
+
+/// Write an `active_runtime.json` for the selected runtime into `dir`, returning the exit code.
+fn export_runtime<T: Platform>(platform: &T, selector: &RuntimeSelector, dir: &Path) -> i32 {
+    let Some(runtime) = selector.find(platform) else {
+        return 1;
+    };
+    match runtime.export_active_runtime_to(dir) {
+        Ok(path) => {
+            println!("Wrote {}", path.display());
+            0
+        }
+        Err(e) => {
+            eprintln!(
+                "Could not export {} to {}: {}",
+                runtime.get_runtime_name(),
+                dir.display(),
+                e
+            );
+            1
+        }
+    }
+}
+
+/// Run a program with the selected runtime active just for it, returning its exit code.
+fn run_with_runtime<T: Platform>(
+    platform: &T,
+    selector: &RuntimeSelector,
+    command: &[OsString],
+) -> i32 {
+    let Some((program, program_args)) = command.split_first() else {
+        eprintln!("No program given to run\n\n{}", HELP);
+        return 1;
+    };
+    let Some(runtime) = selector.find(platform) else {
+        return 1;
+    };
+    // For a runtime with both, this uses the 64-bit manifest, so 32-bit programs can't load it
+    match runtime
+        .command_with_runtime(program)
+        .args(program_args)
+        .status()
+    {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(e) => {
+            eprintln!("Could not run {}: {}", program.to_string_lossy(), e);
+            1
+        }
+    }
+}
+
+/// Report a problem with the arguments and exit.
+fn exit_with_argument_error(e: pico_args::Error) -> ! {
+    eprintln!("Error parsing arguments: {}\n\n{}", e, HELP);
+    std::process::exit(1);
+}
+
+/// Exit with an error if the command did not use all the arguments, including `--json`
+/// unless it was used.
+fn finish_args(args: pico_args::Arguments, unused_json: bool) {
+    let mut unused = args.finish();
+    if unused_json {
+        unused.insert(0, "--json".into());
+    }
+    if !unused.is_empty() {
+        let unused: Vec<_> = unused.iter().map(|a| a.to_string_lossy()).collect();
+        eprintln!("Unexpected arguments: {}\n\n{}", unused.join(" "), HELP);
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    env_logger::init();
+    // Everything after "--" belongs to the program being run, not to us.
+    let mut args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    let trailing = match args.iter().position(|a| a == "--") {
+        Some(i) => {
+            let trailing = args.split_off(i + 1);
+            args.pop();
+            trailing
+        }
+        None => Vec::new(),
+    };
+    let mut args = pico_args::Arguments::from_vec(args);
+    if args.contains(["-h", "--help"]) {
+        print!("{}", HELP);
+        return;
+    }
+
+    let json = args.contains("--json");
+
+    if args.contains("--unset") {
+        finish_args(args, json);
+        std::process::exit(unset_runtime(&make_platform()));
+    }
+
+    let platform = make_platform();
+    match args.subcommand() {
+        Ok(None) => {
+            finish_args(args, false);
+            if json {
+                std::process::exit(list_runtimes_json(&platform));
+            }
+            std::process::exit(list_runtimes(&platform));
+        }
+        Ok(Some(command)) if command == "paths" => {
+            finish_args(args, json);
+            print_search_locations(&platform);
+        }
+        Ok(Some(command)) if command == "list" => {
+            finish_args(args, json);
+            std::process::exit(list_runtimes_indexed(&platform))
+        }
+        Ok(Some(command)) if command == "verify" => {
+            let static_only = args.contains("--static");
+            finish_args(args, json);
+            std::process::exit(verify_runtimes(&platform, static_only));
+        }
+        Ok(Some(command)) if command == "set" => {
+            let options = MakeActiveOptions {
+                arch_decorated: args.contains("--arch-decorated"),
+                use_symlink: !args.contains("--copy"),
+                per_user: args.contains("--user"),
+                ..Default::default()
+            };
+            let dry_run = args.contains("--dry-run");
+            let selector = RuntimeSelector::from_args(&mut args)
+                .unwrap_or_else(|e| exit_with_argument_error(e));
+            finish_args(args, json);
+            #[cfg(windows)]
+            if !options.per_user && platform.needs_elevation_to_set_active() {
+                eprintln!("Note: changing the active runtime requires running as administrator");
+            }
+            std::process::exit(set_runtime(&platform, &selector, &options, dry_run));
+        }
+        #[cfg(unix)]
+        Ok(Some(command)) if command == "prune" => {
+            let keep: usize = args
+                .value_from_str("--keep")
+                .unwrap_or_else(|e| exit_with_argument_error(e));
+            finish_args(args, json);
+            match platform.prune_old_active_runtimes(keep) {
+                Ok(removed) => println!("Removed {} old active runtime backup(s)", removed),
+                Err(e) => {
+                    eprintln!("Could not remove old active runtime backups: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        #[cfg(windows)]
+        Ok(Some(command)) if command == "cleanup" => {
+            finish_args(args, json);
+            match platform.cleanup_stale_available_runtimes() {
+                Ok(removed) => {
+                    for path in &removed {
+                        println!("Removed registration of missing {}", path.display());
+                    }
+                    println!("Removed {} stale runtime registration(s)", removed.len());
+                }
+                Err(e) => {
+                    eprintln!("Could not remove stale runtime registrations: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Ok(Some(command)) if command == "export" => {
+            let dir: PathBuf = args
+                .value_from_str("--to")
+                .unwrap_or_else(|e| exit_with_argument_error(e));
+            let selector = RuntimeSelector::from_args(&mut args)
+                .unwrap_or_else(|e| exit_with_argument_error(e));
+            finish_args(args, json);
+            std::process::exit(export_runtime(&platform, &selector, &dir));
+        }
+        Ok(Some(command)) if command == "run" => {
+            let selector = RuntimeSelector::from_args(&mut args)
+                .unwrap_or_else(|e| exit_with_argument_error(e));
+            finish_args(args, json);
+            std::process::exit(run_with_runtime(&platform, &selector, &trailing));
+        }
+        Ok(Some(command)) => {
+            eprintln!("Unrecognized command: {}\n\n{}", command, HELP);
+            std::process::exit(1);
+        }
+        Err(e) => exit_with_argument_error(e),
+    }
 }
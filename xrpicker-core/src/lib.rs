@@ -1,32 +1,68 @@
 // Copyright 2022-2023, Collabora, Ltd.
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-#![forbid(unsafe_code)]
+// Loading libraries to verify them needs unsafe, so only allow that where we opt in.
+#![cfg_attr(not(feature = "dlopen-verify"), forbid(unsafe_code))]
+#![cfg_attr(feature = "dlopen-verify", deny(unsafe_code))]
 
 pub const OPENXR_MAJOR_VERSION: i32 = 1;
 
 pub const ACTIVE_RUNTIME_FILENAME: &str = "active_runtime.json";
+/// Environment variable the loader checks first for an active runtime manifest path
+pub const XR_RUNTIME_JSON: &str = "XR_RUNTIME_JSON";
 /// Directory used in constructing paths
 pub const OPENXR: &str = "openxr";
+/// Directory holding API layer manifests, under the OpenXR version path
+pub const API_LAYERS: &str = "api_layers";
 
+pub mod api_layer;
 mod app_state;
-#[cfg(windows)]
+pub mod arch_abi;
+#[cfg(feature = "arch-detect")]
 pub(crate) mod arch_detect;
 pub(crate) mod manifest;
 pub(crate) mod path_simplifier;
+pub(crate) mod path_util;
 pub mod platform;
 pub(crate) mod runtime;
+#[cfg(test)]
+mod test_util;
+pub(crate) mod verify;
+pub(crate) mod watch;
+
+pub use api_layer::{ApiLayerKind, ApiLayerManifest};
+pub use app_state::{AppState, PersistentAppState, ThemePreference};
+pub use arch_abi::{ManifestArchDecoration, RuntimeArchAbi};
+#[cfg(feature = "arch-detect")]
+pub use arch_detect::{get_runtime_arch, get_runtime_bitness, RuntimeArch, RuntimeBitness};
+
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, io, path::PathBuf};
 
-pub use app_state::{AppState, PersistentAppState};
+pub use manifest::{LibraryPathKind, RuntimeManifest};
+pub use verify::VerifyResult;
+pub use watch::{ActiveRuntimeWatcher, WatchCallback};
 
-use std::{fmt::Display, io, path::PathBuf};
+/// The specific cause of an `Error::EnumerationError`
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum EnumerationErrorKind {
+    #[error("Tried to add a runtime with no manifest paths")]
+    NoManifestPaths,
+
+    #[error("Could not read directory {0}")]
+    DirectoryUnreadable(PathBuf),
 
-pub(crate) use manifest::RuntimeManifest;
+    #[error("Could not read registry key {0}")]
+    RegistryUnreadable(String),
+
+    #[error("Enumeration was cancelled")]
+    Cancelled,
+}
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Failure while attempting to enumerate available runtimes: {0}")]
-    EnumerationError(String),
+    EnumerationError(EnumerationErrorKind),
 
     #[error("IO error")]
     IoError(#[from] io::Error),
@@ -37,9 +73,21 @@ pub enum Error {
     #[error("Manifest file format version mismatch")]
     ManifestVersionMismatch,
 
+    #[error("Manifest does not have the expected structure: {0}")]
+    ManifestSchemaError(String),
+
+    #[error("Manifest appears to be UTF-16 encoded; OpenXR manifests must be UTF-8")]
+    ManifestUtf16Encoded,
+
+    #[error("The active runtime manifest could not be used: {0}")]
+    ActiveRuntimeManifestUnusable(Box<Error>),
+
     #[error("Error when trying to set active runtime: {0}")]
     SetActiveError(String),
 
+    #[error("You need to run as administrator to change the system runtime")]
+    ElevationRequired,
+
     #[error("Error when trying to load the runtime binary {0} to guess its architecture")]
     RuntimeBinaryLoadError(String),
 }
@@ -47,13 +95,32 @@ pub enum Error {
 #[derive(Debug)]
 pub struct ManifestError(pub PathBuf, pub Error);
 
-#[derive(Debug, Clone, Copy)]
+impl ManifestError {
+    /// Is this an error loading the manifest of the active runtime?
+    pub fn is_for_active_runtime(&self) -> bool {
+        matches!(self.1, Error::ActiveRuntimeManifestUnusable(_))
+    }
+}
+
+/// How a runtime is active, if at all.
+///
+/// The serialized form is a stable identifier for machine-readable output,
+/// unlike the `Display` form which is meant for the UI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ActiveState {
     NotActive,
     ActiveIndependentRuntime,
+    #[serde(rename = "active_64_only")]
     Active64,
+    #[serde(rename = "active_32_only")]
     Active32,
+    #[serde(rename = "active_64_and_32")]
     Active64and32,
+    /// Active because `XR_RUNTIME_JSON` names it, regardless of the configured active runtime.
+    ActiveFromEnvironment,
+    /// Configured as the active runtime, but the loader will use the one named by `XR_RUNTIME_JSON` instead.
+    OverriddenByEnvironment,
 }
 
 impl Display for ActiveState {
@@ -64,6 +131,12 @@ impl Display for ActiveState {
             ActiveState::Active64 => write!(f, "Active - 64-bit only"),
             ActiveState::Active32 => write!(f, "Active - 32-bit only"),
             ActiveState::Active64and32 => write!(f, "Active"),
+            ActiveState::ActiveFromEnvironment => {
+                write!(f, "Active - forced by {}", XR_RUNTIME_JSON)
+            }
+            ActiveState::OverriddenByEnvironment => {
+                write!(f, "Configured, but overridden by {}", XR_RUNTIME_JSON)
+            }
         }
     }
 }
@@ -80,6 +153,14 @@ impl ActiveState {
         }
     }
 
+    /// Is this runtime active at all, for any architecture?
+    pub fn is_active(&self) -> bool {
+        !matches!(
+            self,
+            ActiveState::NotActive | ActiveState::OverriddenByEnvironment
+        )
+    }
+
     /// Is this state at least somewhat inactive, such that we should offer to make it active?
     pub fn should_provide_make_active_button(&self) -> bool {
         match self {
@@ -88,6 +169,9 @@ impl ActiveState {
             ActiveState::Active64 => true,
             ActiveState::Active32 => true,
             ActiveState::Active64and32 => false,
+            // Making it active would persist the choice beyond the environment variable
+            ActiveState::ActiveFromEnvironment => true,
+            ActiveState::OverriddenByEnvironment => false,
         }
     }
 }
@@ -97,6 +181,8 @@ mod linux;
 #[cfg(unix)]
 pub use linux::make_platform;
 
+#[cfg(windows)]
+mod registry;
 #[cfg(windows)]
 mod windows;
 #[cfg(windows)]
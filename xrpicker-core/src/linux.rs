@@ -1,98 +1,642 @@
 // Copyright 2022-2023, Collabora, Ltd.
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use xdg::{BaseDirectories, BaseDirectoriesError};
+#[cfg(feature = "arch-detect")]
+use crate::arch_detect::{get_library_arch, RuntimeArch};
+use itertools::Itertools;
+use notify::{RecursiveMode, Watcher};
 
 use crate::{
-    manifest::{GenericManifest, FILE_INDIRECTION_ARROW},
+    arch_abi::active_runtime_filenames_for_host,
+    manifest::{GenericManifest, LibraryPathKind, FILE_INDIRECTION_ARROW},
     path_simplifier::PathSimplifier,
-    platform::{Platform, PlatformRuntime},
+    path_util::normalize_path,
+    platform::{
+        check_progress, ActiveRuntimeDiagnosis, ActiveRuntimeOverride, ConfigLocation,
+        ConfigSource, MakeActiveOptions, MakeActivePlan, Platform, PlatformCapabilities,
+        PlatformRuntime, SearchedDirectory,
+    },
     runtime::BaseRuntime,
-    ActiveState, Error, ManifestError, ACTIVE_RUNTIME_FILENAME, OPENXR, OPENXR_MAJOR_VERSION,
+    verify::{negotiate_check_base_runtime, verify_base_runtime},
+    ActiveRuntimeWatcher, ActiveState, ApiLayerKind, ApiLayerManifest, EnumerationErrorKind, Error,
+    ManifestArchDecoration, ManifestError, RuntimeArchAbi, VerifyResult, WatchCallback,
+    ACTIVE_RUNTIME_FILENAME, API_LAYERS, OPENXR, OPENXR_MAJOR_VERSION, XR_RUNTIME_JSON,
 };
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    env,
+    ffi::OsString,
     fs,
+    hash::{Hash, Hasher},
+    io,
     iter::once,
-    os::unix::{self, prelude::OsStrExt},
+    ops::ControlFlow,
+    os::unix::{self, fs::PermissionsExt},
     path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 const ETC: &str = "/etc";
+/// Linux gives up resolving a path after following this many symlinks.
+const MAX_SYMLINK_HOPS: usize = 40;
+/// Overrides the fallback system config directory, to match a loader built with a different
+/// sysconfdir, or to look at a staging root. Also read at build time, to set the default.
+const SYSCONFDIR_ENV: &str = "XRPICKER_SYSCONFDIR";
+/// Only exists inside a Flatpak sandbox.
+const FLATPAK_INFO: &str = "/.flatpak-info";
+/// Where the host's `/etc` is inside a Flatpak sandbox, if access was granted.
+const FLATPAK_HOST_ETC: &str = "/run/host/etc";
+const OLD_ACTIVE_RUNTIME_PREFIX: &str = "old_active_runtime";
 
 fn make_path_suffix() -> PathBuf {
     Path::new(OPENXR).join(OPENXR_MAJOR_VERSION.to_string())
 }
 
-fn make_sysconfdir(suffix: &Path) -> PathBuf {
-    Path::new(ETC).join(suffix)
+/// A sandbox we may be running in, which hides the host's configuration unless granted access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sandbox {
+    Flatpak,
+    Snap,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl Sandbox {
+    fn detect(var: &dyn Fn(&str) -> Option<OsString>) -> Option<Self> {
+        if Path::new(FLATPAK_INFO).exists() {
+            Some(Sandbox::Flatpak)
+        } else if var("SNAP").is_some() {
+            Some(Sandbox::Snap)
+        } else {
+            None
+        }
+    }
+
+    /// The user's real home directory, rather than the one the sandbox gives us.
+    fn host_home(self, var: &dyn Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+        let name = match self {
+            // Flatpak only redirects the XDG variables
+            Sandbox::Flatpak => "HOME",
+            Sandbox::Snap => "SNAP_REAL_HOME",
+        };
+        var(name).filter(|v| !v.is_empty()).map(PathBuf::from)
+    }
+
+    /// Explain that `config_home` is hidden, and how to grant access.
+    fn describe_hidden(self, config_home: &Path) -> String {
+        match self {
+            Sandbox::Flatpak => format!(
+                "Running in a Flatpak sandbox, which hides the OpenXR settings in {}. \
+                To allow access, run: flatpak override --user \
+                --filesystem=xdg-config/openxr:create --filesystem=host-etc {}",
+                config_home.display(),
+                env::var("FLATPAK_ID").unwrap_or_else(|_| "<app id>".to_owned())
+            ),
+            Sandbox::Snap => format!(
+                "Running as a Snap, which hides the OpenXR settings in {}. \
+                To allow access, connect an interface that grants it, such as personal-files.",
+                config_home.display()
+            ),
+        }
+    }
+}
+
+/// The directories that configuration is found in.
+///
+/// Normally these come from the XDG environment variables, but they can be given explicitly,
+/// e.g. to look at a fake filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ConfigRoots {
+    /// The user's config directory (`XDG_CONFIG_HOME`), if it could be determined
+    config_home: Option<PathBuf>,
+    /// The system config directories (`XDG_CONFIG_DIRS`), in decreasing order of importance
+    config_dirs: Vec<PathBuf>,
+    /// The fallback system config directory, normally `/etc`
+    sysconfdir: PathBuf,
+    /// The data directories (`XDG_DATA_HOME` and `XDG_DATA_DIRS`), only searched for API layers
+    data_dirs: Vec<PathBuf>,
+    /// The sandbox we are running in, if any
+    sandbox: Option<Sandbox>,
+}
+
+/// The fallback system config directory chosen by the user or packager, if any: from
+/// `XRPICKER_SYSCONFDIR` at runtime if set and not empty, otherwise as configured at build time.
+fn sysconfdir_override(xrpicker_sysconfdir: Option<OsString>) -> Option<PathBuf> {
+    xrpicker_sysconfdir
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| option_env!("XRPICKER_SYSCONFDIR").map(PathBuf::from))
+}
+
+/// An XDG base directory: from the variable `name` if it is an absolute path, otherwise
+/// `default` under the home directory.
+fn xdg_home_dir(
+    var: &dyn Fn(&str) -> Option<OsString>,
+    name: &str,
+    home: Option<&Path>,
+    default: &str,
+) -> Option<PathBuf> {
+    var(name)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| home.map(|home| home.join(default)))
+}
+
+/// A list of XDG base directories: the absolute paths in the variable `name`, or `defaults` if
+/// there are none.
+fn xdg_dirs(var: &dyn Fn(&str) -> Option<OsString>, name: &str, defaults: &[&str]) -> Vec<PathBuf> {
+    let dirs: Vec<PathBuf> = var(name)
+        .map(|v| env::split_paths(&v).filter(|p| p.is_absolute()).collect())
+        .unwrap_or_default();
+    if dirs.is_empty() {
+        defaults.iter().map(PathBuf::from).collect()
+    } else {
+        dirs
+    }
+}
+
+impl ConfigRoots {
+    fn from_environment() -> Self {
+        Self::from_variables(&|name| env::var_os(name))
+    }
+
+    /// Find the directories the way the loader does, given a way to look up environment
+    /// variables.
+    fn from_variables(var: &dyn Fn(&str) -> Option<OsString>) -> Self {
+        let home = var("HOME").filter(|v| !v.is_empty()).map(PathBuf::from);
+        if home.is_none() {
+            log::warn!("Could not find XDG directories: HOME is not set");
+        }
+        let home = home.as_deref();
+        let sysconfdir_override = sysconfdir_override(var(SYSCONFDIR_ENV));
+        let mut roots = ConfigRoots {
+            config_home: xdg_home_dir(var, "XDG_CONFIG_HOME", home, ".config"),
+            config_dirs: xdg_dirs(var, "XDG_CONFIG_DIRS", &["/etc/xdg"]),
+            sysconfdir: sysconfdir_override
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(ETC)),
+            data_dirs: xdg_home_dir(var, "XDG_DATA_HOME", home, ".local/share")
+                .into_iter()
+                .chain(xdg_dirs(
+                    var,
+                    "XDG_DATA_DIRS",
+                    &["/usr/local/share", "/usr/share"],
+                ))
+                .collect(),
+            sandbox: Sandbox::detect(var),
+        };
+        if let Some(sandbox) = roots.sandbox {
+            roots.use_host_dirs(sandbox, var, sysconfdir_override.is_some());
+        }
+        roots
+    }
+
+    /// In a sandbox, the XDG variables point at the sandbox's own directories, but the loader
+    /// in apps outside it uses the host's: use those instead, where we can see them.
+    ///
+    /// An explicit sysconfdir override is kept.
+    fn use_host_dirs(
+        &mut self,
+        sandbox: Sandbox,
+        var: &dyn Fn(&str) -> Option<OsString>,
+        sysconfdir_overridden: bool,
+    ) {
+        log::info!(
+            "Running in a {:?} sandbox, using the host's config directories",
+            sandbox
+        );
+        if let Some(home) = sandbox.host_home(var) {
+            self.config_home = Some(home.join(".config"));
+        }
+        let host_etc = Path::new(FLATPAK_HOST_ETC);
+        if sandbox == Sandbox::Flatpak && host_etc.is_dir() {
+            self.config_dirs = vec![host_etc.join("xdg")];
+            if !sysconfdir_overridden {
+                self.sysconfdir = host_etc.to_owned();
+            }
+        }
+    }
+
+    /// The XDG config directories in decreasing order of importance, along with whether they are the user's.
+    fn xdg_config_dirs_with_source(&self) -> Vec<(PathBuf, ConfigSource)> {
+        self.config_home
+            .iter()
+            .map(|dir| (dir.clone(), ConfigSource::User))
+            .chain(
+                self.config_dirs
+                    .iter()
+                    .map(|dir| (dir.clone(), ConfigSource::System)),
+            )
+            .collect()
+    }
+
+    /// All the config directories the loader searches, in decreasing order of importance,
+    /// without any suffix: the XDG ones, then the fallback.
+    fn search_dirs_with_source(&self) -> Vec<(PathBuf, ConfigSource)> {
+        self.xdg_config_dirs_with_source()
+            .into_iter()
+            .chain(once((self.sysconfdir.clone(), ConfigSource::Sysconfdir)))
+            .collect()
+    }
+}
+
+/// Parse the timestamp out of the name of a backup made by `make_active`
+fn parse_backup_timestamp(path: &Path) -> Option<u64> {
+    let rest = path
+        .file_name()?
+        .to_str()?
+        .strip_prefix(OLD_ACTIVE_RUNTIME_PREFIX)?
+        .strip_suffix(".json")?;
+    // Backups of decorated files have the decoration after the timestamp
+    let timestamp = rest
+        .split_once('.')
+        .map_or(rest, |(timestamp, _)| timestamp);
+    timestamp.parse().ok()
+}
+
+/// The name of the backup of an active runtime file with the given decoration made at `timestamp`.
+fn backup_file_name(timestamp: u64, decoration: ManifestArchDecoration) -> String {
+    format!(
+        "{}{}{}",
+        OLD_ACTIVE_RUNTIME_PREFIX,
+        timestamp,
+        decoration.filename_suffix()
+    )
+}
+
+/// The backups made by `make_active` in `dir`, oldest first.
+fn find_backups(dir: &Path) -> Result<Vec<(u64, PathBuf)>, Error> {
+    let mut backups: Vec<(u64, PathBuf)> = dir
+        .read_dir()?
+        .filter_map(|r| r.ok())
+        .map(|entry| entry.path())
+        .filter_map(|p| parse_backup_timestamp(&p).map(|timestamp| (timestamp, p)))
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
+
+/// Delete all but the newest `keep` backups in `dir`, returning how many were removed.
+fn prune_backups(dir: &Path, keep: usize) -> Result<usize, Error> {
+    let backups = find_backups(dir)?;
+    if backups.len() <= keep {
+        return Ok(0);
+    }
+    let excess = backups.len() - keep;
+    for (_, path) in &backups[..excess] {
+        fs::remove_file(path)?;
+    }
+    Ok(excess)
+}
+
+#[derive(Debug)]
 pub struct LinuxRuntime {
     base: BaseRuntime,
     orig_path: PathBuf,
+    /// Where making this runtime active writes to
+    roots: Arc<ConfigRoots>,
+    /// Lazily computed because it requires reading the library
+    arch_description: OnceLock<String>,
+    /// Lazily computed because it requires reading the library
+    host_compatible: OnceLock<bool>,
+}
+
+// Not derived: two manifests (e.g. one in /etc and one in ~/.config) pointing at the
+// same library are the same runtime as far as the loader is concerned.
+impl PartialEq for LinuxRuntime {
+    fn eq(&self, other: &Self) -> bool {
+        self.library_key() == other.library_key()
+    }
+}
+
+impl Eq for LinuxRuntime {}
+
+impl Hash for LinuxRuntime {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.library_key().hash(state);
+    }
 }
 
 impl LinuxRuntime {
-    fn new(orig_path: &Path, canonical_path: &Path) -> Result<Self, Error> {
+    fn new(
+        orig_path: &Path,
+        canonical_path: &Path,
+        roots: &Arc<ConfigRoots>,
+    ) -> Result<Self, Error> {
         let base = BaseRuntime::new(canonical_path)?;
         Ok(LinuxRuntime {
             base,
             orig_path: orig_path.to_owned(),
+            roots: Arc::clone(roots),
+            arch_description: OnceLock::new(),
+            host_compatible: OnceLock::new(),
         })
     }
 }
 
-impl PlatformRuntime for LinuxRuntime {
-    fn make_active(&self) -> Result<(), Error> {
-        fn convert_err(e: BaseDirectoriesError) -> Error {
-            Error::SetActiveError(e.to_string())
+/// The canonical library path named by a manifest, if it can be loaded.
+fn library_key_of_manifest(manifest_path: &Path) -> Option<PathBuf> {
+    BaseRuntime::new(manifest_path)
+        .ok()
+        .map(|base| base.resolve_library_path())
+}
+
+impl LinuxRuntime {
+    /// The active runtime file name decoration that making this runtime active would use.
+    fn decoration_for(&self, options: &MakeActiveOptions) -> ManifestArchDecoration {
+        if options.arch_decorated {
+            decoration_for_library(&self.base)
+        } else {
+            ManifestArchDecoration::Unspecified
         }
-        let dirs = BaseDirectories::new().map_err(convert_err)?;
-        let suffix = make_path_suffix();
-        let path = dirs.place_config_file(suffix.join(ACTIVE_RUNTIME_FILENAME))?;
+    }
 
-        // First move the old file out of the way, if any.
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        let move_target =
-            dirs.place_config_file(suffix.join(format!("old_active_runtime{}.json", timestamp)))?;
+    /// The canonical path of our library, which identifies the runtime.
+    ///
+    /// For a library found using the search path, this is just its name.
+    fn library_key(&self) -> PathBuf {
+        self.base.resolve_library_path()
+    }
 
-        match fs::rename(&path, &move_target) {
-            Ok(_) => {
-                // Only keep our renamed file if it wasn't a symlink
-                if let Ok(m) = move_target.symlink_metadata() {
-                    if m.is_symlink() && fs::remove_file(&move_target).is_err() {
-                        // that's ok
-                        eprintln!(
-                            "Got an error trying to remove an apparently-symlink {}",
-                            move_target.display()
-                        )
-                    }
+    /// Copy our manifest to `dest`, making a relative library path absolute so it still resolves.
+    fn copy_manifest_to(&self, dest: &Path) -> Result<(), Error> {
+        self.base.copy_manifest_to(dest, |e| write_error(dest, e))
+    }
+
+    /// Warn about files that apps would load code from, which someone else might be able to change.
+    ///
+    /// Purely informational: we don't refuse to use such runtimes.
+    pub fn get_permission_warnings(&self) -> Vec<String> {
+        let library = if self.base.uses_search_path() {
+            None
+        } else {
+            Some(self.base.resolve_library_path())
+        };
+        once(self.base.get_manifest_path().to_owned())
+            .chain(library)
+            .filter_map(|p| describe_insecure_permissions(&p))
+            .collect()
+    }
+}
+
+/// Describe whether a file is writable by users other than its owner.
+fn describe_insecure_permissions(path: &Path) -> Option<String> {
+    let mode = path.metadata().ok()?.permissions().mode();
+    let problem = if mode & 0o002 != 0 {
+        "world-writable"
+    } else if mode & 0o020 != 0 {
+        "group-writable"
+    } else {
+        return None;
+    };
+    Some(format!(
+        "⚠ {} is {}",
+        PathSimplifier::new().simplify(path).display(),
+        problem
+    ))
+}
+
+/// Explain a failure to write to the user's config directory, if it is something they can fix.
+fn write_error(path: &Path, e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::PermissionDenied {
+        Error::SetActiveError(format!(
+            "Permission denied writing {}: make sure that directory is owned by and writable by you",
+            path.display()
+        ))
+    } else {
+        e.into()
+    }
+}
+
+fn now_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The directory in the user's config directory that making a runtime active writes to.
+fn user_config_dir(roots: &ConfigRoots) -> Result<PathBuf, Error> {
+    roots
+        .config_home
+        .as_ref()
+        .map(|d| d.join(make_path_suffix()))
+        .ok_or_else(|| {
+            Error::SetActiveError("Could not find the user's config directory".to_owned())
+        })
+}
+
+/// The file that making a runtime active with the given decoration writes to.
+fn active_runtime_write_path(
+    roots: &ConfigRoots,
+    decoration: ManifestArchDecoration,
+) -> Result<PathBuf, Error> {
+    Ok(user_config_dir(roots)?.join(decoration.active_runtime_filename()))
+}
+
+/// Where making a runtime active writes to, and where any existing file there is moved aside to.
+///
+/// Used both to make the change and to preview it, so they can't disagree.
+struct ActiveRuntimeLocations {
+    /// The directory holding the active runtime file
+    dir: PathBuf,
+    /// The active runtime file to write
+    path: PathBuf,
+    /// Where an existing active runtime file is moved to
+    aside: PathBuf,
+}
+
+impl ActiveRuntimeLocations {
+    fn new(roots: &ConfigRoots, decoration: ManifestArchDecoration) -> Result<Self, Error> {
+        let dir = user_config_dir(roots)?;
+        Ok(Self {
+            path: active_runtime_write_path(roots, decoration)?,
+            aside: dir.join(backup_file_name(now_timestamp(), decoration)),
+            dir,
+        })
+    }
+}
+
+/// Move the user's active runtime file out of the way, if any, creating its directory if needed.
+///
+/// Real files are kept as timestamped backups, while symlinks are just removed:
+/// they hold nothing that can't be re-created by choosing that runtime again.
+fn move_active_runtime_aside(locations: &ActiveRuntimeLocations) -> Result<(), Error> {
+    let ActiveRuntimeLocations {
+        dir,
+        path,
+        aside: move_target,
+    } = locations;
+    fs::create_dir_all(dir).map_err(|e| write_error(dir, e))?;
+
+    match fs::rename(path, move_target) {
+        Ok(_) => {
+            // Only keep our renamed file if it wasn't a symlink
+            if let Ok(m) = move_target.symlink_metadata() {
+                if m.is_symlink() && fs::remove_file(move_target).is_err() {
+                    // that's ok
+                    log::warn!(
+                        "Got an error trying to remove an apparently-symlink {}",
+                        move_target.display()
+                    )
                 }
             }
-            Err(e) => {
-                // ignore and hope it meant there was just nothing to move
-                eprintln!(
-                    "Got an error trying to rename {} to {}: {}",
-                    path.display(),
-                    move_target.display(),
-                    e
-                );
+        }
+        Err(e) => {
+            // ignore and hope it meant there was just nothing to move
+            log::debug!(
+                "Got an error trying to rename {} to {}: {}",
+                path.display(),
+                move_target.display(),
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The active runtime file name decoration matching a runtime's library, if it is for a single architecture.
+#[cfg(feature = "arch-detect")]
+fn decoration_for_library(base: &BaseRuntime) -> ManifestArchDecoration {
+    match get_library_arch(base) {
+        Ok(RuntimeArch::Specific(abi)) => ManifestArchDecoration::Specified(abi),
+        _ => ManifestArchDecoration::Unspecified,
+    }
+}
+
+/// Without inspecting the library we can't tell, so it can't be decorated.
+#[cfg(not(feature = "arch-detect"))]
+fn decoration_for_library(_base: &BaseRuntime) -> ManifestArchDecoration {
+    ManifestArchDecoration::Unspecified
+}
+
+/// Whether apps on this computer can load a runtime's library, assuming so if we can't tell.
+#[cfg(feature = "arch-detect")]
+fn library_compatible_with_host(base: &BaseRuntime) -> bool {
+    match get_library_arch(base) {
+        Ok(RuntimeArch::Specific(abi)) => abi.is_compatible_with_host(),
+        _ => true,
+    }
+}
+
+/// Without inspecting the library we can't tell, so assume it is compatible.
+#[cfg(not(feature = "arch-detect"))]
+fn library_compatible_with_host(_base: &BaseRuntime) -> bool {
+    true
+}
+
+impl PlatformRuntime for LinuxRuntime {
+    fn make_active_with_options(&self, options: &MakeActiveOptions) -> Result<(), Error> {
+        let locations = ActiveRuntimeLocations::new(&self.roots, self.decoration_for(options))?;
+        move_active_runtime_aside(&locations)?;
+        if options.max_backups > 0 {
+            if let Err(e) = prune_backups(&locations.dir, options.max_backups) {
+                // not worth failing over
+                log::warn!("Got an error trying to remove old backups: {}", e);
             }
         }
-        unix::fs::symlink(self.base.get_manifest_path(), &path)?;
+        let path = locations.path;
+        if options.use_symlink {
+            unix::fs::symlink(self.base.get_manifest_path(), &path)
+                .map_err(|e| write_error(&path, e))?;
+        } else {
+            self.copy_manifest_to(&path)?;
+        }
         Ok(())
     }
 
+    fn preview_make_active_with_options(
+        &self,
+        options: &MakeActiveOptions,
+    ) -> Result<MakeActivePlan, Error> {
+        let ActiveRuntimeLocations { dir, path, aside } =
+            ActiveRuntimeLocations::new(&self.roots, self.decoration_for(options))?;
+        let mut steps = vec![];
+        if !dir.is_dir() {
+            steps.push(format!("Create directory {}", dir.display()));
+        }
+        let mut backed_up = false;
+        match path.symlink_metadata() {
+            Ok(m) if m.is_symlink() => {
+                steps.push(format!("Remove existing link {}", path.display()));
+            }
+            Ok(_) => {
+                backed_up = true;
+                steps.push(format!(
+                    "Rename existing {} to {}",
+                    path.display(),
+                    aside.display()
+                ));
+            }
+            Err(_) => {}
+        }
+        if options.max_backups > 0 && dir.is_dir() {
+            let count = find_backups(&dir)?.len() + usize::from(backed_up);
+            if count > options.max_backups {
+                steps.push(format!(
+                    "Delete the oldest {} backup(s) in {}",
+                    count - options.max_backups,
+                    dir.display()
+                ));
+            }
+        }
+        let manifest = self.base.get_manifest_path();
+        if options.use_symlink {
+            steps.push(format!("Link {} -> {}", path.display(), manifest.display()));
+        } else if self.base.library_relative_to_manifest() {
+            steps.push(format!(
+                "Copy {} to {}, making its library path absolute",
+                manifest.display(),
+                path.display()
+            ));
+        } else {
+            steps.push(format!("Copy {} to {}", manifest.display(), path.display()));
+        }
+        Ok(MakeActivePlan { steps })
+    }
+
+    fn get_active_runtime_write_path(&self, options: &MakeActiveOptions) -> Option<PathBuf> {
+        active_runtime_write_path(&self.roots, self.decoration_for(options)).ok()
+    }
+
+    fn export_active_runtime_to(&self, dir: &Path) -> Result<PathBuf, Error> {
+        fs::create_dir_all(dir).map_err(|e| write_error(dir, e))?;
+        let path = dir.join(ACTIVE_RUNTIME_FILENAME);
+        if path.symlink_metadata().is_ok() {
+            fs::remove_file(&path).map_err(|e| write_error(&path, e))?;
+        }
+        unix::fs::symlink(self.base.get_manifest_path(), &path)
+            .map_err(|e| write_error(&path, e))?;
+        Ok(path)
+    }
+
     fn get_runtime_name(&self) -> String {
         self.base.get_runtime_name()
     }
 
+    fn get_arch_description(&self) -> String {
+        self.arch_description
+            .get_or_init(|| self.base.describe_arch())
+            .clone()
+    }
+
+    fn is_arch_description_resolved(&self) -> bool {
+        self.arch_description.get().is_some() && self.host_compatible.get().is_some()
+    }
+
+    fn get_runtime_version(&self) -> Option<String> {
+        self.base.get_declared_version()
+    }
+
+    fn get_negotiate_function_name(&self) -> Option<String> {
+        self.base.get_negotiate_function_name().map(str::to_owned)
+    }
+
+    fn is_compatible_with_host(&self) -> bool {
+        *self
+            .host_compatible
+            .get_or_init(|| library_compatible_with_host(&self.base))
+    }
+
     fn get_manifests(&self) -> Vec<&Path> {
         vec![self.base.get_manifest_path()]
     }
@@ -102,9 +646,25 @@ impl PlatformRuntime for LinuxRuntime {
         vec![path]
     }
 
+    fn uniqueness_key(&self) -> Vec<PathBuf> {
+        vec![self.library_key()]
+    }
+
+    fn library_path_kind(&self) -> LibraryPathKind {
+        self.base.classify_library_path()
+    }
+
+    fn verify(&self) -> VerifyResult {
+        verify_base_runtime(&self.base)
+    }
+
+    fn open_negotiate_check(&self) -> VerifyResult {
+        negotiate_check_base_runtime(&self.base)
+    }
+
     fn describe(&self) -> String {
-        let description = self.base.describe_manifest(self.base.get_manifest_path());
-        if self.orig_path != self.base.get_manifest_path() {
+        let description = self.base.describe();
+        let description = if self.orig_path != self.base.get_manifest_path() {
             format!(
                 "{}{}{}",
                 PathSimplifier::new().simplify(&self.orig_path).display(),
@@ -113,39 +673,92 @@ impl PlatformRuntime for LinuxRuntime {
             )
         } else {
             description
-        }
+        };
+        once(description)
+            .chain(self.get_permission_warnings())
+            .join("\n")
     }
 }
 
 pub struct LinuxPlatform {
     path_suffix: PathBuf,
+    roots: Arc<ConfigRoots>,
+    /// Directories the user added to search for manifests, after the standard ones
+    extra_search_dirs: Mutex<Vec<PathBuf>>,
 }
 
 impl LinuxPlatform {
     fn new() -> Self {
-        let path_suffix = make_path_suffix();
-        Self { path_suffix }
+        Self::with_config_roots(ConfigRoots::from_environment())
     }
-}
 
-fn is_active_runtime_name(p: &Path) -> bool {
-    p.file_name().map(|s| s.as_bytes()) == Some(ACTIVE_RUNTIME_FILENAME.as_bytes())
+    fn with_config_roots(roots: ConfigRoots) -> Self {
+        Self {
+            path_suffix: make_path_suffix(),
+            roots: Arc::new(roots),
+            extra_search_dirs: Mutex::default(),
+        }
+    }
+
+    /// Create a platform that uses the given directories instead of the XDG environment variables:
+    /// the user's config directory, the system config directories in decreasing order of
+    /// importance, and the fallback system config directory (normally `/etc`).
+    ///
+    /// Useful for looking at another filesystem, or a fake one. Data directories are not searched
+    /// for API layers.
+    pub fn with_roots(config_home: PathBuf, config_dirs: Vec<PathBuf>, etc_dir: PathBuf) -> Self {
+        Self::with_config_roots(ConfigRoots {
+            config_home: Some(config_home),
+            config_dirs,
+            sysconfdir: etc_dir,
+            data_dirs: vec![],
+            sandbox: None,
+        })
+    }
+
+    fn get_extra_search_dirs(&self) -> Vec<PathBuf> {
+        self.extra_search_dirs
+            .lock()
+            .map(|dirs| dirs.clone())
+            .unwrap_or_default()
+    }
+
+    /// Delete all but the newest `keep` backups of previous active runtimes from the user's
+    /// config directory, returning how many were removed.
+    pub fn prune_old_active_runtimes(&self, keep: usize) -> Result<usize, Error> {
+        let Some(dir) = self.get_config_directory() else {
+            return Ok(0);
+        };
+        if !dir.is_dir() {
+            return Ok(0);
+        }
+        prune_backups(&dir, keep)
+    }
 }
 
-fn find_potential_manifests_xdg(suffix: &Path) -> impl Iterator<Item = PathBuf> {
-    let suffix = suffix.to_owned();
-    BaseDirectories::new()
-        .ok()
-        .into_iter()
-        .flat_map(move |xdg_dirs| xdg_dirs.list_config_files(&suffix))
-        .filter(|p| !is_active_runtime_name(p))
+/// Is this an active runtime file name, possibly decorated with an architecture?
+fn is_active_runtime_name(p: &Path) -> bool {
+    let Some(name) = p.file_name().and_then(|s| s.to_str()) else {
+        return false;
+    };
+    RuntimeArchAbi::from_filename(name).active_runtime_filename() == name
 }
 
-fn find_potential_manifests_sysconfdir(suffix: &Path) -> impl Iterator<Item = PathBuf> {
-    make_sysconfdir(suffix)
-        .read_dir()
-        .into_iter()
-        .flatten()
+/// The files in a directory that might be runtime manifests.
+///
+/// A directory that does not exist just has none, but one that can't be read is an error.
+fn potential_manifests_in(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => {
+            log::warn!("Could not read directory {}: {}", dir.display(), e);
+            return Err(Error::EnumerationError(
+                EnumerationErrorKind::DirectoryUnreadable(dir.to_owned()),
+            ));
+        }
+    };
+    Ok(entries
         .filter_map(|r| r.ok())
         .filter(|entry| {
             // keep only files and symlinks
@@ -156,101 +769,611 @@ fn find_potential_manifests_sysconfdir(suffix: &Path) -> impl Iterator<Item = Pa
         })
         .map(|entry| entry.path())
         .filter(|p| !is_active_runtime_name(p))
+        .collect())
+}
+
+/// The manifests in a directory the user added: unlike standard ones, it may hold other files.
+fn find_potential_manifests_extra(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    Ok(potential_manifests_in(dir)?
+        .into_iter()
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .collect())
+}
+
+/// The directories that may hold API layer manifests of the given kind, in decreasing order of importance.
+fn api_layer_dirs(roots: &ConfigRoots, kind: ApiLayerKind) -> Vec<PathBuf> {
+    let suffix = make_path_suffix()
+        .join(API_LAYERS)
+        .join(kind.directory_name());
+    roots
+        .search_dirs_with_source()
+        .into_iter()
+        .map(|(d, _)| d)
+        .chain(roots.data_dirs.iter().cloned())
+        .map(|d| d.join(&suffix))
+        .collect()
+}
+
+fn find_api_layer_manifests(dir: &Path) -> impl Iterator<Item = PathBuf> {
+    dir.read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(|r| r.ok())
+        .map(|entry| entry.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "json"))
 }
 
-pub struct LinuxActiveRuntimeData(Option<PathBuf>);
+pub struct LinuxActiveRuntimeData {
+    /// The canonical path of the configured active runtime manifest
+    configured: Option<PathBuf>,
+    /// The `active_runtime.json` that configures it, and the kind of directory it is in
+    configured_by: Option<(PathBuf, ConfigSource)>,
+    /// The canonical path of the manifest named by `XR_RUNTIME_JSON`, if set to an existing file
+    env_override: Option<PathBuf>,
+    /// The library of the configured active runtime, since we may know it by another manifest
+    configured_library: Option<PathBuf>,
+    /// The library of the runtime named by `XR_RUNTIME_JSON`
+    env_override_library: Option<PathBuf>,
+    /// The active runtime files when this was read, to notice changes cheaply
+    links_snapshot: Vec<(PathBuf, Option<SystemTime>, Option<PathBuf>)>,
+}
 
 impl LinuxActiveRuntimeData {
-    fn new() -> Self {
-        LinuxActiveRuntimeData(possible_active_runtimes().next())
+    fn new(roots: &ConfigRoots) -> Self {
+        Self::read(roots, env::var_os(XR_RUNTIME_JSON))
+    }
+
+    /// Read the active runtime configuration, given the value of `XR_RUNTIME_JSON`.
+    fn read(roots: &ConfigRoots, xr_runtime_json: Option<OsString>) -> Self {
+        let configured = possible_active_runtimes(roots).next();
+        let env_override = xr_runtime_json
+            .filter(|v| !v.is_empty())
+            .map(|v| normalize_path(Path::new(&v)))
+            .filter(|p| p.is_file());
+        LinuxActiveRuntimeData {
+            configured_by: active_runtime_links_with_source(roots).find(|(p, _)| p.is_file()),
+            links_snapshot: active_runtime_links_snapshot(roots),
+            configured_library: configured.as_deref().and_then(library_key_of_manifest),
+            env_override_library: env_override.as_deref().and_then(library_key_of_manifest),
+            configured,
+            env_override,
+        }
+    }
+
+    fn get_override(&self) -> Option<ActiveRuntimeOverride> {
+        let effective = self.env_override.as_ref()?;
+        let same_library = self.configured_library.is_some()
+            && self.configured_library == self.env_override_library;
+        if self.configured.as_ref() == Some(effective) || same_library {
+            return None;
+        }
+        Some(ActiveRuntimeOverride {
+            effective: effective.clone(),
+            configured: self.configured.iter().cloned().collect(),
+        })
     }
 
     fn check_runtime(&self, runtime: &LinuxRuntime) -> ActiveState {
-        if let Some(active_path) = &self.0 {
-            if active_path == runtime.base.get_manifest_path() {
-                return ActiveState::ActiveIndependentRuntime;
+        let path = runtime.base.get_normalized_manifest_path();
+        let library = runtime.library_key();
+        let is_configured = self.configured.as_deref() == Some(path)
+            || self.configured_library.as_ref() == Some(&library);
+        let is_env_override = self.env_override.as_deref() == Some(path)
+            || self.env_override_library.as_ref() == Some(&library);
+        match &self.env_override {
+            // The loader uses XR_RUNTIME_JSON in preference to any active_runtime.json
+            Some(_) if is_env_override => {
+                if is_configured {
+                    ActiveState::ActiveIndependentRuntime
+                } else {
+                    ActiveState::ActiveFromEnvironment
+                }
             }
+            Some(_) if is_configured => ActiveState::OverriddenByEnvironment,
+            _ if is_configured => ActiveState::ActiveIndependentRuntime,
+            _ => ActiveState::NotActive,
+        }
+    }
+
+    fn describe_reason(&self, runtime: &LinuxRuntime) -> Option<String> {
+        let describe_configured = || {
+            self.configured_by.as_ref().map(|(link, source)| {
+                let kind = if link.is_symlink() { "symlink" } else { "copy" };
+                format!("{} ({}, {})", link.display(), source, kind)
+            })
+        };
+        let environment = ConfigSource::Environment;
+        match self.check_runtime(runtime) {
+            ActiveState::ActiveFromEnvironment => Some(format!("Selected by {}", environment)),
+            ActiveState::OverriddenByEnvironment => Some(format!(
+                "Selected by {}, but {} takes precedence",
+                describe_configured()?,
+                environment
+            )),
+            ActiveState::ActiveIndependentRuntime if self.env_override.is_some() => Some(format!(
+                "Selected by {}, and also by {}",
+                environment,
+                describe_configured()?
+            )),
+            ActiveState::ActiveIndependentRuntime => {
+                Some(format!("Selected by {}", describe_configured()?))
+            }
+            _ => None,
         }
-        ActiveState::NotActive
     }
 }
 
-fn possible_active_runtimes() -> impl Iterator<Item = PathBuf> {
-    let suffix = make_path_suffix().join(ACTIVE_RUNTIME_FILENAME);
-    let etc_iter = once(make_sysconfdir(&suffix));
-    // Warning: BaseDirectories returns increasing order of importance, which is
-    // opposite of what we want, so we reverse it.
-    let xdg_iter = BaseDirectories::new()
-        .ok()
-        .into_iter()
-        .flat_map(move |d| d.find_config_files(&suffix))
-        .rev();
+/// Whether following symlinks from `path` comes back around (or goes on too long to be resolved),
+/// rather than ending at a file or a missing target.
+fn is_symlink_loop(path: &Path) -> bool {
+    let mut visited = HashSet::new();
+    let mut current = path.to_owned();
+    for _ in 0..MAX_SYMLINK_HOPS {
+        let Ok(target) = fs::read_link(&current) else {
+            return false;
+        };
+        let next = match current.parent() {
+            Some(parent) => parent.join(target),
+            None => target,
+        };
+        if !visited.insert(current) {
+            return true;
+        }
+        current = next;
+    }
+    true
+}
 
-    xdg_iter
-        .chain(etc_iter)
-        .filter(|p| {
-            p.metadata()
+/// The canonical paths of configured active runtime manifests, in decreasing order of importance.
+fn possible_active_runtimes(roots: &ConfigRoots) -> impl Iterator<Item = PathBuf> {
+    possible_active_runtime_files(roots).map(|p| normalize_path(&p))
+}
+
+/// The existing `active_runtime.json` files, in decreasing order of importance.
+fn possible_active_runtime_files(roots: &ConfigRoots) -> impl Iterator<Item = PathBuf> {
+    active_runtime_links(roots).filter(|p| p.is_file())
+}
+
+/// The `active_runtime.json` files or symlinks, even broken ones, in decreasing order of importance.
+fn active_runtime_links(roots: &ConfigRoots) -> impl Iterator<Item = PathBuf> {
+    active_runtime_links_with_source(roots).map(|(p, _)| p)
+}
+
+/// The `active_runtime.json` files or symlinks, even broken ones, in decreasing order of importance,
+/// along with the kind of directory they are in.
+fn active_runtime_links_with_source(
+    roots: &ConfigRoots,
+) -> impl Iterator<Item = (PathBuf, ConfigSource)> {
+    let suffix = make_path_suffix();
+    let filenames = active_runtime_filenames_for_host(RuntimeArchAbi::get_current_arch());
+    // Within each directory, the decorated name (if any) takes precedence.
+    roots
+        .search_dirs_with_source()
+        .into_iter()
+        .flat_map(move |(d, source)| {
+            let dir = d.join(&suffix);
+            filenames
+                .iter()
+                .map(|name| (dir.join(name), source))
+                .collect_vec()
+        })
+        .filter(|(p, _)| {
+            p.symlink_metadata()
                 .map(|m| m.is_file() || m.is_symlink())
                 .ok()
                 .unwrap_or_default()
         })
-        .filter_map(|p| p.canonicalize().ok())
 }
 
-impl Platform for LinuxPlatform {
-    type PlatformRuntimeType = LinuxRuntime;
-    type PlatformActiveData = LinuxActiveRuntimeData;
+/// Identifies the current active runtime settings, to notice changes when polling.
+fn active_runtime_links_snapshot(
+    roots: &ConfigRoots,
+) -> Vec<(PathBuf, Option<SystemTime>, Option<PathBuf>)> {
+    active_runtime_links(roots)
+        .map(|p| {
+            let modified = p.symlink_metadata().and_then(|m| m.modified()).ok();
+            let target = fs::read_link(&p).ok();
+            (p, modified, target)
+        })
+        .collect()
+}
 
-    fn find_available_runtimes(
-        &self,
-        extra_paths: Box<dyn '_ + Iterator<Item = PathBuf>>,
-    ) -> Result<(Vec<Self::PlatformRuntimeType>, Vec<ManifestError>), Error> {
-        let mut known_manifests: HashSet<PathBuf> = HashSet::default();
+/// Watch the directories that hold active runtime settings using inotify.
+///
+/// Directories that do not exist yet cannot be watched, so this fails if the user's own
+/// one does not, since that is where a change is most likely.
+fn watch_active_runtime_dirs(
+    roots: &ConfigRoots,
+    user_dir: &Path,
+    mut callback: WatchCallback,
+) -> notify::Result<notify::RecommendedWatcher> {
+    if !user_dir.is_dir() {
+        return Err(notify::Error::path_not_found().add_path(user_dir.to_owned()));
+    }
+    let mut watcher =
+        notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+            Ok(event) if event.paths.iter().any(|p| is_active_runtime_name(p)) => callback(),
+            Ok(_) => {}
+            Err(e) => log::warn!("Error while watching for active runtime changes: {}", e),
+        })?;
+    let suffix = make_path_suffix();
+    for dir in roots
+        .search_dirs_with_source()
+        .into_iter()
+        .map(|(d, _)| d.join(&suffix))
+        .filter(|d| d.is_dir())
+    {
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    }
+    Ok(watcher)
+}
 
-        let manifest_files = find_potential_manifests_xdg(&self.path_suffix)
-            .chain(find_potential_manifests_sysconfdir(&self.path_suffix))
-            .chain(possible_active_runtimes()) // put these almost last so they are only included if they mention a not-previously-found runtime
-            .chain(extra_paths)
-            .filter_map(|p| p.canonicalize().ok().map(|canonical| (p, canonical)));
+/// Loads runtimes one manifest at a time, in the loader's search order, skipping duplicates.
+struct RuntimeScan<'a> {
+    roots: &'a Arc<ConfigRoots>,
+    /// Each potential manifest, along with its normalized path.
+    manifest_files: Box<dyn 'a + Iterator<Item = (PathBuf, PathBuf)>>,
+    active_manifest: Option<PathBuf>,
+    known_manifests: HashSet<PathBuf>,
+    /// The first manifest seen for each runtime library.
+    known_runtimes: HashMap<PathBuf, PathBuf>,
+    /// Directories that could not be read, still to be reported.
+    unreadable_dirs: std::vec::IntoIter<ManifestError>,
+}
 
-        let mut runtimes = vec![];
-        let mut nonfatal_errors = vec![];
+impl<'a> RuntimeScan<'a> {
+    fn new(
+        platform: &'a LinuxPlatform,
+        extra_paths: Box<dyn 'a + Iterator<Item = PathBuf>>,
+    ) -> Self {
+        let roots = &platform.roots;
+        let mut found_manifests = vec![];
+        let mut unreadable_dirs = vec![];
+        let standard_dirs = roots
+            .search_dirs_with_source()
+            .into_iter()
+            .map(|(d, _)| (d.join(&platform.path_suffix), false));
+        let extra_dirs = platform
+            .get_extra_search_dirs()
+            .into_iter()
+            .map(|d| (d, true));
+        for (dir, is_extra) in standard_dirs.chain(extra_dirs) {
+            let found = if is_extra {
+                find_potential_manifests_extra(&dir)
+            } else {
+                potential_manifests_in(&dir)
+            };
+            match found {
+                Ok(found) => found_manifests.extend(found),
+                Err(e) => unreadable_dirs.push(ManifestError(dir, e)),
+            }
+        }
+        let manifest_files = found_manifests
+            .into_iter()
+            .chain(possible_active_runtimes(roots)) // put these almost last so they are only included if they mention a not-previously-found runtime
+            .chain(extra_paths)
+            .filter(|p| p.exists())
+            .map(|p| {
+                let canonical = normalize_path(&p);
+                (p, canonical)
+            });
+        Self {
+            roots,
+            manifest_files: Box::new(manifest_files),
+            active_manifest: possible_active_runtimes(roots).next(),
+            known_manifests: HashSet::default(),
+            known_runtimes: HashMap::default(),
+            unreadable_dirs: unreadable_dirs.into_iter(),
+        }
+    }
 
-        for (orig_path, canonical) in manifest_files {
-            if known_manifests.contains(&orig_path) {
+    /// Load the next runtime, or report the next manifest that could not be loaded,
+    /// calling `progress` with each manifest path before it is examined.
+    fn next_with_progress(
+        &mut self,
+        progress: &mut dyn FnMut(&Path) -> ControlFlow<()>,
+    ) -> Result<Option<Result<LinuxRuntime, ManifestError>>, Error> {
+        if let Some(e) = self.unreadable_dirs.next() {
+            return Ok(Some(Err(e)));
+        }
+        for (orig_path, canonical) in self.manifest_files.by_ref() {
+            if self.known_manifests.contains(&orig_path) {
                 continue;
             }
-            if known_manifests.contains(&canonical) {
+            if self.known_manifests.contains(&canonical) {
                 continue;
             }
-            let runtime = match LinuxRuntime::new(&orig_path, &canonical) {
+            check_progress(progress, &orig_path)?;
+            let runtime = match LinuxRuntime::new(&orig_path, &canonical, self.roots) {
                 Ok(r) => r,
                 Err(e) => {
-                    eprintln!(
+                    log::warn!(
                         "Error when trying to load {} -> {}: {}",
                         orig_path.display(),
                         canonical.display(),
                         e
                     );
-                    nonfatal_errors.push(ManifestError(orig_path, e));
-                    continue;
+                    // Make sure a broken active runtime is not silently absent
+                    let e = if self.active_manifest.as_ref() == Some(&canonical) {
+                        Error::ActiveRuntimeManifestUnusable(Box::new(e))
+                    } else {
+                        e
+                    };
+                    // Don't report the same broken manifest again under another path
+                    self.known_manifests.insert(canonical);
+                    self.known_manifests.insert(orig_path.clone());
+                    return Ok(Some(Err(ManifestError(orig_path, e))));
                 }
             };
-            runtimes.push(runtime);
             if orig_path != canonical {
-                known_manifests.insert(canonical);
+                self.known_manifests.insert(canonical);
+            }
+            self.known_manifests.insert(orig_path.clone());
+            // Another manifest for a runtime we already have is just a duplicate
+            if let Some(first) = self.known_runtimes.get(&runtime.library_key()) {
+                log::debug!(
+                    "Skipping {}: it names the same library as {}",
+                    orig_path.display(),
+                    first.display()
+                );
+                continue;
+            }
+            self.known_runtimes.insert(runtime.library_key(), orig_path);
+            return Ok(Some(Ok(runtime)));
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for RuntimeScan<'_> {
+    type Item = Result<LinuxRuntime, ManifestError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Only a request to stop can make this fail
+        self.next_with_progress(&mut |_| ControlFlow::Continue(()))
+            .ok()
+            .flatten()
+    }
+}
+
+impl Platform for LinuxPlatform {
+    type PlatformRuntimeType = LinuxRuntime;
+    type PlatformActiveData = LinuxActiveRuntimeData;
+
+    fn find_available_runtimes_with_progress(
+        &self,
+        extra_paths: Box<dyn '_ + Iterator<Item = PathBuf>>,
+        progress: &mut dyn FnMut(&Path) -> ControlFlow<()>,
+    ) -> Result<(Vec<Self::PlatformRuntimeType>, Vec<ManifestError>), Error> {
+        let mut scan = RuntimeScan::new(self, extra_paths);
+        let mut runtimes = vec![];
+        let mut nonfatal_errors = vec![];
+        while let Some(result) = scan.next_with_progress(progress)? {
+            match result {
+                Ok(runtime) => runtimes.push(runtime),
+                Err(e) => nonfatal_errors.push(e),
             }
-            known_manifests.insert(orig_path);
         }
         Ok((runtimes, nonfatal_errors))
     }
 
+    fn find_available_runtimes_iter<'a>(
+        &'a self,
+        extra_paths: Box<dyn 'a + Iterator<Item = PathBuf>>,
+    ) -> Box<dyn 'a + Iterator<Item = Result<Self::PlatformRuntimeType, ManifestError>>> {
+        Box::new(RuntimeScan::new(self, extra_paths))
+    }
+
+    fn load_extra_runtime(&self, manifest: &Path) -> Result<Self::PlatformRuntimeType, Error> {
+        LinuxRuntime::new(manifest, &normalize_path(manifest), &self.roots)
+    }
+
+    fn find_available_api_layers(
+        &self,
+    ) -> Result<(Vec<ApiLayerManifest>, Vec<ManifestError>), Error> {
+        let mut known_manifests: HashSet<PathBuf> = HashSet::default();
+        let mut layers = vec![];
+        let mut nonfatal_errors = vec![];
+        for kind in [ApiLayerKind::Implicit, ApiLayerKind::Explicit] {
+            for path in api_layer_dirs(&self.roots, kind)
+                .iter()
+                .flat_map(|d| find_api_layer_manifests(d))
+            {
+                if !known_manifests.insert(normalize_path(&path)) {
+                    continue;
+                }
+                match ApiLayerManifest::new(&path, kind) {
+                    Ok(layer) => layers.push(layer),
+                    Err(e) => nonfatal_errors.push(ManifestError(path, e)),
+                }
+            }
+        }
+        Ok((layers, nonfatal_errors))
+    }
+
+    fn get_search_locations(&self) -> Vec<String> {
+        self.roots
+            .search_dirs_with_source()
+            .into_iter()
+            .map(|(d, _)| d.join(&self.path_suffix))
+            .chain(self.get_extra_search_dirs())
+            .map(|d| d.display().to_string())
+            .collect()
+    }
+
+    fn get_loader_search_summary(&self) -> Vec<SearchedDirectory> {
+        // Same directories, in the same order, as find_available_runtimes
+        self.roots
+            .search_dirs_with_source()
+            .into_iter()
+            .map(|(d, source)| (d.join(&self.path_suffix), source))
+            .map(|(path, source)| SearchedDirectory {
+                exists: path.is_dir(),
+                manifest_count: potential_manifests_in(&path).map_or(0, |m| m.len()),
+                path,
+                source,
+            })
+            .chain(
+                self.get_extra_search_dirs()
+                    .into_iter()
+                    .map(|path| SearchedDirectory {
+                        exists: path.is_dir(),
+                        manifest_count: find_potential_manifests_extra(&path)
+                            .map_or(0, |m| m.len()),
+                        path,
+                        source: ConfigSource::ExtraSearchDir,
+                    }),
+            )
+            .collect()
+    }
+
+    fn capabilities(&self) -> PlatformCapabilities {
+        PlatformCapabilities {
+            supports_clear: true,
+            // Architecture-specific active runtimes are optional, and fall back to the plain one
+            has_split_bitness: false,
+            // The active runtime is in the user's own config directory
+            may_need_elevation: false,
+            supports_env_override: true,
+            supports_extra_search_dirs: true,
+            // There is no one file manager to ask
+            file_manager_selects_file: false,
+        }
+    }
+
+    fn get_sandbox_warning(&self) -> Option<String> {
+        let sandbox = self.roots.sandbox?;
+        let config_home = self.roots.config_home.as_ref()?;
+        // Without access, the directory is not there at all
+        if config_home.is_dir() {
+            return None;
+        }
+        Some(sandbox.describe_hidden(config_home))
+    }
+
+    fn set_extra_search_dirs(&self, dirs: &[PathBuf]) {
+        if let Ok(mut extra_search_dirs) = self.extra_search_dirs.lock() {
+            *extra_search_dirs = dirs.to_vec();
+        }
+    }
+
+    fn get_config_directory(&self) -> Option<PathBuf> {
+        user_config_dir(&self.roots).ok()
+    }
+
+    fn get_active_runtime_write_path(&self) -> Option<PathBuf> {
+        active_runtime_write_path(&self.roots, ManifestArchDecoration::Unspecified).ok()
+    }
+
     fn get_active_runtime_manifests(&self) -> Vec<PathBuf> {
-        LinuxActiveRuntimeData::new().0.into_iter().collect()
+        LinuxActiveRuntimeData::new(&self.roots)
+            .configured
+            .into_iter()
+            .collect()
+    }
+
+    fn diagnose_active_runtime(&self) -> ActiveRuntimeDiagnosis {
+        let Some(link) = active_runtime_links(&self.roots).next() else {
+            return ActiveRuntimeDiagnosis::NotConfigured;
+        };
+        if link.exists() {
+            return ActiveRuntimeDiagnosis::Valid(normalize_path(&link));
+        }
+        if is_symlink_loop(&link) {
+            return ActiveRuntimeDiagnosis::SymlinkLoop { link };
+        }
+        let target = fs::read_link(&link)
+            .map(|target| match link.parent() {
+                Some(parent) => parent.join(target),
+                None => target,
+            })
+            .unwrap_or_else(|_| link.clone());
+        ActiveRuntimeDiagnosis::Dangling {
+            link: ConfigLocation::File(link),
+            target,
+        }
+    }
+
+    fn get_active_runtime_candidates(&self) -> Vec<(ConfigLocation, ConfigSource)> {
+        // The loader uses XR_RUNTIME_JSON in preference to any active_runtime.json
+        let env_override = env::var_os(XR_RUNTIME_JSON)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .filter(|p| p.is_file())
+            .map(|p| (p, ConfigSource::Environment));
+        env_override
+            .into_iter()
+            .chain(active_runtime_links_with_source(&self.roots).filter(|(p, _)| p.is_file()))
+            .map(|(p, source)| (ConfigLocation::File(p), source))
+            .collect()
+    }
+
+    fn get_active_runtime(&self) -> Result<Option<Self::PlatformRuntimeType>, Error> {
+        possible_active_runtime_files(&self.roots)
+            .next()
+            .map(|p| LinuxRuntime::new(&p, &normalize_path(&p), &self.roots))
+            .transpose()
+    }
+
+    fn watch_active_runtime(&self, callback: WatchCallback) -> Result<ActiveRuntimeWatcher, Error> {
+        // Shared so that we can still poll if setting up the native watcher fails part way
+        let callback = Arc::new(Mutex::new(callback));
+        let make_callback = || -> WatchCallback {
+            let callback = Arc::clone(&callback);
+            Box::new(move || {
+                if let Ok(mut callback) = callback.lock() {
+                    callback()
+                }
+            })
+        };
+        let native = self
+            .get_config_directory()
+            .ok_or_else(|| notify::Error::generic("No user config directory"))
+            .and_then(|user_dir| {
+                watch_active_runtime_dirs(&self.roots, &user_dir, make_callback())
+            });
+        match native {
+            Ok(watcher) => Ok(ActiveRuntimeWatcher::native(watcher)),
+            Err(e) => {
+                log::debug!(
+                    "Could not watch for active runtime changes, polling instead: {}",
+                    e
+                );
+                let roots = Arc::clone(&self.roots);
+                ActiveRuntimeWatcher::polling(
+                    move || active_runtime_links_snapshot(&roots),
+                    make_callback(),
+                )
+            }
+        }
+    }
+
+    fn clear_active_runtime(&self) -> Result<bool, Error> {
+        // Clear what this architecture's apps would use, whether decorated or not
+        let decorations = once(ManifestArchDecoration::for_current_arch())
+            .chain(once(ManifestArchDecoration::Unspecified))
+            .unique();
+        let mut cleared = false;
+        for decoration in decorations {
+            let locations = ActiveRuntimeLocations::new(&self.roots, decoration)?;
+            cleared |= locations.path.symlink_metadata().is_ok();
+            move_active_runtime_aside(&locations)?;
+            if locations.path.symlink_metadata().is_ok() {
+                return Err(Error::SetActiveError(format!(
+                    "Could not move {} out of the way",
+                    locations.path.display()
+                )));
+            }
+        }
+        Ok(cleared)
     }
 
     fn get_active_data(&self) -> Self::PlatformActiveData {
-        LinuxActiveRuntimeData::new()
+        LinuxActiveRuntimeData::new(&self.roots)
+    }
+
+    fn active_data_is_stale(&self, active_data: &Self::PlatformActiveData) -> bool {
+        active_runtime_links_snapshot(&self.roots) != active_data.links_snapshot
     }
 
     fn get_runtime_active_state(
@@ -260,9 +1383,266 @@ impl Platform for LinuxPlatform {
     ) -> ActiveState {
         active_data.check_runtime(runtime)
     }
+
+    fn describe_active_reason(
+        &self,
+        runtime: &Self::PlatformRuntimeType,
+        active_data: &Self::PlatformActiveData,
+    ) -> Option<String> {
+        active_data.describe_reason(runtime)
+    }
+
+    fn get_active_runtime_override(
+        &self,
+        active_data: &Self::PlatformActiveData,
+    ) -> Option<ActiveRuntimeOverride> {
+        active_data.get_override()
+    }
 }
 
 /// Call to create a platform-specific object implementing the `Platform` trait.
 pub fn make_platform() -> LinuxPlatform {
     LinuxPlatform::new()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use tempfile::TempDir;
+
+    /// Write a runtime manifest named `file_name` in the OpenXR directory under `root`.
+    fn write_manifest(root: &Path, file_name: &str, library_path: &str) -> PathBuf {
+        test_util::write_manifest(
+            &root.join(make_path_suffix()),
+            file_name,
+            library_path,
+            None,
+        )
+    }
+
+    /// A user config directory, one XDG config directory, and a sysconfdir, all empty.
+    struct FakeRoots {
+        _temp: TempDir,
+        home: PathBuf,
+        xdg: PathBuf,
+        etc: PathBuf,
+    }
+
+    impl FakeRoots {
+        fn new() -> Self {
+            let temp = TempDir::new().unwrap();
+            let root = normalize_path(temp.path());
+            Self {
+                home: root.join("home"),
+                xdg: root.join("xdg"),
+                etc: root.join("etc"),
+                _temp: temp,
+            }
+        }
+
+        fn platform(&self) -> LinuxPlatform {
+            LinuxPlatform::with_roots(self.home.clone(), vec![self.xdg.clone()], self.etc.clone())
+        }
+    }
+
+    #[test]
+    fn enumerate_and_find_active() {
+        let roots = FakeRoots::new();
+        let monado = write_manifest(&roots.home, "monado.json", "/usr/lib/libopenxr_monado.so");
+        let steamvr = write_manifest(
+            &roots.xdg,
+            "steamxr_linux64.json",
+            "/opt/steamvr/vrclient.so",
+        );
+        write_manifest(&roots.etc, "varjo.json", "/opt/varjo/VarjoOpenXR.so");
+        let active_link = roots
+            .home
+            .join(make_path_suffix())
+            .join(ACTIVE_RUNTIME_FILENAME);
+        unix::fs::symlink(&steamvr, &active_link).unwrap();
+
+        let platform = roots.platform();
+        let (runtimes, nonfatal_errors) = platform
+            .find_available_runtimes(Box::new(std::iter::empty()))
+            .unwrap();
+        assert!(nonfatal_errors.is_empty(), "{:?}", nonfatal_errors);
+        let names: Vec<String> = runtimes
+            .iter()
+            .map(PlatformRuntime::get_runtime_name)
+            .sorted()
+            .collect();
+        assert_eq!(names, ["Monado", "SteamVR", "Varjo"]);
+
+        let active = platform.get_active_runtime().unwrap().unwrap();
+        assert_eq!(active.get_manifests(), vec![steamvr.as_path()]);
+        assert_eq!(
+            platform.get_active_runtime_write_path(),
+            Some(active_link.clone())
+        );
+
+        let active_data = LinuxActiveRuntimeData::read(&platform.roots, None);
+        for runtime in &runtimes {
+            let expected = if runtime.get_manifests() == vec![steamvr.as_path()] {
+                ActiveState::ActiveIndependentRuntime
+            } else {
+                ActiveState::NotActive
+            };
+            assert_eq!(
+                platform.get_runtime_active_state(runtime, &active_data),
+                expected
+            );
+        }
+
+        // XR_RUNTIME_JSON takes precedence over the active runtime file
+        let active_data = LinuxActiveRuntimeData::read(&platform.roots, Some(monado.into()));
+        for runtime in &runtimes {
+            let expected = match runtime.get_runtime_name().as_str() {
+                "Monado" => ActiveState::ActiveFromEnvironment,
+                "SteamVR" => ActiveState::OverriddenByEnvironment,
+                _ => ActiveState::NotActive,
+            };
+            assert_eq!(
+                platform.get_runtime_active_state(runtime, &active_data),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn no_active_runtime() {
+        let roots = FakeRoots::new();
+        let monado = write_manifest(&roots.home, "monado.json", "/usr/lib/libopenxr_monado.so");
+        let platform = roots.platform();
+        assert!(platform.get_active_runtime().unwrap().is_none());
+
+        // A broken link is not an active runtime either
+        let active_link = roots
+            .home
+            .join(make_path_suffix())
+            .join(ACTIVE_RUNTIME_FILENAME);
+        unix::fs::symlink(roots.home.join("gone.json"), &active_link).unwrap();
+        assert!(platform.get_active_runtime().unwrap().is_none());
+
+        // The loader only looks at the most important active runtime file that exists
+        let etc_link = roots
+            .etc
+            .join(make_path_suffix())
+            .join(ACTIVE_RUNTIME_FILENAME);
+        fs::create_dir_all(etc_link.parent().unwrap()).unwrap();
+        unix::fs::symlink(&monado, &etc_link).unwrap();
+        let active = platform.get_active_runtime().unwrap().unwrap();
+        assert_eq!(active.get_manifests(), vec![monado.as_path()]);
+    }
+
+    #[test]
+    fn manifests_for_the_same_library_are_one_runtime() {
+        let roots = FakeRoots::new();
+        let lib_dir = roots.home.join("lib");
+        fs::create_dir_all(&lib_dir).unwrap();
+        let library = lib_dir.join("libopenxr_monado.so");
+        fs::write(&library, b"").unwrap();
+        let library_link = lib_dir.join("libopenxr_monado.so.1");
+        unix::fs::symlink(&library, &library_link).unwrap();
+        let user = write_manifest(&roots.home, "monado.json", library.to_str().unwrap());
+        let system = write_manifest(&roots.etc, "monado.json", library_link.to_str().unwrap());
+
+        let platform = roots.platform();
+        let (runtimes, _) = platform
+            .find_available_runtimes(Box::new(std::iter::empty()))
+            .unwrap();
+        assert_eq!(runtimes.len(), 1);
+
+        // Whichever manifest is active, the one runtime is
+        let active_link = roots
+            .home
+            .join(make_path_suffix())
+            .join(ACTIVE_RUNTIME_FILENAME);
+        for manifest in [&user, &system] {
+            let _ = fs::remove_file(&active_link);
+            unix::fs::symlink(manifest, &active_link).unwrap();
+            let active_data = LinuxActiveRuntimeData::read(&platform.roots, None);
+            assert_eq!(
+                platform.get_runtime_active_state(&runtimes[0], &active_data),
+                ActiveState::ActiveIndependentRuntime,
+                "{}",
+                manifest.display()
+            );
+        }
+    }
+
+    #[test]
+    fn active_runtime_written_under_xdg_config_home() {
+        let temp = TempDir::new().unwrap();
+        let root = normalize_path(temp.path());
+        let config_home = root.join("config");
+        let vars: HashMap<&str, OsString> = [
+            ("HOME", root.join("home").into()),
+            ("XDG_CONFIG_HOME", config_home.clone().into()),
+            ("XDG_CONFIG_DIRS", root.join("xdg").into()),
+            (SYSCONFDIR_ENV, root.join("etc").into()),
+        ]
+        .into_iter()
+        .collect();
+        let roots = ConfigRoots::from_variables(&|name| vars.get(name).cloned());
+        assert_eq!(roots.config_home, Some(config_home.clone()));
+        assert_eq!(roots.config_dirs, vec![root.join("xdg")]);
+        assert_eq!(roots.sysconfdir, root.join("etc"));
+
+        let manifest = write_manifest(&root.join("etc"), "monado.json", "/opt/libmonado.so");
+        let platform = LinuxPlatform::with_config_roots(roots);
+        let active_file = config_home
+            .join(make_path_suffix())
+            .join(ACTIVE_RUNTIME_FILENAME);
+        assert_eq!(
+            platform.get_active_runtime_write_path(),
+            Some(active_file.clone())
+        );
+        let (runtimes, _) = platform
+            .find_available_runtimes(Box::new(std::iter::empty()))
+            .unwrap();
+        assert_eq!(runtimes.len(), 1);
+        runtimes[0].make_active().unwrap();
+        assert_eq!(normalize_path(&active_file), manifest);
+
+        // Without XDG_CONFIG_HOME, or with a relative path in it, the default is used
+        let home = root.join("home");
+        let vars: HashMap<&str, OsString> = [
+            ("HOME", home.clone().into()),
+            ("XDG_CONFIG_HOME", "relative/config".into()),
+        ]
+        .into_iter()
+        .collect();
+        let roots = ConfigRoots::from_variables(&|name| vars.get(name).cloned());
+        assert_eq!(roots.config_home, Some(home.join(".config")));
+        assert_eq!(roots.config_dirs, vec![PathBuf::from("/etc/xdg")]);
+    }
+
+    #[test]
+    fn sysconfdir_from_environment_variable() {
+        let built_in = option_env!("XRPICKER_SYSCONFDIR").map(PathBuf::from);
+        assert_eq!(sysconfdir_override(None), built_in);
+        assert_eq!(sysconfdir_override(Some("".into())), built_in);
+
+        let temp = TempDir::new().unwrap();
+        let sysconfdir = normalize_path(temp.path());
+        let manifest = write_manifest(&sysconfdir, "staged.json", "/opt/staged/libstaged.so");
+        assert_eq!(
+            sysconfdir_override(Some(sysconfdir.clone().into())),
+            Some(sysconfdir.clone())
+        );
+
+        // Manifests are found in the overriding directory
+        let platform = LinuxPlatform::with_roots(
+            temp.path().join("home"),
+            vec![],
+            sysconfdir_override(Some(sysconfdir.into())).unwrap(),
+        );
+        let (runtimes, _) = platform
+            .find_available_runtimes(Box::new(std::iter::empty()))
+            .unwrap();
+        assert!(runtimes
+            .iter()
+            .any(|r| r.get_manifests() == vec![manifest.as_path()]));
+    }
+}
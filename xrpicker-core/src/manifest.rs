@@ -1,16 +1,127 @@
 // Copyright 2022, Collabora, Ltd.
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use std::path::Path;
+use std::{fs, io, path::Path};
 
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::path_simplifier::PathSimplifier;
+use crate::{path_simplifier::PathSimplifier, Error};
 
 // The string to put between two file names/paths to indicate that one points to another,
 // when used in a *multiline-capable* GUI field.
 pub(crate) const FILE_INDIRECTION_ARROW: &str = "\n    ⮩ ";
 
+// The string to put between a symlink and its final target, on the same line.
+pub(crate) const SYMLINK_ARROW: &str = " -> ";
+
+/// Does this file start like UTF-16 text, with or without a byte order mark?
+fn looks_like_utf16(bytes: &[u8]) -> bool {
+    match bytes {
+        // Byte order marks, little and big endian
+        [0xFF, 0xFE, ..] | [0xFE, 0xFF, ..] => true,
+        // No BOM, but an ASCII character (like the opening brace) padded with a NUL
+        [0, b, ..] | [b, 0, ..] => b.is_ascii() && *b != 0,
+        _ => false,
+    }
+}
+
+/// Read and parse a manifest file, of any kind.
+///
+/// Does not check the file format version.
+pub(crate) fn read_manifest<T: DeserializeOwned>(manifest_path: &Path) -> Result<T, Error> {
+    parse_manifest(fs::read(manifest_path)?)
+}
+
+/// Parse the contents of a manifest file, of any kind.
+///
+/// Does not check the file format version.
+fn parse_manifest<T: DeserializeOwned>(bytes: Vec<u8>) -> Result<T, Error> {
+    if looks_like_utf16(&bytes) {
+        return Err(Error::ManifestUtf16Encoded);
+    }
+    let contents =
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    // Some Windows tooling writes a UTF-8 byte-order mark, which the JSON parser rejects
+    let contents = contents
+        .strip_prefix('\u{feff}')
+        .unwrap_or(&contents)
+        .trim();
+    serde_json::from_str(contents).map_err(|e| {
+        // Valid JSON with the wrong structure deserves a more specific error
+        if e.classify() == serde_json::error::Category::Data {
+            Error::ManifestSchemaError(e.to_string())
+        } else {
+            e.into()
+        }
+    })
+}
+
+/// The newest manifest file format version we know about, as major, minor, patch.
+const NEWEST_KNOWN_FILE_FORMAT_VERSION: (u32, u32, u32) = (1, 0, 0);
+
+/// Parse a "major.minor.patch" file format version.
+fn parse_file_format_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version
+        .trim()
+        .split('.')
+        .map(|part| part.parse::<u32>().ok());
+    let parsed = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(parsed)
+}
+
+/// Can we handle manifests with this file format version?
+///
+/// Any 1.x.y version is accepted, since later minor and patch versions stay compatible,
+/// but we warn about versions newer than we know about.
+fn is_supported_file_format_version(version: &str) -> bool {
+    match parse_file_format_version(version) {
+        Some(parsed @ (1, _, _)) => {
+            if parsed > NEWEST_KNOWN_FILE_FORMAT_VERSION {
+                log::warn!(
+                    "Manifest file format version {} is newer than we know about, using it anyway",
+                    version
+                );
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Substrings of a runtime's library path that identify it, along with the name to show,
+/// for manifests that do not declare a name. The first match wins.
+const RUNTIME_NAME_HEURISTICS: &[(&str, &str)] = &[
+    ("MixedRealityRuntime", "Windows Mixed Reality"),
+    ("monado", "Monado"),
+    ("VarjoOpenXR", "Varjo"),
+    ("steamxr", "SteamVR"),
+    // SteamVR's manifests are named steamxr_*.json, but point at its vrclient library
+    ("vrclient", "SteamVR"),
+];
+
+/// Guess a friendly name for a runtime from its library path, for manifests that do not declare one.
+pub(crate) fn guess_runtime_name_from_library(library_path: &str) -> Option<&'static str> {
+    RUNTIME_NAME_HEURISTICS
+        .iter()
+        .find(|(substring, _)| library_path.contains(substring))
+        .map(|(_, name)| *name)
+}
+
+/// How the library path in a manifest is interpreted by the loader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LibraryPathKind {
+    /// A bare file name, found using the system shared library search path,
+    /// so it may resolve differently for different apps.
+    SearchPath,
+    /// A path relative to the directory containing the manifest
+    RelativeToManifest,
+    /// An absolute path
+    Absolute,
+}
+
 pub(crate) trait GenericManifest {
     /// Get the library path as stored in the manifest
     fn library_path(&self) -> &str;
@@ -32,62 +143,152 @@ pub(crate) trait GenericManifest {
             && path.chars().nth(1) != Some(':')
     }
 
+    /// Classify how the library path will be interpreted.
+    fn classify_library_path(&self) -> LibraryPathKind {
+        if self.uses_search_path() {
+            LibraryPathKind::SearchPath
+        } else if self.library_relative_to_manifest() {
+            LibraryPathKind::RelativeToManifest
+        } else {
+            LibraryPathKind::Absolute
+        }
+    }
+
     /// Describe this manifest by using the manifest path and library path
     fn describe_manifest(&self, manifest_path: &Path) -> String {
         let simplifier = PathSimplifier::new();
         let manifest_path = simplifier.simplify(manifest_path);
         let manifest = manifest_path.display();
-        if self.uses_search_path() {
-            format!(
+        match self.classify_library_path() {
+            LibraryPathKind::SearchPath => format!(
                 "{}{}{} in the dynamic library search path",
                 manifest,
                 FILE_INDIRECTION_ARROW,
                 self.library_path()
-            )
-        } else if self.library_relative_to_manifest() {
-            format!(
+            ),
+            LibraryPathKind::RelativeToManifest => format!(
                 "{}{}{} relative to the manifest",
                 manifest,
                 FILE_INDIRECTION_ARROW,
                 self.library_path()
-            )
-        } else {
-            let lib_path = Path::new(self.library_path());
-            format!(
-                "{}{}{}",
-                manifest,
-                FILE_INDIRECTION_ARROW,
-                simplifier.simplify(lib_path).display()
-            )
+            ),
+            LibraryPathKind::Absolute => {
+                let lib_path = Path::new(self.library_path());
+                format!(
+                    "{}{}{}",
+                    manifest,
+                    FILE_INDIRECTION_ARROW,
+                    simplifier.simplify(lib_path).display()
+                )
+            }
         }
     }
 }
 
 /// Non-top-level objects in a runtime manifest
 pub(crate) mod json_subobjects {
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
 
     /// The optional table of function symbol renaming in a runtime manifest
-    #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
     pub(crate) struct RuntimeFunctions {
-        #[serde(rename = "xrNegotiateLoaderRuntimeInterface")]
+        #[serde(
+            rename = "xrNegotiateLoaderRuntimeInterface",
+            skip_serializing_if = "Option::is_none"
+        )]
         pub(crate) xr_negotiate_loader_runtime_interface: Option<String>,
     }
 
     /// The main object in a runtime manifest
-    #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+    #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
     pub(crate) struct Runtime {
         pub(crate) library_path: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub(crate) name: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub(crate) functions: Option<RuntimeFunctions>,
+        /// Not part of the standard, but some runtimes include it: may be a string or a number.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub(crate) version: Option<serde_json::Value>,
+    }
+
+    /// The main object in an API layer manifest
+    #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+    pub(crate) struct ApiLayer {
+        pub(crate) name: String,
+        pub(crate) library_path: String,
+        pub(crate) api_version: String,
+        pub(crate) implementation_version: String,
+        pub(crate) description: Option<String>,
+        /// Required for implicit layers, absent for explicit ones
+        pub(crate) disable_environment: Option<String>,
     }
 }
 
+/// The file format version we write manifests with.
+const WRITTEN_FILE_FORMAT_VERSION: &str = "1.0.0";
+
 /// Top level structure corresponding to a runtime manifest
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
-pub(crate) struct RuntimeManifest {
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RuntimeManifest {
     file_format_version: String,
     pub(crate) runtime: json_subobjects::Runtime,
+    /// Any other top-level fields, such as vendor-specific ones, kept for debugging
+    #[serde(flatten)]
+    pub(crate) extra_fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl RuntimeManifest {
+    /// Read and parse a runtime manifest.
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        read_manifest(path)
+    }
+
+    /// Write this manifest out as JSON.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Get the file format version declared in this manifest.
+    pub fn file_format_version(&self) -> &str {
+        &self.file_format_version
+    }
+
+    /// Get the library path as stored in the manifest.
+    pub fn library_path(&self) -> &str {
+        &self.runtime.library_path
+    }
+
+    /// Change the library path.
+    pub fn set_library_path(&mut self, library_path: &str) {
+        self.runtime.library_path = library_path.to_owned();
+    }
+
+    /// Get the name the runtime declares, if any.
+    pub fn name(&self) -> Option<&str> {
+        self.runtime.name.as_deref()
+    }
+
+    /// Change or remove the name the runtime declares.
+    pub fn set_name(&mut self, name: Option<&str>) {
+        self.runtime.name = name.map(|s| s.to_owned());
+    }
+
+    /// Write a new, minimal runtime manifest.
+    pub fn write_new(path: &Path, library_path: &str, name: Option<&str>) -> Result<(), Error> {
+        RuntimeManifest {
+            file_format_version: WRITTEN_FILE_FORMAT_VERSION.to_owned(),
+            runtime: json_subobjects::Runtime {
+                library_path: library_path.to_owned(),
+                name: name.map(|s| s.to_owned()),
+                functions: None,
+                version: None,
+            },
+            extra_fields: Default::default(),
+        }
+        .write(path)
+    }
 }
 
 impl GenericManifest for RuntimeManifest {
@@ -95,6 +296,148 @@ impl GenericManifest for RuntimeManifest {
         &self.runtime.library_path
     }
     fn is_file_format_version_ok(&self) -> bool {
-        self.file_format_version == "1.0.0"
+        is_supported_file_format_version(&self.file_format_version)
+    }
+}
+
+/// Top level structure corresponding to an API layer manifest
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub(crate) struct ApiLayerManifestContents {
+    file_format_version: String,
+    pub(crate) api_layer: json_subobjects::ApiLayer,
+}
+
+impl GenericManifest for ApiLayerManifestContents {
+    fn library_path(&self) -> &str {
+        &self.api_layer.library_path
+    }
+    fn is_file_format_version_ok(&self) -> bool {
+        is_supported_file_format_version(&self.file_format_version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_order_mark_and_whitespace() {
+        let json = r#"{"file_format_version": "1.0.0", "runtime": {"library_path": "libopenxr_monado.so"}}"#;
+        let contents = format!("\u{feff}\n  {}\r\n", json);
+        let manifest: RuntimeManifest = parse_manifest(contents.into_bytes()).unwrap();
+        assert_eq!(manifest.library_path(), "libopenxr_monado.so");
+        assert!(manifest.is_file_format_version_ok());
+    }
+
+    #[test]
+    fn utf16_is_detected() {
+        let json = r#"{"file_format_version": "1.0.0", "runtime": {"library_path": "libopenxr_monado.so"}}"#;
+        let utf16: Vec<u16> = json.encode_utf16().collect();
+        let little_endian: Vec<u8> = utf16.iter().flat_map(|c| c.to_le_bytes()).collect();
+        let big_endian: Vec<u8> = utf16.iter().flat_map(|c| c.to_be_bytes()).collect();
+        for (bom, bytes) in [
+            (&[0xFF, 0xFE][..], &little_endian),
+            (&[][..], &little_endian),
+            (&[0xFE, 0xFF][..], &big_endian),
+            (&[][..], &big_endian),
+        ] {
+            let contents = [bom, bytes].concat();
+            assert!(matches!(
+                parse_manifest::<RuntimeManifest>(contents),
+                Err(Error::ManifestUtf16Encoded)
+            ));
+        }
+
+        // Plain UTF-8, including non-ASCII text, is not mistaken for UTF-16
+        let utf8 = json.replace("libopenxr_monado.so", "/opt/rüntime/libopenxr_monado.so");
+        let manifest: RuntimeManifest = parse_manifest(utf8.into_bytes()).unwrap();
+        assert_eq!(manifest.library_path(), "/opt/rüntime/libopenxr_monado.so");
+    }
+
+    #[test]
+    fn file_format_versions() {
+        for version in ["1.0.0", "1.0.1", "1.1.0", " 1.0.0 "] {
+            assert!(is_supported_file_format_version(version), "{}", version);
+        }
+        for version in ["2.0.0", "0.9.0", "1.0", "1.0.0.0", "1.x.0", ""] {
+            assert!(!is_supported_file_format_version(version), "{}", version);
+        }
+        assert_eq!(parse_file_format_version("1.2.3"), Some((1, 2, 3)));
+        assert_eq!(parse_file_format_version("1.0"), None);
+        assert_eq!(parse_file_format_version("1.0.0.0"), None);
+    }
+
+    #[test]
+    fn write_modify_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("active_runtime.json");
+        RuntimeManifest::write_new(&path, "libopenxr_monado.so", Some("Monado")).unwrap();
+
+        let mut manifest = RuntimeManifest::read(&path).unwrap();
+        assert_eq!(manifest.file_format_version(), "1.0.0");
+        assert_eq!(manifest.library_path(), "libopenxr_monado.so");
+        assert_eq!(manifest.name(), Some("Monado"));
+
+        manifest.set_library_path("/opt/monado/lib/libopenxr_monado.so");
+        manifest.set_name(None);
+        manifest.write(&path).unwrap();
+
+        let reread = RuntimeManifest::read(&path).unwrap();
+        assert_eq!(reread, manifest);
+        assert_eq!(reread.library_path(), "/opt/monado/lib/libopenxr_monado.so");
+        assert_eq!(reread.name(), None);
+    }
+
+    #[test]
+    fn schema_errors() {
+        let missing_library_path = br#"{
+            "file_format_version": "1.0.0",
+            "runtime": { "name": "Example" }
+        }"#;
+        let runtime_is_array = br#"{
+            "file_format_version": "1.0.0",
+            "runtime": []
+        }"#;
+        let wrong_type = br#"{
+            "file_format_version": "1.0.0",
+            "runtime": { "library_path": 42 }
+        }"#;
+        for (contents, expected) in [
+            (&missing_library_path[..], "missing field `library_path`"),
+            (&runtime_is_array[..], "expected struct Runtime"),
+            (&wrong_type[..], "invalid type"),
+        ] {
+            match parse_manifest::<RuntimeManifest>(contents.to_vec()) {
+                Err(Error::ManifestSchemaError(reason)) => {
+                    assert!(reason.contains(expected), "{}", reason);
+                    assert!(reason.contains("line"), "{}", reason);
+                }
+                other => panic!("expected a schema error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn guess_runtime_name() {
+        let cases = [
+            (
+                "C:\\Program Files (x86)\\Steam\\steamapps\\common\\SteamVR\\bin\\steamxr_win64.dll",
+                Some("SteamVR"),
+            ),
+            (
+                "/home/user/.steam/steam/steamapps/common/SteamVR/bin/linux64/vrclient.so",
+                Some("SteamVR"),
+            ),
+            ("/usr/lib/x86_64-linux-gnu/libopenxr_monado.so", Some("Monado")),
+            ("/opt/something/libopenxr_unknown.so", None),
+        ];
+        for (library_path, expected) in cases {
+            assert_eq!(
+                guess_runtime_name_from_library(library_path),
+                expected,
+                "{}",
+                library_path
+            );
+        }
     }
 }
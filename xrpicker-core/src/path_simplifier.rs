@@ -3,31 +3,126 @@
 
 use std::{
     borrow::Cow,
+    cmp::Reverse,
     iter,
     path::{Component, Path, PathBuf},
 };
 
-/// Replace the literal home directory path with ~ in a Path and possibly similar things in the future.
+use itertools::Itertools;
+
+/// How the home directory is shown: the way users of each platform would write it.
+#[cfg(windows)]
+const HOME_LABEL: &str = "%USERPROFILE%";
+#[cfg(not(windows))]
+const HOME_LABEL: &str = "~";
+
+/// Replace the literal home directory path with ~ (or `%USERPROFILE%` on Windows) in a Path,
+/// and on Windows, other well-known directories with the variables that name them.
 pub(crate) struct PathSimplifier {
-    home_dir: Option<PathBuf>,
+    /// Prefixes to replace, and what to replace them with, longest prefix first.
+    rules: Vec<(PathBuf, String)>,
 }
 
 impl PathSimplifier {
     pub(crate) fn new() -> Self {
-        Self {
-            home_dir: dirs::home_dir(),
-        }
+        Self::with_home(dirs::home_dir())
+    }
+
+    /// Use the given home directory, if any, along with the platform's other rules.
+    fn with_home(home: Option<PathBuf>) -> Self {
+        let home = home.map(|dir| (dir, HOME_LABEL.to_owned()));
+        Self::with_rules(home.into_iter().chain(platform_rules()))
+    }
+
+    /// Use an explicit set of (prefix, label) rules. Where prefixes overlap, the longest wins.
+    pub(crate) fn with_rules(rules: impl IntoIterator<Item = (PathBuf, String)>) -> Self {
+        let mut rules = rules.into_iter().collect_vec();
+        rules.sort_by_key(|(prefix, _)| Reverse(prefix.components().count()));
+        Self { rules }
     }
 
     pub(crate) fn simplify<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
-        if let Some(home_dir) = &self.home_dir {
-            if path.starts_with(home_dir) {
-                let simplified: PathBuf = iter::once(Component::Normal("~".as_ref()))
-                    .chain(path.components().skip(home_dir.components().count()))
+        match self
+            .rules
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix))
+        {
+            Some((prefix, label)) => {
+                let simplified: PathBuf = iter::once(Component::Normal(label.as_ref()))
+                    .chain(path.components().skip(prefix.components().count()))
                     .collect();
-                return Cow::Owned(simplified);
+                Cow::Owned(simplified)
             }
+            None => Cow::Borrowed(path),
         }
-        Cow::Borrowed(path)
+    }
+}
+
+/// The system and program directories, which most runtime manifests and libraries are in.
+#[cfg(windows)]
+fn platform_rules() -> Vec<(PathBuf, String)> {
+    ["SystemRoot", "ProgramFiles", "ProgramFiles(x86)"]
+        .into_iter()
+        .filter_map(|var| {
+            let dir = std::env::var_os(var).filter(|v| !v.is_empty())?;
+            Some((PathBuf::from(dir), format!("%{}%", var)))
+        })
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn platform_rules() -> Vec<(PathBuf, String)> {
+    vec![]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The root of the filesystem, or of the system drive on Windows.
+    fn root() -> &'static Path {
+        Path::new(if cfg!(windows) { "C:\\" } else { "/" })
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let program_files = root().join("Program Files");
+        let varjo = program_files.join("Varjo");
+        let simplifier = PathSimplifier::with_rules([
+            (program_files.clone(), "%ProgramFiles%".to_owned()),
+            (varjo.clone(), "%VARJO%".to_owned()),
+        ]);
+        assert_eq!(
+            simplifier.simplify(&varjo.join("varjo-openxr/VarjoOpenXR.json")),
+            Path::new("%VARJO%/varjo-openxr/VarjoOpenXR.json")
+        );
+        assert_eq!(
+            simplifier.simplify(&program_files.join("Other/runtime.json")),
+            Path::new("%ProgramFiles%/Other/runtime.json")
+        );
+    }
+
+    #[test]
+    fn home_directory_is_labeled() {
+        let home = root().join("home").join("user");
+        let simplifier = PathSimplifier::with_home(Some(home.clone()));
+        let path = home.join(".config/openxr/1/active_runtime.json");
+        let simplified = simplifier.simplify(&path);
+        assert_eq!(
+            simplified,
+            Path::new(HOME_LABEL).join(".config/openxr/1/active_runtime.json")
+        );
+        assert!(simplified.starts_with(HOME_LABEL));
+    }
+
+    #[test]
+    fn unmatched_path_is_borrowed() {
+        let simplifier = PathSimplifier::with_rules([(
+            root().join("Program Files"),
+            "%ProgramFiles%".to_owned(),
+        )]);
+        // Only whole components match
+        let path = root().join("Program Files Extra").join("runtime.json");
+        assert!(matches!(simplifier.simplify(&path), Cow::Borrowed(p) if p == path));
     }
 }
@@ -0,0 +1,84 @@
+// Copyright 2024, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use std::path::{Component, Path, PathBuf};
+
+/// Normalize a path so that paths can be compared uniformly.
+///
+/// Canonicalizes (resolving symlinks) if possible, otherwise just collapses `.` and `..`
+/// components without touching the filesystem.
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .unwrap_or_else(|_| lexically_normalize_path(path))
+}
+
+/// Collapse `.` and `..` components of a path without touching the filesystem.
+fn lexically_normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                // Can't go above the root
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(component),
+            },
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lexical_normalization() {
+        let cases = [
+            ("a/./b/../c", "a/c"),
+            ("/..", "/"),
+            ("/../a", "/a"),
+            ("../a", "../a"),
+            ("../../a/..", "../.."),
+            ("a/../..", ".."),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(
+                lexically_normalize_path(Path::new(path)),
+                PathBuf::from(expected),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn missing_path_is_normalized_lexically() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        assert_eq!(
+            normalize_path(&base.join("missing/./child/../file.json")),
+            base.join("missing/file.json")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_resolved() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let target = base.join("target");
+        std::fs::create_dir(&target).unwrap();
+        std::fs::write(target.join("file.json"), "{}").unwrap();
+        let link = base.join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert_eq!(
+            normalize_path(&link.join("./file.json")),
+            target.join("file.json")
+        );
+    }
+}
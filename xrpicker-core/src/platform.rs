@@ -1,14 +1,133 @@
 // Copyright 2022-2023, Collabora, Ltd.
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use std::path::{Path, PathBuf};
+use std::{
+    ffi::OsStr,
+    fmt::Display,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
-use crate::{ActiveState, Error, ManifestError};
+use crate::{
+    path_util::normalize_path, ActiveRuntimeWatcher, ActiveState, ApiLayerManifest,
+    EnumerationErrorKind, Error, LibraryPathKind, ManifestError, VerifyResult, WatchCallback,
+    XR_RUNTIME_JSON,
+};
+
+/// A shell command to set an environment variable to a path, quoted as needed.
+#[cfg(windows)]
+fn shell_set_variable(name: &str, path: &Path) -> String {
+    // Quoting the whole assignment keeps the quotes out of the value
+    format!("set \"{}={}\"", name, path.display())
+}
+
+/// A shell command to set an environment variable to a path, quoted as needed.
+#[cfg(not(windows))]
+fn shell_set_variable(name: &str, path: &Path) -> String {
+    let quoted = path.display().to_string().replace('\'', r"'\''");
+    format!("export {}='{}'", name, quoted)
+}
+
+/// Calls a progress callback for `path`, turning a request to stop into an error.
+pub(crate) fn check_progress(
+    progress: &mut dyn FnMut(&Path) -> ControlFlow<()>,
+    path: &Path,
+) -> Result<(), Error> {
+    match progress(path) {
+        ControlFlow::Continue(()) => Ok(()),
+        ControlFlow::Break(()) => Err(Error::EnumerationError(EnumerationErrorKind::Cancelled)),
+    }
+}
+
+/// Default number of backups of the previous active runtime to keep.
+pub const DEFAULT_MAX_BACKUPS: usize = 10;
+
+/// Options for `PlatformRuntime::make_active_with_options`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MakeActiveOptions {
+    /// How many backups of the previous active runtime to keep, where the platform makes them.
+    ///
+    /// 0 means keep all of them.
+    pub max_backups: usize,
+
+    /// Where the platform supports it, write an architecture-specific active runtime
+    /// (e.g. `active_runtime.x86_64.json`) based on the runtime binary, so that apps of
+    /// other architectures can use a different runtime.
+    ///
+    /// Universal runtimes are always written undecorated.
+    pub arch_decorated: bool,
+
+    /// Where the platform uses files, link to the runtime's manifest rather than copying it.
+    ///
+    /// A copy stays valid even if the original path does not (e.g. in a container), but will not
+    /// pick up changes to the original.
+    pub use_symlink: bool,
+
+    /// Where the platform has both system-wide and per-user active runtimes (Windows),
+    /// only make the runtime active for the current user, which does not need administrator rights.
+    pub per_user: bool,
+}
+
+impl Default for MakeActiveOptions {
+    fn default() -> Self {
+        Self {
+            max_backups: DEFAULT_MAX_BACKUPS,
+            arch_decorated: false,
+            use_symlink: true,
+            per_user: false,
+        }
+    }
+}
+
+/// What making a runtime active would change, from `PlatformRuntime::preview_make_active`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MakeActivePlan {
+    /// Descriptions of each filesystem or registry change, in the order they would be made.
+    pub steps: Vec<String>,
+}
+
+impl Display for MakeActivePlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.steps.join("\n"))
+    }
+}
 
 /// Trait for platform-specific interaction with a runtime.
 pub trait PlatformRuntime {
     /// Attempt to make this runtime active.
-    fn make_active(&self) -> Result<(), Error>;
+    fn make_active(&self) -> Result<(), Error> {
+        self.make_active_with_options(&MakeActiveOptions::default())
+    }
+
+    /// Attempt to make this runtime active, using the given options.
+    fn make_active_with_options(&self, options: &MakeActiveOptions) -> Result<(), Error>;
+
+    /// Write an `active_runtime.json` for this runtime into `dir` (creating it if needed),
+    /// for use somewhere other than the usual place, e.g. a different `XDG_CONFIG_HOME`.
+    ///
+    /// Replaces any existing file there, without a backup. Returns the path written.
+    fn export_active_runtime_to(&self, dir: &Path) -> Result<PathBuf, Error>;
+
+    /// Describe what `make_active` would change, without changing anything.
+    fn preview_make_active(&self) -> Result<MakeActivePlan, Error> {
+        self.preview_make_active_with_options(&MakeActiveOptions::default())
+    }
+
+    /// Describe what `make_active_with_options` would change, without changing anything.
+    fn preview_make_active_with_options(
+        &self,
+        options: &MakeActiveOptions,
+    ) -> Result<MakeActivePlan, Error>;
+
+    /// Get the file that making this runtime active with the given options will write to,
+    /// if it is stored in the filesystem.
+    ///
+    /// Unlike `Platform::get_active_runtime_write_path()`, this accounts for
+    /// architecture-specific file names.
+    fn get_active_runtime_write_path(&self, _options: &MakeActiveOptions) -> Option<PathBuf> {
+        None
+    }
 
     /// Get a name for the runtime, preferably the self-declared one.
     ///
@@ -18,8 +137,221 @@ pub trait PlatformRuntime {
     fn get_manifests(&self) -> Vec<&Path>;
     fn get_libraries(&self) -> Vec<PathBuf>;
 
+    /// How the loader will interpret the library path in the (first) manifest.
+    fn library_path_kind(&self) -> LibraryPathKind;
+
+    /// Check that the library looks like a usable OpenXR runtime: that it exists and
+    /// provides the negotiate function.
+    ///
+    /// By default, this only inspects the binary: with the `dlopen-verify` feature,
+    /// it loads the library instead.
+    fn verify(&self) -> VerifyResult;
+
+    /// Check that the library exports the negotiate function the manifest names (or the standard
+    /// one), only by reading the binary: never loading it, even with the `dlopen-verify` feature.
+    fn open_negotiate_check(&self) -> VerifyResult;
+
+    /// A key identifying this runtime for de-duplication: the sorted, normalized manifest paths,
+    /// so it does not depend on the order or spelling of the manifest paths.
+    fn uniqueness_key(&self) -> Vec<PathBuf> {
+        let mut key: Vec<PathBuf> = self
+            .get_manifests()
+            .into_iter()
+            .map(normalize_path)
+            .collect();
+        key.sort();
+        key
+    }
+
     /// Describe this specific instance of a runtime: usually using the manifest(s) and library
     fn describe(&self) -> String;
+
+    /// Describe the architecture(s) of the runtime binaries, e.g. "64-bit" or "universal (search path)".
+    ///
+    /// Inspects the binaries the first time it is called, then caches the result.
+    fn get_arch_description(&self) -> String;
+
+    /// Have the runtime binaries been inspected already, so that `get_arch_description()` and
+    /// `is_compatible_with_host()` are cheap?
+    fn is_arch_description_resolved(&self) -> bool {
+        true
+    }
+
+    /// Get the version of this runtime, if we can find out.
+    ///
+    /// The standard manifest format has no version, so this is only known for some runtimes.
+    fn get_runtime_version(&self) -> Option<String> {
+        None
+    }
+
+    /// Get the name the manifest gives the loader negotiation function, if it renames it.
+    ///
+    /// `None` means the standard `xrNegotiateLoaderRuntimeInterface` is used.
+    fn get_negotiate_function_name(&self) -> Option<String> {
+        None
+    }
+
+    /// Can apps on this computer load this runtime, as far as we can tell?
+    ///
+    /// Only false if the runtime's library is for a single architecture that apps here can't use.
+    /// Universal (search path or fat binary) runtimes, and ones we can't inspect, count as compatible.
+    fn is_compatible_with_host(&self) -> bool {
+        true
+    }
+
+    /// The manifest to put in `XR_RUNTIME_JSON` to use this runtime, as a canonical path.
+    ///
+    /// If there is more than one manifest (one per architecture), the first is used: on Windows,
+    /// that is the 64-bit one. The loader uses the variable whatever the app's bitness, so a
+    /// 32-bit app given this will fail to load the runtime.
+    fn get_env_override_manifest(&self) -> Option<PathBuf> {
+        self.get_manifests().first().map(|p| normalize_path(p))
+    }
+
+    /// Create a command to run `program` with this runtime selected for that process only,
+    /// by setting `XR_RUNTIME_JSON`. Does not change the active runtime for anything else.
+    ///
+    /// Uses `get_env_override_manifest()`, so it only suits programs of that manifest's
+    /// architecture (64-bit ones, on Windows).
+    fn command_with_runtime(&self, program: &OsStr) -> Command {
+        let mut command = Command::new(program);
+        if let Some(manifest) = self.get_env_override_manifest() {
+            command.env(XR_RUNTIME_JSON, manifest);
+        }
+        command
+    }
+
+    /// A shell command that selects this runtime for programs started from that shell afterwards,
+    /// by setting `XR_RUNTIME_JSON`: for `cmd.exe` on Windows, otherwise for POSIX shells.
+    ///
+    /// Like `command_with_runtime()`, this only suits programs of one architecture.
+    fn env_override_shell_command(&self) -> Option<String> {
+        self.get_env_override_manifest()
+            .map(|manifest| shell_set_variable(XR_RUNTIME_JSON, &manifest))
+    }
+}
+
+/// Describes an active runtime override from the `XR_RUNTIME_JSON` environment variable,
+/// when it differs from the configured active runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveRuntimeOverride {
+    /// The manifest the environment variable points at, which the loader will actually use.
+    pub effective: PathBuf,
+    /// The manifest(s) the configured active runtime points at, which is what we would change.
+    pub configured: Vec<PathBuf>,
+}
+
+/// The state of the configured active runtime reference, independent of whether we can load it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActiveRuntimeDiagnosis {
+    /// No active runtime is configured.
+    NotConfigured,
+    /// The active runtime reference points at a manifest that does not exist.
+    Dangling {
+        /// Where the reference is: the `active_runtime.json` symlink, or the registry key.
+        link: ConfigLocation,
+        /// The missing manifest it points at.
+        target: PathBuf,
+    },
+    /// The active runtime reference is a symlink that leads back to itself,
+    /// so it never reaches a manifest.
+    SymlinkLoop {
+        /// The `active_runtime.json` symlink.
+        link: PathBuf,
+    },
+    /// The active runtime reference points at an existing manifest.
+    Valid(PathBuf),
+}
+
+/// Where an active runtime setting comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConfigSource {
+    /// The `XR_RUNTIME_JSON` environment variable.
+    Environment,
+    /// Configuration for just this user: `XDG_CONFIG_HOME`, or `HKEY_CURRENT_USER`.
+    User,
+    /// System-wide configuration: a directory in `XDG_CONFIG_DIRS`, or `HKEY_LOCAL_MACHINE`.
+    System,
+    /// The fallback system configuration directory, normally `/etc`.
+    Sysconfdir,
+    /// A directory the user added to search for runtimes. Never holds active runtime settings.
+    ExtraSearchDir,
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigSource::Environment => write!(f, "environment variable {}", XR_RUNTIME_JSON),
+            ConfigSource::User => write!(f, "user configuration"),
+            ConfigSource::System => write!(f, "system configuration"),
+            ConfigSource::Sysconfdir => write!(f, "fallback system configuration"),
+            ConfigSource::ExtraSearchDir => write!(f, "added search folder"),
+        }
+    }
+}
+
+/// Where an active runtime setting is stored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ConfigLocation {
+    /// A file, such as `active_runtime.json`, or the manifest named by `XR_RUNTIME_JSON`.
+    File(PathBuf),
+    /// A registry key, such as `HKEY_LOCAL_MACHINE\Software\Khronos\OpenXR\1`.
+    RegistryKey(String),
+}
+
+impl Display for ConfigLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigLocation::File(path) => write!(f, "{}", path.display()),
+            ConfigLocation::RegistryKey(key) => write!(f, "{}", key),
+        }
+    }
+}
+
+/// A directory that was searched for runtime manifests, and what was found there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchedDirectory {
+    /// The directory, with any environment variables already resolved.
+    pub path: PathBuf,
+    /// Which setting the directory comes from.
+    pub source: ConfigSource,
+    /// Whether the directory exists.
+    pub exists: bool,
+    /// How many potential runtime manifests are in it.
+    pub manifest_count: usize,
+}
+
+impl Display for SearchedDirectory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): ", self.path.display(), self.source)?;
+        match (self.exists, self.manifest_count) {
+            (false, _) => write!(f, "does not exist"),
+            (true, 1) => write!(f, "1 manifest"),
+            (true, n) => write!(f, "{} manifests", n),
+        }
+    }
+}
+
+/// What a platform supports, so that frontends can adapt their UI to it rather than checking
+/// which platform they were built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlatformCapabilities {
+    /// Whether `Platform::clear_active_runtime()` can leave no runtime active.
+    pub supports_clear: bool,
+    /// Whether there is a separate active runtime for each bitness (e.g. 64-bit and 32-bit),
+    /// so a runtime may be active for only some apps.
+    pub has_split_bitness: bool,
+    /// Whether making a runtime active may need administrator rights.
+    pub may_need_elevation: bool,
+    /// Whether the loader honors `XR_RUNTIME_JSON`, so a runtime can be chosen for just
+    /// the programs started from a terminal.
+    pub supports_env_override: bool,
+    /// Whether runtimes are found by searching directories, so extra search directories
+    /// (`Platform::set_extra_search_dirs()`) have any effect.
+    pub supports_extra_search_dirs: bool,
+    /// Whether the file manager can show a file selected in its folder (with `explorer /select,`),
+    /// rather than just opening the folder.
+    pub file_manager_selects_file: bool,
 }
 
 /// Trait abstracting over the underlying system/platform type.
@@ -38,16 +370,143 @@ pub trait Platform {
     fn find_available_runtimes(
         &self,
         extra_paths: Box<dyn '_ + Iterator<Item = PathBuf>>,
+    ) -> Result<(Vec<Self::PlatformRuntimeType>, Vec<ManifestError>), Error> {
+        self.find_available_runtimes_with_progress(extra_paths, &mut |_| ControlFlow::Continue(()))
+    }
+
+    /// Enumerate all available runtimes we might be aware of, calling `progress`
+    /// with each manifest path before it is examined.
+    ///
+    /// If `progress` returns `ControlFlow::Break`, enumeration stops and
+    /// `EnumerationErrorKind::Cancelled` is returned.
+    fn find_available_runtimes_with_progress(
+        &self,
+        extra_paths: Box<dyn '_ + Iterator<Item = PathBuf>>,
+        progress: &mut dyn FnMut(&Path) -> ControlFlow<()>,
     ) -> Result<(Vec<Self::PlatformRuntimeType>, Vec<ManifestError>), Error>;
 
+    /// Enumerate available runtimes lazily, yielding each one (or each manifest that could not
+    /// be loaded) as soon as it has been examined, so a frontend can show them as they are found.
+    ///
+    /// Yields the same runtimes and errors as `find_available_runtimes()`, interleaved.
+    fn find_available_runtimes_iter<'a>(
+        &'a self,
+        extra_paths: Box<dyn 'a + Iterator<Item = PathBuf>>,
+    ) -> Box<dyn 'a + Iterator<Item = Result<Self::PlatformRuntimeType, ManifestError>>>;
+
+    /// Load a single runtime from a manifest chosen by the user, the same way an extra path
+    /// passed to `find_available_runtimes()` would be loaded.
+    fn load_extra_runtime(&self, manifest: &Path) -> Result<Self::PlatformRuntimeType, Error>;
+
+    /// Enumerate all API layers we might be aware of, both implicit and explicit.
+    ///
+    /// These are only listed, there is nothing to make active.
+    fn find_available_api_layers(
+        &self,
+    ) -> Result<(Vec<ApiLayerManifest>, Vec<ManifestError>), Error>;
+
+    /// Describe the directories, registry keys, etc. that are consulted to find runtimes and
+    /// the active runtime, in the order they are consulted.
+    ///
+    /// Does not read or scan any of them, so it is free of side effects.
+    fn get_search_locations(&self) -> Vec<String>;
+
+    /// Summarize the directories actually searched for runtime manifests, in order,
+    /// including which exist and how many manifests each holds.
+    ///
+    /// Unlike `get_search_locations()`, this reads the directories. It is empty on platforms
+    /// that do not find runtimes by searching directories.
+    fn get_loader_search_summary(&self) -> Vec<SearchedDirectory> {
+        vec![]
+    }
+
+    /// Describe what this platform supports, for frontends to adapt to.
+    fn capabilities(&self) -> PlatformCapabilities;
+
+    /// Explain why runtimes and settings may be missing because we are running in a sandbox
+    /// (e.g. Flatpak) that hides them, and how to grant access, if so.
+    fn get_sandbox_warning(&self) -> Option<String> {
+        None
+    }
+
+    /// Also search these directories for runtime manifests (`*.json`), after the standard ones.
+    ///
+    /// Ignored on platforms that do not find runtimes by searching directories.
+    fn set_extra_search_dirs(&self, _dirs: &[PathBuf]) {}
+
+    /// Get the directory where the user's active runtime configuration is stored (along with
+    /// any backups), if it is stored in the filesystem.
+    fn get_config_directory(&self) -> Option<PathBuf>;
+
+    /// Get the file that making a runtime active with the default options will write to,
+    /// if it is stored in the filesystem.
+    ///
+    /// On Linux this honors `XDG_CONFIG_HOME`, so it is useful for diagnosing where a selection went.
+    /// See `PlatformRuntime::get_active_runtime_write_path()` for a specific runtime and options.
+    fn get_active_runtime_write_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Does changing the active runtime affect every user of the system, rather than just this one?
+    fn active_runtime_is_system_wide(&self) -> bool {
+        false
+    }
+
     /// Get the paths of all active runtime manifests. (There may be one per architecture.)
     fn get_active_runtime_manifests(&self) -> Vec<PathBuf>;
 
+    /// Get every active runtime setting the loader would consider, in its order of precedence,
+    /// along with where each comes from.
+    ///
+    /// The loader uses the first one, so any others are overridden by it. The locations are where
+    /// the setting is (e.g. the `active_runtime.json` file), not the manifests they resolve to.
+    /// Where there is one active runtime per architecture, all of them are included.
+    fn get_active_runtime_candidates(&self) -> Vec<(ConfigLocation, ConfigSource)>;
+
+    /// Load the active runtime directly from its manifest(s), without enumerating all runtimes.
+    ///
+    /// Returns `Ok(None)` if no runtime is active, and an error if the active manifest could not be loaded.
+    /// Where there is one active runtime per architecture, they are combined into a single object.
+    fn get_active_runtime(&self) -> Result<Option<Self::PlatformRuntimeType>, Error>;
+
+    /// Check whether the configured active runtime reference points at an existing manifest.
+    ///
+    /// Unlike the other active runtime methods, this does not ignore broken references.
+    fn diagnose_active_runtime(&self) -> ActiveRuntimeDiagnosis;
+
+    /// Start watching for something else changing the active runtime, calling `callback`
+    /// (from another thread) when it may have changed.
+    ///
+    /// Uses the system's change notifications where possible, and polls otherwise.
+    /// Watching stops when the returned watcher is dropped.
+    fn watch_active_runtime(&self, callback: WatchCallback) -> Result<ActiveRuntimeWatcher, Error>;
+
+    /// Attempt to return to having no active runtime (for all architectures).
+    ///
+    /// Returns whether there was anything to clear.
+    fn clear_active_runtime(&self) -> Result<bool, Error>;
+
     /// Get a snapshot of what the active runtime(s) is/are,
     /// to use when checking if a runtime we know about is active.
     /// Returns a relatively opaque type used to pass into `get_runtime_active_state()`
     fn get_active_data(&self) -> Self::PlatformActiveData;
 
+    /// Have the active runtime settings changed since `active_data` was read?
+    ///
+    /// Only does a cheap check (file times, registry values), so it is fine to call often.
+    fn active_data_is_stale(&self, active_data: &Self::PlatformActiveData) -> bool;
+
+    /// Explain what makes the given runtime active, e.g. which file or registry value selects it.
+    ///
+    /// Returns `None` if it is not active.
+    fn describe_active_reason(
+        &self,
+        _runtime: &Self::PlatformRuntimeType,
+        _active_data: &Self::PlatformActiveData,
+    ) -> Option<String> {
+        None
+    }
+
     /// Is the given runtime marked as active?
     ///
     /// Some platforms might have separate 32-bit and 64-bit active runtime settings,
@@ -57,4 +516,15 @@ pub trait Platform {
         runtime: &Self::PlatformRuntimeType,
         active_data: &Self::PlatformActiveData,
     ) -> ActiveState;
+
+    /// Is the environment overriding the configured active runtime with a different one?
+    ///
+    /// If so, changing the active runtime will not have an effect on the loader
+    /// until the environment variable is unset.
+    fn get_active_runtime_override(
+        &self,
+        _active_data: &Self::PlatformActiveData,
+    ) -> Option<ActiveRuntimeOverride> {
+        None
+    }
 }
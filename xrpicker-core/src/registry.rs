@@ -0,0 +1,406 @@
+// Copyright 2024, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Access to the registry values the OpenXR loader uses, behind a trait so the logic using
+//! them can be tested without touching the machine's registry.
+
+use std::{ffi::OsStr, fmt::Debug, io};
+
+use winreg::{
+    enums::{
+        HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, KEY_CREATE_SUB_KEY, KEY_QUERY_VALUE, KEY_READ,
+        KEY_WOW64_32KEY, KEY_WOW64_64KEY, KEY_WRITE,
+    },
+    RegKey,
+};
+
+/// A registry hive the loader looks in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Hive {
+    /// `HKEY_CURRENT_USER`, for settings for just this user
+    CurrentUser,
+    /// `HKEY_LOCAL_MACHINE`, for system-wide settings
+    LocalMachine,
+}
+
+impl Hive {
+    /// The hives in the loader's order of precedence.
+    pub(crate) const ALL: [Hive; 2] = [Hive::CurrentUser, Hive::LocalMachine];
+
+    /// The name Windows gives the hive, which starts the full name of a key in it.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Hive::CurrentUser => "HKEY_CURRENT_USER",
+            Hive::LocalMachine => "HKEY_LOCAL_MACHINE",
+        }
+    }
+
+    fn predef(self) -> RegKey {
+        RegKey::predef(match self {
+            Hive::CurrentUser => HKEY_CURRENT_USER,
+            Hive::LocalMachine => HKEY_LOCAL_MACHINE,
+        })
+    }
+}
+
+/// A view of the registry: on 64-bit Windows, 32-bit apps see a separate one for most keys,
+/// including the loader's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum RegistryView {
+    Bits64,
+    Bits32,
+}
+
+impl RegistryView {
+    /// Both views, the 64-bit one first.
+    pub(crate) const ALL: [RegistryView; 2] = [RegistryView::Bits64, RegistryView::Bits32];
+
+    /// Describe the view, e.g. "64-bit".
+    pub(crate) fn description(self) -> &'static str {
+        match self {
+            RegistryView::Bits64 => "64-bit",
+            RegistryView::Bits32 => "32-bit",
+        }
+    }
+
+    /// The flags to open a key in this view with.
+    pub(crate) fn flags(self) -> u32 {
+        match self {
+            RegistryView::Bits64 => KEY_WOW64_64KEY,
+            RegistryView::Bits32 => KEY_WOW64_32KEY,
+        }
+    }
+}
+
+/// Reading and writing the registry values the loader uses.
+///
+/// Keys are relative to the hive, with backslashes between their parts. A key or value
+/// that does not exist is an `io::ErrorKind::NotFound` error.
+pub(crate) trait RegistryAccess: Debug + Send + Sync {
+    /// Does this view of the registry exist here? 32-bit Windows has no 64-bit view.
+    fn has_view(&self, view: RegistryView) -> bool;
+
+    /// Read a string value.
+    fn get_string(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &str,
+        name: &str,
+    ) -> io::Result<String>;
+
+    /// List the names of the values in a key, along with the value of each that is a DWORD.
+    fn get_dword_values(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &str,
+    ) -> io::Result<Vec<(String, Option<u32>)>>;
+
+    /// List the names of the subkeys of a key.
+    fn get_subkeys(&self, hive: Hive, view: RegistryView, key: &str) -> io::Result<Vec<String>>;
+
+    /// Set a string value, creating the key if needed.
+    fn set_string(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &str,
+        name: &str,
+        value: &OsStr,
+    ) -> io::Result<()>;
+
+    /// Remove a value.
+    fn delete_value(&self, hive: Hive, view: RegistryView, key: &str, name: &str)
+        -> io::Result<()>;
+
+    /// Could we set values in a key, creating it if needed? Checks without changing anything.
+    fn can_write(&self, hive: Hive, view: RegistryView, key: &str) -> bool;
+}
+
+/// The real registry, through `winreg`.
+#[derive(Debug, Default)]
+pub(crate) struct WindowsRegistry;
+
+impl RegistryAccess for WindowsRegistry {
+    #[cfg(target_pointer_width = "64")]
+    fn has_view(&self, _view: RegistryView) -> bool {
+        true
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    fn has_view(&self, view: RegistryView) -> bool {
+        // A 32-bit build only sees a 64-bit view when running on 64-bit Windows
+        view == RegistryView::Bits32 || iswow64::iswow64()
+    }
+
+    fn get_string(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &str,
+        name: &str,
+    ) -> io::Result<String> {
+        hive.predef()
+            .open_subkey_with_flags(key, view.flags() | KEY_READ | KEY_QUERY_VALUE)?
+            .get_value(name)
+    }
+
+    fn get_dword_values(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &str,
+    ) -> io::Result<Vec<(String, Option<u32>)>> {
+        let key = hive
+            .predef()
+            .open_subkey_with_flags(key, view.flags() | KEY_READ | KEY_QUERY_VALUE)?;
+        Ok(key
+            .enum_values()
+            .filter_map(|v| v.ok())
+            .map(|(name, _)| {
+                let value = key.get_value::<u32, _>(&name).ok();
+                (name, value)
+            })
+            .collect())
+    }
+
+    fn get_subkeys(&self, hive: Hive, view: RegistryView, key: &str) -> io::Result<Vec<String>> {
+        hive.predef()
+            .open_subkey_with_flags(key, view.flags() | KEY_READ)?
+            .enum_keys()
+            .collect()
+    }
+
+    fn set_string(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &str,
+        name: &str,
+        value: &OsStr,
+    ) -> io::Result<()> {
+        let (key, _disp) = hive.predef().create_subkey_with_flags(
+            key,
+            view.flags() | KEY_WRITE | KEY_READ | KEY_QUERY_VALUE | KEY_CREATE_SUB_KEY,
+        )?;
+        key.set_value(name, &value)
+    }
+
+    fn delete_value(
+        &self,
+        hive: Hive,
+        view: RegistryView,
+        key: &str,
+        name: &str,
+    ) -> io::Result<()> {
+        hive.predef()
+            .open_subkey_with_flags(key, view.flags() | KEY_WRITE | KEY_READ | KEY_QUERY_VALUE)?
+            .delete_value(name)
+    }
+
+    fn can_write(&self, hive: Hive, view: RegistryView, key: &str) -> bool {
+        let predef = hive.predef();
+        // The key itself, then each of its parents, in case it does not exist yet
+        let ancestors = std::iter::successors(Some(key), |key| {
+            key.rsplit_once('\\').map(|(parent, _)| parent)
+        });
+        for (i, key) in ancestors.enumerate() {
+            let access = if i == 0 {
+                KEY_WRITE
+            } else {
+                KEY_CREATE_SUB_KEY
+            };
+            match predef.open_subkey_with_flags(key, view.flags() | access) {
+                Ok(_) => return true,
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return false,
+                // Doesn't exist yet, so see if we could create it in its parent
+                Err(_) => continue,
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+pub(crate) use memory::MemoryRegistry;
+
+#[cfg(test)]
+mod memory {
+    use super::*;
+    use std::{
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+        sync::Mutex,
+    };
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Value {
+        String(String),
+        Dword(u32),
+    }
+
+    type Location = (Hive, RegistryView, String);
+
+    /// An in-memory registry for tests, with both views unless told otherwise.
+    ///
+    /// Keys exist only while they hold values, or have subkeys that do.
+    #[derive(Debug, Default)]
+    pub(crate) struct MemoryRegistry {
+        keys: Mutex<HashMap<Location, BTreeMap<String, Value>>>,
+        /// Views that do not exist, as on 32-bit Windows
+        missing_views: Mutex<HashSet<RegistryView>>,
+        /// Where writes fail as if we were not allowed to make them
+        read_only: Mutex<HashSet<(Hive, RegistryView)>>,
+    }
+
+    fn not_found() -> io::Error {
+        io::Error::from(io::ErrorKind::NotFound)
+    }
+
+    impl MemoryRegistry {
+        /// Set a DWORD value, as used in `AvailableRuntimes`.
+        pub(crate) fn set_dword(
+            &self,
+            hive: Hive,
+            view: RegistryView,
+            key: &str,
+            name: &str,
+            value: u32,
+        ) {
+            self.keys
+                .lock()
+                .unwrap()
+                .entry((hive, view, key.to_owned()))
+                .or_default()
+                .insert(name.to_owned(), Value::Dword(value));
+        }
+
+        /// Make writes to a hive and view fail with `PermissionDenied`.
+        pub(crate) fn make_read_only(&self, hive: Hive, view: RegistryView) {
+            self.read_only.lock().unwrap().insert((hive, view));
+        }
+
+        /// Act as if a view does not exist.
+        pub(crate) fn remove_view(&self, view: RegistryView) {
+            self.missing_views.lock().unwrap().insert(view);
+        }
+
+        fn check_writable(&self, hive: Hive, view: RegistryView) -> io::Result<()> {
+            if self.read_only.lock().unwrap().contains(&(hive, view)) {
+                Err(io::Error::from(io::ErrorKind::PermissionDenied))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl RegistryAccess for MemoryRegistry {
+        fn has_view(&self, view: RegistryView) -> bool {
+            !self.missing_views.lock().unwrap().contains(&view)
+        }
+
+        fn get_string(
+            &self,
+            hive: Hive,
+            view: RegistryView,
+            key: &str,
+            name: &str,
+        ) -> io::Result<String> {
+            match self
+                .keys
+                .lock()
+                .unwrap()
+                .get(&(hive, view, key.to_owned()))
+                .and_then(|values| values.get(name))
+            {
+                Some(Value::String(s)) => Ok(s.clone()),
+                Some(Value::Dword(_)) => Err(io::Error::from(io::ErrorKind::InvalidData)),
+                None => Err(not_found()),
+            }
+        }
+
+        fn get_dword_values(
+            &self,
+            hive: Hive,
+            view: RegistryView,
+            key: &str,
+        ) -> io::Result<Vec<(String, Option<u32>)>> {
+            let keys = self.keys.lock().unwrap();
+            let values = keys
+                .get(&(hive, view, key.to_owned()))
+                .ok_or_else(not_found)?;
+            Ok(values
+                .iter()
+                .map(|(name, value)| {
+                    let dword = match value {
+                        Value::Dword(d) => Some(*d),
+                        Value::String(_) => None,
+                    };
+                    (name.clone(), dword)
+                })
+                .collect())
+        }
+
+        fn get_subkeys(
+            &self,
+            hive: Hive,
+            view: RegistryView,
+            key: &str,
+        ) -> io::Result<Vec<String>> {
+            let prefix = format!("{}\\", key);
+            let keys = self.keys.lock().unwrap();
+            let subkeys: BTreeSet<String> = keys
+                .keys()
+                .filter(|(h, v, _)| *h == hive && *v == view)
+                .filter_map(|(_, _, path)| path.strip_prefix(&prefix))
+                .map(|rest| rest.split('\\').next().unwrap_or(rest).to_owned())
+                .collect();
+            if subkeys.is_empty() && !keys.contains_key(&(hive, view, key.to_owned())) {
+                return Err(not_found());
+            }
+            Ok(subkeys.into_iter().collect())
+        }
+
+        fn set_string(
+            &self,
+            hive: Hive,
+            view: RegistryView,
+            key: &str,
+            name: &str,
+            value: &OsStr,
+        ) -> io::Result<()> {
+            self.check_writable(hive, view)?;
+            self.keys
+                .lock()
+                .unwrap()
+                .entry((hive, view, key.to_owned()))
+                .or_default()
+                .insert(
+                    name.to_owned(),
+                    Value::String(value.to_string_lossy().into_owned()),
+                );
+            Ok(())
+        }
+
+        fn delete_value(
+            &self,
+            hive: Hive,
+            view: RegistryView,
+            key: &str,
+            name: &str,
+        ) -> io::Result<()> {
+            self.check_writable(hive, view)?;
+            self.keys
+                .lock()
+                .unwrap()
+                .get_mut(&(hive, view, key.to_owned()))
+                .and_then(|values| values.remove(name))
+                .map(|_| ())
+                .ok_or_else(not_found)
+        }
+
+        fn can_write(&self, hive: Hive, view: RegistryView, _key: &str) -> bool {
+            self.check_writable(hive, view).is_ok()
+        }
+    }
+}
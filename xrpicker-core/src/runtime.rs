@@ -2,11 +2,20 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use std::{
-    fs,
+    fs, io,
     path::{Path, PathBuf},
 };
 
-use crate::{manifest::GenericManifest, Error, RuntimeManifest};
+use itertools::Itertools;
+
+#[cfg(feature = "arch-detect")]
+use crate::arch_detect::describe_runtime_arch;
+use crate::{
+    manifest::{guess_runtime_name_from_library, read_manifest, GenericManifest, SYMLINK_ARROW},
+    path_simplifier::PathSimplifier,
+    path_util::normalize_path,
+    Error, RuntimeManifest,
+};
 
 /// The path and parsed data of a runtime manifest.
 ///
@@ -14,6 +23,7 @@ use crate::{manifest::GenericManifest, Error, RuntimeManifest};
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct BaseRuntime {
     manifest_path: PathBuf,
+    normalized_manifest_path: PathBuf,
     manifest: RuntimeManifest,
 }
 
@@ -23,13 +33,13 @@ impl BaseRuntime {
     /// Does not check whether the library is valid, just whether we can load and parse the JSON
     /// according to our schema.
     pub(crate) fn new(manifest_path: &Path) -> Result<Self, Error> {
-        let contents = fs::read_to_string(manifest_path)?;
-        let manifest: RuntimeManifest = serde_json::from_str(&contents)?;
+        let manifest: RuntimeManifest = read_manifest(manifest_path)?;
         if !manifest.is_file_format_version_ok() {
             return Err(Error::ManifestVersionMismatch);
         }
         Ok(BaseRuntime {
             manifest_path: manifest_path.to_owned(),
+            normalized_manifest_path: normalize_path(manifest_path),
             manifest,
         })
     }
@@ -39,24 +49,59 @@ impl BaseRuntime {
         &self.manifest_path
     }
 
+    /// Get the name the runtime declares in its manifest, if any.
+    pub(crate) fn get_declared_runtime_name(&self) -> Option<&str> {
+        self.manifest.runtime.name.as_deref()
+    }
+
+    /// Get the name of the loader negotiation function, if the manifest renames it.
+    pub(crate) fn get_negotiate_function_name(&self) -> Option<&str> {
+        self.manifest
+            .runtime
+            .functions
+            .as_ref()
+            .and_then(|f| f.xr_negotiate_loader_runtime_interface.as_deref())
+    }
+
+    /// Get the runtime version from a nonstandard `version` field in the manifest, if any.
+    ///
+    /// Looks in the runtime object first, then at the top level.
+    pub(crate) fn get_declared_version(&self) -> Option<String> {
+        let version = self
+            .manifest
+            .runtime
+            .version
+            .as_ref()
+            .or_else(|| self.manifest.extra_fields.get("version"))?;
+        match version {
+            serde_json::Value::String(s) if !s.trim().is_empty() => Some(s.trim().to_owned()),
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Get any top-level manifest fields we do not otherwise handle.
+    pub(crate) fn get_extra_manifest_fields(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.manifest.extra_fields
+    }
+
+    /// Get the normalized path to our manifest, for comparisons
+    pub(crate) fn get_normalized_manifest_path(&self) -> &Path {
+        &self.normalized_manifest_path
+    }
+
     /// Get a name for the runtime, preferably the self-declared one.
     ///
     /// Not promised to be unique, though!
     pub(crate) fn get_runtime_name(&self) -> String {
         // Prefer the runtime's advertised name if it has one
-        if let Some(s) = &self.manifest.runtime.name {
-            return s.clone();
+        if let Some(s) = self.get_declared_runtime_name() {
+            return s.to_owned();
         }
 
-        // Heuristics go here, for manifests that lack the name
-        if self.manifest.library_path().contains("MixedRealityRuntime") {
-            return "Windows Mixed Reality".to_owned();
-        }
-        if self.manifest.library_path().contains("monado") {
-            return "Monado".to_owned();
-        }
-        if self.manifest.library_path().contains("VarjoOpenXR") {
-            return "Varjo".to_owned();
+        // Heuristics for manifests that lack the name
+        if let Some(name) = guess_runtime_name_from_library(self.manifest.library_path()) {
+            return name.to_owned();
         }
 
         // Fallback to manifest path or library path
@@ -66,14 +111,93 @@ impl BaseRuntime {
             .to_owned()
     }
 
-    /// Get the fully resolved, canonical path to the library in this manifest/runtime, if possible
-    pub(crate) fn resolve_library_path(&self) -> PathBuf {
-        let notcanon = self
-            .manifest_path
+    /// Get the path to the library in this manifest/runtime, relative to the manifest directory
+    /// if applicable, but without resolving any symlinks.
+    fn unresolved_library_path(&self) -> PathBuf {
+        self.manifest_path
             .parent()
             .expect("files always have parents")
-            .join(self.manifest.library_path());
-        notcanon.canonicalize().unwrap_or(notcanon)
+            .join(self.manifest.library_path())
+    }
+
+    /// Describe the architecture of the library, for display: "unknown" if it can't be inspected.
+    pub(crate) fn describe_arch(&self) -> String {
+        #[cfg(feature = "arch-detect")]
+        return describe_runtime_arch(self);
+        #[cfg(not(feature = "arch-detect"))]
+        return "unknown".to_owned();
+    }
+
+    /// Get the fully resolved, canonical path to the library in this manifest/runtime, if possible
+    pub(crate) fn resolve_library_path(&self) -> PathBuf {
+        normalize_path(&self.unresolved_library_path())
+    }
+
+    /// Copy this manifest to `dest`, making a relative library path absolute so it still resolves.
+    ///
+    /// Errors writing `dest` go through `map_write_error`, so platforms can explain them.
+    pub(crate) fn copy_manifest_to(
+        &self,
+        dest: &Path,
+        map_write_error: impl Fn(io::Error) -> Error,
+    ) -> Result<(), Error> {
+        if !self.library_relative_to_manifest() {
+            fs::copy(&self.manifest_path, dest).map_err(map_write_error)?;
+            return Ok(());
+        }
+        let mut contents: serde_json::Value = read_manifest(&self.manifest_path)?;
+        contents["runtime"]["library_path"] = self.resolve_library_path().to_string_lossy().into();
+        fs::write(dest, serde_json::to_string_pretty(&contents)?).map_err(map_write_error)?;
+        Ok(())
+    }
+
+    /// If the library in this manifest is a symlink, get its final target.
+    pub(crate) fn get_library_symlink_target(&self) -> Option<PathBuf> {
+        if self.manifest.uses_search_path() {
+            return None;
+        }
+        let path = self.unresolved_library_path();
+        if !path.symlink_metadata().ok()?.is_symlink() {
+            return None;
+        }
+        path.canonicalize().ok()
+    }
+
+    /// Does the library in this manifest exist?
+    ///
+    /// Always true for libraries found on the search path, since we cannot check those without loading them.
+    pub(crate) fn library_exists(&self) -> bool {
+        self.manifest.uses_search_path() || self.unresolved_library_path().exists()
+    }
+
+    /// Describe this runtime using the manifest path and library path,
+    /// including the final target if the library is a symlink.
+    pub(crate) fn describe(&self) -> String {
+        let description = self.describe_manifest(&self.manifest_path);
+        let description = match self.get_library_symlink_target() {
+            Some(target) => format!(
+                "{}{}{}",
+                description,
+                SYMLINK_ARROW,
+                PathSimplifier::new().simplify(&target).display()
+            ),
+            None => description,
+        };
+        let mut description = if self.library_exists() {
+            description
+        } else {
+            format!("{} (library missing!)", description)
+        };
+        if let Some(name) = self.get_negotiate_function_name() {
+            description.push_str(&format!("\nNegotiate function: {}", name));
+        }
+        if !self.get_extra_manifest_fields().is_empty() {
+            description.push_str(&format!(
+                "\nExtra manifest fields: {}",
+                self.get_extra_manifest_fields().keys().join(", ")
+            ));
+        }
+        description
     }
 }
 
@@ -0,0 +1,53 @@
+// Copyright 2024, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Fixtures shared by the tests of several modules.
+
+use crate::manifest::{json_subobjects::RuntimeFunctions, RuntimeManifest};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Write a runtime manifest named `file_name` in `dir`, creating the directory if needed.
+///
+/// `negotiate_function` renames `xrNegotiateLoaderRuntimeInterface`, if given.
+pub(crate) fn write_manifest(
+    dir: &Path,
+    file_name: &str,
+    library_path: &str,
+    negotiate_function: Option<&str>,
+) -> PathBuf {
+    fs::create_dir_all(dir).unwrap();
+    let path = dir.join(file_name);
+    RuntimeManifest::write_new(&path, library_path, None).unwrap();
+    if let Some(name) = negotiate_function {
+        let mut manifest = RuntimeManifest::read(&path).unwrap();
+        manifest.runtime.functions = Some(RuntimeFunctions {
+            xr_negotiate_loader_runtime_interface: Some(name.to_owned()),
+        });
+        manifest.write(&path).unwrap();
+    }
+    path
+}
+
+/// The ELF machine type of x86-64.
+#[cfg(feature = "arch-detect")]
+pub(crate) const EM_X86_64: u16 = 62;
+/// The ELF machine type of 64-bit ARM.
+#[cfg(feature = "arch-detect")]
+pub(crate) const EM_AARCH64: u16 = 183;
+
+/// Write just enough of an ELF header for the library's architecture to be detected, creating
+/// the directory if needed.
+#[cfg(feature = "arch-detect")]
+pub(crate) fn write_library(path: &Path, machine: u16) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let mut header = vec![0u8; 64];
+    header[..7].copy_from_slice(b"\x7fELF\x02\x01\x01");
+    header[16] = 3; // ET_DYN
+    header[18..20].copy_from_slice(&machine.to_le_bytes());
+    header[20] = 1; // EV_CURRENT
+    header[52] = 64; // e_ehsize
+    fs::write(path, header).unwrap();
+}
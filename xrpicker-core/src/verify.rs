@@ -0,0 +1,332 @@
+// Copyright 2022-2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Checking that a runtime's library looks like something the loader can actually use.
+
+use crate::{manifest::GenericManifest, runtime::BaseRuntime};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+/// The function the loader negotiates with a runtime through, unless the manifest renames it.
+const DEFAULT_NEGOTIATE_FUNCTION: &str = "xrNegotiateLoaderRuntimeInterface";
+
+/// The outcome of checking a runtime's library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyResult {
+    /// The library exists and provides the negotiate function.
+    LooksOk,
+    /// The library the manifest points to does not exist.
+    LibraryMissing(PathBuf),
+    /// The library does not provide the negotiate function.
+    SymbolMissing { library: PathBuf, symbol: String },
+    /// The library does not provide the negotiate function under the name the manifest gives it
+    /// in its `functions` table. The loader then fails without saying much.
+    RenamedSymbolMissing {
+        library: PathBuf,
+        symbol: String,
+        /// Whether it does provide it under the standard name.
+        exports_default: bool,
+    },
+    /// The library exists but could not be inspected or loaded.
+    LoadFailed { library: PathBuf, reason: String },
+    /// The library could not be checked, for the reason given: e.g. it is found using the
+    /// dynamic library search path, so which one is used depends on the app.
+    Unverifiable(String),
+}
+
+impl VerifyResult {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, VerifyResult::LooksOk)
+    }
+}
+
+impl Display for VerifyResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyResult::LooksOk => write!(f, "looks OK"),
+            VerifyResult::LibraryMissing(library) => {
+                write!(f, "library {} is missing", library.display())
+            }
+            VerifyResult::SymbolMissing { library, symbol } => {
+                write!(
+                    f,
+                    "library {} does not export {}",
+                    library.display(),
+                    symbol
+                )
+            }
+            VerifyResult::RenamedSymbolMissing {
+                library,
+                symbol,
+                exports_default,
+            } => {
+                write!(
+                    f,
+                    "library {} does not export {}, the negotiate function named in the manifest",
+                    library.display(),
+                    symbol
+                )?;
+                if *exports_default {
+                    write!(f, " (it does export {})", DEFAULT_NEGOTIATE_FUNCTION)?;
+                }
+                Ok(())
+            }
+            VerifyResult::LoadFailed { library, reason } => {
+                write!(
+                    f,
+                    "could not load library {}: {}",
+                    library.display(),
+                    reason
+                )
+            }
+            VerifyResult::Unverifiable(reason) => write!(f, "not checked: {}", reason),
+        }
+    }
+}
+
+/// Check the library of a single manifest.
+pub(crate) fn verify_base_runtime(runtime: &BaseRuntime) -> VerifyResult {
+    check_base_runtime(runtime, has_symbol)
+}
+
+/// Check the library of a single manifest by only reading it, even with `dlopen-verify`.
+pub(crate) fn negotiate_check_base_runtime(runtime: &BaseRuntime) -> VerifyResult {
+    check_base_runtime(runtime, has_symbol_static)
+}
+
+type SymbolCheck = fn(&Path, &str) -> Result<Option<bool>, String>;
+
+fn check_base_runtime(runtime: &BaseRuntime, has_symbol: SymbolCheck) -> VerifyResult {
+    if runtime.uses_search_path() {
+        return VerifyResult::Unverifiable("library is found using the search path".to_owned());
+    }
+    let library = runtime.resolve_library_path();
+    if !library.exists() {
+        return VerifyResult::LibraryMissing(library);
+    }
+    let symbol = runtime
+        .get_negotiate_function_name()
+        .unwrap_or(DEFAULT_NEGOTIATE_FUNCTION);
+    match has_symbol(&library, symbol) {
+        Ok(Some(true)) => VerifyResult::LooksOk,
+        Ok(None) => VerifyResult::Unverifiable(
+            "this build can neither inspect nor load libraries".to_owned(),
+        ),
+        Ok(Some(false)) if symbol != DEFAULT_NEGOTIATE_FUNCTION => {
+            VerifyResult::RenamedSymbolMissing {
+                exports_default: has_symbol(&library, DEFAULT_NEGOTIATE_FUNCTION) == Ok(Some(true)),
+                library,
+                symbol: symbol.to_owned(),
+            }
+        }
+        Ok(Some(false)) => VerifyResult::SymbolMissing {
+            library,
+            symbol: symbol.to_owned(),
+        },
+        Err(reason) => VerifyResult::LoadFailed { library, reason },
+    }
+}
+
+/// Combine the results for several manifests of one runtime, reporting the first problem.
+#[cfg(any(windows, test))]
+pub(crate) fn combine_results(results: impl IntoIterator<Item = VerifyResult>) -> VerifyResult {
+    results
+        .into_iter()
+        .find(|r| !r.is_ok())
+        .unwrap_or(VerifyResult::LooksOk)
+}
+
+/// Look for an exported symbol the default way, which is by reading the binary.
+#[cfg(not(feature = "dlopen-verify"))]
+fn has_symbol(library: &Path, symbol: &str) -> Result<Option<bool>, String> {
+    has_symbol_static(library, symbol)
+}
+
+/// Look for an exported symbol by reading the binary, without loading it.
+#[cfg(feature = "arch-detect")]
+fn has_symbol_static(library: &Path, symbol: &str) -> Result<Option<bool>, String> {
+    use object::{
+        read::macho::{FatArch, MachOFatFile32, MachOFatFile64},
+        FileKind, Object, ObjectSymbol,
+    };
+
+    fn exports_symbol(data: &[u8], symbol: &str) -> Result<bool, String> {
+        let file = object::File::parse(data).map_err(|e| e.to_string())?;
+        let exports = file.exports().map_err(|e| e.to_string())?;
+        // Mach-O prefixes C symbols with an underscore
+        let prefixed = format!("_{}", symbol);
+        if exports
+            .iter()
+            .any(|e| e.name() == symbol.as_bytes() || e.name() == prefixed.as_bytes())
+        {
+            return Ok(true);
+        }
+        // Exports only covers some formats, so also check the dynamic symbol table
+        Ok(file
+            .dynamic_symbols()
+            .any(|s| s.is_definition() && s.name_bytes() == Ok(symbol.as_bytes())))
+    }
+
+    let data = std::fs::read(library).map_err(|e| e.to_string())?;
+    let arch_data: Vec<&[u8]> = match FileKind::parse(&*data).map_err(|e| e.to_string())? {
+        FileKind::MachOFat32 => MachOFatFile32::parse(&*data)
+            .map_err(|e| e.to_string())?
+            .arches()
+            .iter()
+            .map(|a| a.data(&*data))
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?,
+        FileKind::MachOFat64 => MachOFatFile64::parse(&*data)
+            .map_err(|e| e.to_string())?
+            .arches()
+            .iter()
+            .map(|a| a.data(&*data))
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?,
+        _ => vec![&*data],
+    };
+    // Every architecture in a fat binary needs it
+    for data in arch_data {
+        if !exports_symbol(data, symbol)? {
+            return Ok(Some(false));
+        }
+    }
+    Ok(Some(true))
+}
+
+/// Without a way to read a library, we can't tell.
+#[cfg(not(feature = "arch-detect"))]
+fn has_symbol_static(_library: &Path, _symbol: &str) -> Result<Option<bool>, String> {
+    Ok(None)
+}
+
+/// Look for an exported symbol by actually loading the library.
+///
+/// This runs the library's initialization code, which is why it needs the `dlopen-verify` feature.
+#[cfg(feature = "dlopen-verify")]
+#[allow(unsafe_code)]
+fn has_symbol(library: &Path, symbol: &str) -> Result<Option<bool>, String> {
+    // SAFETY: Loading a library runs arbitrary initialization code; opting in to this feature
+    // means trusting the runtimes that are installed.
+    let lib = unsafe { libloading::Library::new(library) }.map_err(|e| e.to_string())?;
+    // SAFETY: We only check whether the symbol exists, and never call it.
+    let found = unsafe { lib.get::<unsafe extern "C" fn()>(symbol.as_bytes()) }.is_ok();
+    Ok(Some(found))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_manifest;
+    use std::fs;
+
+    /// Write a manifest for `library_path` into `dir`, along with a placeholder library file.
+    fn make_runtime(dir: &Path, library_path: &str, renamed: Option<&str>) -> BaseRuntime {
+        let manifest_path = write_manifest(dir, "runtime.json", library_path, renamed);
+        fs::write(dir.join("libruntime.so"), b"not really a library").unwrap();
+        BaseRuntime::new(&manifest_path).unwrap()
+    }
+
+    fn exports_nothing(_: &Path, _: &str) -> Result<Option<bool>, String> {
+        Ok(Some(false))
+    }
+
+    fn exports_only_default(_: &Path, symbol: &str) -> Result<Option<bool>, String> {
+        Ok(Some(symbol == DEFAULT_NEGOTIATE_FUNCTION))
+    }
+
+    fn cannot_load(_: &Path, _: &str) -> Result<Option<bool>, String> {
+        Err("bad library".to_owned())
+    }
+
+    fn cannot_check(_: &Path, _: &str) -> Result<Option<bool>, String> {
+        Ok(None)
+    }
+
+    #[test]
+    fn symbol_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = make_runtime(dir.path(), "./libruntime.so", None);
+        assert_eq!(
+            check_base_runtime(&runtime, exports_only_default),
+            VerifyResult::LooksOk
+        );
+        assert!(matches!(
+            check_base_runtime(&runtime, exports_nothing),
+            VerifyResult::SymbolMissing { symbol, .. } if symbol == DEFAULT_NEGOTIATE_FUNCTION
+        ));
+        assert!(matches!(
+            check_base_runtime(&runtime, cannot_load),
+            VerifyResult::LoadFailed { reason, .. } if reason == "bad library"
+        ));
+        assert!(matches!(
+            check_base_runtime(&runtime, cannot_check),
+            VerifyResult::Unverifiable(_)
+        ));
+    }
+
+    #[test]
+    fn renamed_symbol() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = make_runtime(dir.path(), "./libruntime.so", Some("myNegotiate"));
+        let result = check_base_runtime(&runtime, exports_only_default);
+        assert!(matches!(
+            &result,
+            VerifyResult::RenamedSymbolMissing { symbol, exports_default: true, .. }
+                if symbol == "myNegotiate"
+        ));
+        assert!(result.to_string().contains("it does export"));
+        assert!(matches!(
+            check_base_runtime(&runtime, exports_nothing),
+            VerifyResult::RenamedSymbolMissing {
+                exports_default: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn missing_or_unverifiable_library() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = make_runtime(dir.path(), "./libmissing.so", None);
+        assert!(matches!(
+            check_base_runtime(&missing, exports_only_default),
+            VerifyResult::LibraryMissing(_)
+        ));
+        let search_path = make_runtime(dir.path(), "libruntime.so", None);
+        let result = check_base_runtime(&search_path, exports_only_default);
+        assert!(matches!(result, VerifyResult::Unverifiable(_)));
+        assert!(!result.is_ok());
+    }
+
+    #[cfg(feature = "arch-detect")]
+    #[test]
+    fn static_check_rejects_non_binaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = make_runtime(dir.path(), "./libruntime.so", None);
+        assert!(matches!(
+            negotiate_check_base_runtime(&runtime),
+            VerifyResult::LoadFailed { .. }
+        ));
+    }
+
+    #[test]
+    fn combine() {
+        assert_eq!(combine_results(vec![]), VerifyResult::LooksOk);
+        assert_eq!(
+            combine_results(vec![VerifyResult::LooksOk, VerifyResult::LooksOk]),
+            VerifyResult::LooksOk
+        );
+        let missing = VerifyResult::LibraryMissing(PathBuf::from("lib32.dll"));
+        assert_eq!(
+            combine_results(vec![
+                VerifyResult::LooksOk,
+                missing.clone(),
+                VerifyResult::Unverifiable("search path".to_owned()),
+            ]),
+            missing
+        );
+    }
+}
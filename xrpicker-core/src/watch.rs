@@ -0,0 +1,73 @@
+// Copyright 2022-2023, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Noticing when something else changes the active runtime.
+
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use crate::Error;
+
+/// How often to check for changes when there is no native change notification.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Called (from another thread) when the active runtime may have changed.
+pub type WatchCallback = Box<dyn FnMut() + Send>;
+
+/// Keeps watching for changes to the active runtime until dropped.
+pub struct ActiveRuntimeWatcher {
+    inner: WatcherInner,
+}
+
+enum WatcherInner {
+    #[cfg(unix)]
+    /// Only kept so that watching stops when it is dropped.
+    Native {
+        _watcher: notify::RecommendedWatcher,
+    },
+    /// Dropping the sender stops the polling thread.
+    Polling { _stop: mpsc::Sender<()> },
+}
+
+impl ActiveRuntimeWatcher {
+    #[cfg(unix)]
+    pub(crate) fn native(watcher: notify::RecommendedWatcher) -> Self {
+        Self {
+            inner: WatcherInner::Native { _watcher: watcher },
+        }
+    }
+
+    /// Watch by periodically taking a snapshot of the settings, and calling back when it differs.
+    pub(crate) fn polling<S, F>(snapshot: F, mut callback: WatchCallback) -> Result<Self, Error>
+    where
+        S: PartialEq,
+        F: Fn() -> S + Send + 'static,
+    {
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        thread::Builder::new()
+            .name("active runtime watcher".to_owned())
+            .spawn(move || {
+                let mut previous = snapshot();
+                // Both a message and a disconnect mean to stop
+                while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(POLL_INTERVAL) {
+                    let current = snapshot();
+                    if current != previous {
+                        log::debug!("Active runtime settings changed");
+                        callback();
+                        previous = current;
+                    }
+                }
+            })?;
+        Ok(Self {
+            inner: WatcherInner::Polling { _stop: stop_tx },
+        })
+    }
+
+    /// Is this checking periodically, rather than being notified by the system?
+    pub fn is_polling(&self) -> bool {
+        matches!(self.inner, WatcherInner::Polling { .. })
+    }
+}
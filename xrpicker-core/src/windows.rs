@@ -1,35 +1,69 @@
 // Copyright 2022, Collabora, Ltd.
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+#[cfg(feature = "arch-detect")]
+use crate::arch_detect::{get_library_arch, get_runtime_bitness, RuntimeArch, RuntimeBitness};
 use crate::{
-    arch_detect::{get_runtime_bitness, PushUnique, RuntimeBitness},
-    manifest::GenericManifest,
-    platform::{Platform, PlatformRuntime},
+    arch_abi::RuntimeArchAbi,
+    manifest::{GenericManifest, LibraryPathKind},
+    path_util::normalize_path,
+    platform::{
+        check_progress, ActiveRuntimeDiagnosis, ActiveRuntimeOverride, ConfigLocation,
+        ConfigSource, MakeActiveOptions, MakeActivePlan, Platform, PlatformCapabilities,
+        PlatformRuntime,
+    },
+    registry::{Hive, RegistryAccess, RegistryView, WindowsRegistry},
     runtime::BaseRuntime,
-    ActiveState, Error, ManifestError, OPENXR, OPENXR_MAJOR_VERSION,
+    verify::{combine_results, negotiate_check_base_runtime, verify_base_runtime},
+    ActiveRuntimeWatcher, ActiveState, ApiLayerKind, ApiLayerManifest, EnumerationErrorKind, Error,
+    ManifestError, VerifyResult, WatchCallback, ACTIVE_RUNTIME_FILENAME, OPENXR,
+    OPENXR_MAJOR_VERSION, XR_RUNTIME_JSON,
 };
 use itertools::Itertools;
 use special_folder::SpecialFolder;
 use std::{
-    collections::{hash_map::RandomState, HashMap, HashSet},
+    collections::{BTreeMap, HashSet, VecDeque},
+    env,
+    ffi::OsString,
+    fs, io,
+    ops::ControlFlow,
     path::{Path, PathBuf},
-};
-use winreg::{
-    enums::{HKEY_LOCAL_MACHINE, KEY_CREATE_SUB_KEY, KEY_QUERY_VALUE, KEY_READ, KEY_WRITE},
-    RegKey, RegValue,
+    sync::{Arc, OnceLock},
 };
 
 #[derive(Debug, Clone)]
 pub struct WindowsRuntime {
-    base64: Option<BaseRuntime>,
-    base32: Option<BaseRuntime>,
+    /// Where to make it active
+    registry: Arc<dyn RegistryAccess>,
+    /// The manifest for apps of each architecture.
+    ///
+    /// On ARM64 Windows, native and emulated x64 apps share the 64-bit registry view, so a
+    /// runtime may have a manifest for each. The architecture is only read from the library
+    /// when needed to tell manifests in the same view apart: otherwise it is the one the view
+    /// is usually for.
+    bases: BTreeMap<RuntimeArchAbi, BaseRuntime>,
+    /// Lazily computed because it requires reading the libraries
+    arch_description: OnceLock<String>,
+    /// Lazily computed because it may require searching the registry
+    version: OnceLock<Option<String>>,
+    /// Lazily computed because it requires reading the libraries
+    host_compatible: OnceLock<bool>,
 }
 
 const WINMR_JSON_NAME: &str = "MixedRealityRuntime.json";
 
 const AVAILABLE_RUNTIMES: &str = "AvailableRuntimes";
+
+const API_LAYERS_KEY: &str = "ApiLayers";
 const ACTIVE_RUNTIME: &str = "ActiveRuntime";
 
+/// Where Steam records where it is installed, under `HKEY_CURRENT_USER`.
+const STEAM_KEY: &str = "Software\\Valve\\Steam";
+/// Where Windows records its version, under `HKEY_LOCAL_MACHINE`.
+const WINDOWS_VERSION_KEY: &str = "Software\\Microsoft\\Windows NT\\CurrentVersion";
+/// Where installed programs are listed, under `HKEY_LOCAL_MACHINE`.
+const UNINSTALL_KEY: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Uninstall";
+
 #[cfg(target_pointer_width = "64")]
 fn system_dir_64() -> Option<PathBuf> {
     SpecialFolder::System.get()
@@ -60,92 +94,560 @@ fn system_dir_32() -> Option<PathBuf> {
     SpecialFolder::System.get()
 }
 
-fn make_prefix_key() -> PathBuf {
-    Path::new("Software")
-        .join("Khronos")
-        .join(OPENXR)
-        .join(&OPENXR_MAJOR_VERSION.to_string())
+/// Where SteamVR's manifest is in the default Steam library, if Steam is installed.
+fn steamvr_manifest_path(registry: &dyn RegistryAccess) -> Option<PathBuf> {
+    let steam_path = available_views(registry).find_map(|view| {
+        registry
+            .get_string(Hive::CurrentUser, view, STEAM_KEY, "SteamPath")
+            .ok()
+    })?;
+    Some(
+        Path::new(&steam_path)
+            .join("steamapps")
+            .join("common")
+            .join("SteamVR")
+            .join("steamxr_win64.json"),
+    )
 }
 
-#[cfg(target_pointer_width = "64")]
-fn make_prefix_key_flags_64() -> Option<u32> {
-    use winreg::enums::KEY_WOW64_64KEY;
-
-    Some(KEY_WOW64_64KEY)
+fn varjo_manifest_path() -> Option<PathBuf> {
+    SpecialFolder::ProgramFiles.get().map(|p| {
+        p.join("Varjo")
+            .join("varjo-openxr")
+            .join("VarjoOpenXR.json")
+    })
 }
 
-#[cfg(target_pointer_width = "64")]
-fn make_prefix_key_flags_32() -> Option<u32> {
-    use winreg::enums::KEY_WOW64_32KEY;
+/// The version of Windows, which Windows Mixed Reality is part of.
+fn windows_build_version(registry: &dyn RegistryAccess) -> Option<String> {
+    let view = available_views(registry).next()?;
+    let build = registry
+        .get_string(
+            Hive::LocalMachine,
+            view,
+            WINDOWS_VERSION_KEY,
+            "CurrentBuild",
+        )
+        .ok()?;
+    let update = registry
+        .get_dword_values(Hive::LocalMachine, view, WINDOWS_VERSION_KEY)
+        .ok()
+        .and_then(|values| values.into_iter().find(|(name, _)| name == "UBR"))
+        .and_then(|(_, value)| value);
+    Some(match update {
+        Some(update) => format!("Windows build {}.{}", build, update),
+        None => format!("Windows build {}", build),
+    })
+}
 
-    Some(KEY_WOW64_32KEY)
+/// The version of an installed program, from its uninstall entry, found by display name.
+///
+/// Looks in each registry view, since 32-bit programs are listed separately.
+fn installed_program_version(registry: &dyn RegistryAccess, display_name: &str) -> Option<String> {
+    available_views(registry).find_map(|view| {
+        let get = |key: &str, name: &str| {
+            registry
+                .get_string(Hive::LocalMachine, view, key, name)
+                .ok()
+        };
+        registry
+            .get_subkeys(Hive::LocalMachine, view, UNINSTALL_KEY)
+            .ok()?
+            .into_iter()
+            .map(|name| subkey(UNINSTALL_KEY, &name))
+            .find(|key| get(key, "DisplayName").is_some_and(|name| name == display_name))
+            .and_then(|key| get(&key, "DisplayVersion"))
+    })
 }
 
-#[cfg(target_pointer_width = "32")]
-fn make_prefix_key_flags_64() -> Option<u32> {
-    use iswow64::iswow64;
-    use winreg::enums::KEY_WOW64_64KEY;
-    if iswow64() {
-        Some(KEY_WOW64_64KEY)
+/// The version of a runtime we special-case, which does not say in its manifest.
+fn special_case_runtime_version(registry: &dyn RegistryAccess, manifest: &Path) -> Option<String> {
+    if manifest
+        .file_name()
+        .is_some_and(|name| name.eq_ignore_ascii_case(WINMR_JSON_NAME))
+    {
+        windows_build_version(registry)
+    } else if varjo_manifest_path().is_some_and(|varjo| normalize_path(&varjo) == manifest) {
+        installed_program_version(registry, "Varjo Base")
     } else {
         None
     }
 }
 
-#[cfg(target_pointer_width = "32")]
-fn make_prefix_key_flags_32() -> Option<u32> {
-    use winreg::enums::KEY_WOW64_32KEY;
-    Some(KEY_WOW64_32KEY)
+/// The registry key, relative to a hive, that the loader keeps its settings in.
+fn make_prefix_key() -> String {
+    format!("Software\\Khronos\\{}\\{}", OPENXR, OPENXR_MAJOR_VERSION)
+}
+
+/// Join registry key names: unlike file paths, these always use backslashes.
+fn subkey(parent: &str, child: &str) -> String {
+    format!("{}\\{}", parent, child)
+}
+
+/// The registry views that exist here, the 64-bit one first.
+fn available_views(registry: &dyn RegistryAccess) -> impl Iterator<Item = RegistryView> + '_ {
+    RegistryView::ALL
+        .into_iter()
+        .filter(|&view| registry.has_view(view))
 }
 
-fn get_active_runtime_manifest_path(prefix: &Path, reg_flags: Option<u32>) -> Option<PathBuf> {
-    let reg_flags = reg_flags?;
-    let base = RegKey::predef(HKEY_LOCAL_MACHINE)
-        .open_subkey_with_flags(prefix, reg_flags | KEY_READ | KEY_QUERY_VALUE)
+/// Where Windows records the native processor architecture.
+const SYSTEM_ENVIRONMENT_KEY: &str =
+    "SYSTEM\\CurrentControlSet\\Control\\Session Manager\\Environment";
+
+/// The native architecture of this computer, even when this build runs under emulation
+/// (e.g. the x64 build on ARM64 Windows), falling back to this build's architecture.
+fn native_host_arch(registry: &dyn RegistryAccess) -> Option<RuntimeArchAbi> {
+    // This key is shared between the views, so either will do
+    let view = if registry.has_view(RegistryView::Bits64) {
+        RegistryView::Bits64
+    } else {
+        RegistryView::Bits32
+    };
+    registry
+        .get_string(
+            Hive::LocalMachine,
+            view,
+            SYSTEM_ENVIRONMENT_KEY,
+            "PROCESSOR_ARCHITECTURE",
+        )
+        .ok()
+        .and_then(|arch| match arch.as_str() {
+            "AMD64" => Some(RuntimeArchAbi::X86_64),
+            "x86" => Some(RuntimeArchAbi::I686),
+            "ARM64" => Some(RuntimeArchAbi::Aarch64),
+            "ARM" => Some(RuntimeArchAbi::Armv7aVfp),
+            _ => None,
+        })
+        .or_else(RuntimeArchAbi::get_current_arch)
+}
+
+/// Can some app on Windows with the given native architecture load a library for `library`?
+///
+/// ARM64 Windows also runs x64 and x86 apps under emulation. Those share the registry views
+/// with native apps of the same bitness, so a runtime for them is still usable, by them.
+#[cfg(feature = "arch-detect")]
+fn windows_host_can_load(host: RuntimeArchAbi, library: RuntimeArchAbi) -> bool {
+    library.is_compatible_with(host)
+        || matches!(
+            (host, library),
+            (RuntimeArchAbi::Aarch64, RuntimeArchAbi::X86_64)
+                | (RuntimeArchAbi::Aarch64, RuntimeArchAbi::I686)
+        )
+}
+
+/// Treat being denied access to the registry as needing to run as administrator.
+fn convert_registry_write_error(e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::PermissionDenied {
+        Error::ElevationRequired
+    } else {
+        e.into()
+    }
+}
+
+/// Explain being denied access when writing a file.
+fn write_error(path: &Path, e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::PermissionDenied {
+        Error::SetActiveError(format!(
+            "Permission denied writing {}: make sure that directory is writable by you",
+            path.display()
+        ))
+    } else {
+        e.into()
+    }
+}
+
+/// Whether a registry hive holds per-user or system-wide settings.
+fn hive_config_source(hive: Hive) -> ConfigSource {
+    match hive {
+        Hive::CurrentUser => ConfigSource::User,
+        Hive::LocalMachine => ConfigSource::System,
+    }
+}
+
+/// Get the active runtime manifest path set in a single hive, if any.
+fn get_active_runtime_in_hive(
+    registry: &dyn RegistryAccess,
+    hive: Hive,
+    prefix: &str,
+    view: RegistryView,
+) -> Option<PathBuf> {
+    let val = registry
+        .get_string(hive, view, prefix, ACTIVE_RUNTIME)
         .ok()?;
-    let val: String = base.get_value(ACTIVE_RUNTIME).ok()?;
     Some(Path::new(&val).to_path_buf())
 }
 
+/// Get the active runtime manifest path the loader would use, along with the hive it was
+/// found in: a per-user setting takes precedence over the system-wide one.
+fn get_active_runtime_location(
+    registry: &dyn RegistryAccess,
+    prefix: &str,
+    view: RegistryView,
+) -> Option<(Hive, PathBuf)> {
+    if !registry.has_view(view) {
+        return None;
+    }
+    Hive::ALL.into_iter().find_map(|hive| {
+        get_active_runtime_in_hive(registry, hive, prefix, view).map(|path| (hive, path))
+    })
+}
+
+/// Remove the active runtime value in one view of one hive, returning whether there was one.
+fn delete_active_runtime_value(
+    registry: &dyn RegistryAccess,
+    hive: Hive,
+    prefix: &str,
+    view: RegistryView,
+) -> Result<bool, Error> {
+    if get_active_runtime_in_hive(registry, hive, prefix, view).is_none() {
+        return Ok(false);
+    }
+    match registry.delete_value(hive, view, prefix, ACTIVE_RUNTIME) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(convert_registry_write_error(e)),
+    }
+}
+
+/// Which registry view apps of an architecture use.
+fn view_for_arch(arch: RuntimeArchAbi) -> RegistryView {
+    if arch.bit_width() == 64 {
+        RegistryView::Bits64
+    } else {
+        RegistryView::Bits32
+    }
+}
+
+/// The architecture of the apps a registry view is usually used by.
+fn default_arch_for_view(view: RegistryView) -> RuntimeArchAbi {
+    match view {
+        RegistryView::Bits64 => RuntimeArchAbi::X86_64,
+        RegistryView::Bits32 => RuntimeArchAbi::I686,
+    }
+}
+
+/// The architecture of a manifest's library, if it is one that uses the registry view the
+/// manifest was found in, otherwise the one that view is usually for.
+#[cfg(feature = "arch-detect")]
+fn detect_arch(base: &BaseRuntime, view: RegistryView) -> RuntimeArchAbi {
+    match get_library_arch(base) {
+        Ok(RuntimeArch::Specific(abi)) if view_for_arch(abi) == view => abi,
+        _ => default_arch_for_view(view),
+    }
+}
+
+/// Without inspecting the library we can't tell, so assume the one the view is usually for.
+#[cfg(not(feature = "arch-detect"))]
+fn detect_arch(_base: &BaseRuntime, view: RegistryView) -> RuntimeArchAbi {
+    default_arch_for_view(view)
+}
+
+/// Decide which architecture each manifest is for, given the registry view it was found in.
+///
+/// Reading a library can be slow, so this only does so for views with more than one manifest.
+fn assign_arches(
+    bases: Vec<(RegistryView, BaseRuntime)>,
+) -> impl Iterator<Item = (RuntimeArchAbi, BaseRuntime)> {
+    let shared_views: HashSet<RegistryView> =
+        bases.iter().map(|(view, _)| *view).duplicates().collect();
+    bases.into_iter().map(move |(view, base)| {
+        let arch = if shared_views.contains(&view) {
+            detect_arch(&base, view)
+        } else {
+            default_arch_for_view(view)
+        };
+        (arch, base)
+    })
+}
+
+/// Whether some app on this computer can load a runtime's library, assuming so if we can't tell.
+#[cfg(feature = "arch-detect")]
+fn library_usable_here(registry: &dyn RegistryAccess, base: &BaseRuntime) -> bool {
+    match get_library_arch(base) {
+        Ok(RuntimeArch::Specific(abi)) => match native_host_arch(registry) {
+            Some(host) => windows_host_can_load(host, abi),
+            None => true,
+        },
+        _ => true,
+    }
+}
+
+/// Without inspecting the library we can't tell, so assume it is usable.
+#[cfg(not(feature = "arch-detect"))]
+fn library_usable_here(_registry: &dyn RegistryAccess, _base: &BaseRuntime) -> bool {
+    true
+}
+
 impl WindowsRuntime {
-    fn new(path64: Option<&Path>, path32: Option<&Path>) -> Result<Self, Error> {
-        let base64 = path64.map(BaseRuntime::new).transpose()?;
-        let base32 = path32.map(BaseRuntime::new).transpose()?;
-        Ok(WindowsRuntime { base64, base32 })
+    /// Load a runtime from manifests for the given registry views.
+    ///
+    /// If more than one is for the same architecture, only the first is used.
+    fn new(
+        registry: Arc<dyn RegistryAccess>,
+        manifests: &[(RegistryView, &Path)],
+    ) -> Result<Self, Error> {
+        let bases = manifests
+            .iter()
+            .map(|&(view, path)| BaseRuntime::new(path).map(|base| (view, base)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut by_arch = BTreeMap::new();
+        for (arch, base) in assign_arches(bases) {
+            by_arch.entry(arch).or_insert(base);
+        }
+        Ok(Self::from_bases(registry, by_arch))
+    }
+
+    fn from_bases(
+        registry: Arc<dyn RegistryAccess>,
+        bases: BTreeMap<RuntimeArchAbi, BaseRuntime>,
+    ) -> Self {
+        WindowsRuntime {
+            registry,
+            bases,
+            arch_description: OnceLock::new(),
+            version: OnceLock::new(),
+            host_compatible: OnceLock::new(),
+        }
     }
 
+    /// All the manifests, the 64-bit ones first.
     fn runtimes(&self) -> impl Iterator<Item = &BaseRuntime> {
-        self.base64.iter().chain(self.base32.iter())
+        RegistryView::ALL
+            .into_iter()
+            .flat_map(|view| self.runtimes_in_view(view).map(|(_, base)| base))
+    }
+
+    /// The manifests for apps that use a registry view.
+    fn runtimes_in_view(
+        &self,
+        view: RegistryView,
+    ) -> impl Iterator<Item = (RuntimeArchAbi, &BaseRuntime)> {
+        self.bases
+            .iter()
+            .filter(move |(arch, _)| view_for_arch(**arch) == view)
+            .map(|(arch, base)| (*arch, base))
+    }
+
+    /// The manifest to make active in a registry view, if any: if there is more than one,
+    /// the one for this computer's native architecture.
+    fn runtime_for_view(&self, view: RegistryView) -> Option<&BaseRuntime> {
+        let candidates = self.runtimes_in_view(view).collect_vec();
+        if candidates.len() > 1 {
+            let native = native_host_arch(&*self.registry);
+            if let Some((_, base)) = candidates.iter().find(|(arch, _)| Some(*arch) == native) {
+                return Some(base);
+            }
+        }
+        candidates.first().map(|(_, base)| *base)
+    }
+
+    /// The manifest to make active in each registry view, if any.
+    fn runtimes_by_view(&self) -> [(Option<&BaseRuntime>, RegistryView); 2] {
+        RegistryView::ALL.map(|view| (self.runtime_for_view(view), view))
+    }
+
+    /// Is `active_manifest`, set in a registry view, one of this runtime's manifests for it?
+    fn is_active_in_view(&self, active_manifest: Option<&Path>, view: RegistryView) -> bool {
+        active_manifest.is_some_and(|active_manifest| {
+            self.runtimes_in_view(view)
+                .any(|(_, base)| base.get_normalized_manifest_path() == active_manifest)
+        })
     }
 }
 
 impl PlatformRuntime for WindowsRuntime {
-    fn make_active(&self) -> Result<(), Error> {
+    fn make_active_with_options(&self, options: &MakeActiveOptions) -> Result<(), Error> {
+        /// Returns the previous value, if we wrote anything.
         fn try_set_active(
-            reg_path: &Path,
-            runtime: &Option<BaseRuntime>,
-            flags: Option<u32>,
-        ) -> Result<(), Error> {
-            if let (Some(runtime), Some(flags)) = (runtime, flags) {
-                let (key, _disp) = RegKey::predef(HKEY_LOCAL_MACHINE).create_subkey_with_flags(
-                    reg_path,
-                    flags | KEY_WRITE | KEY_READ | KEY_QUERY_VALUE | KEY_CREATE_SUB_KEY,
-                )?;
-                key.set_value(ACTIVE_RUNTIME, &runtime.get_manifest_path().as_os_str())?;
+            registry: &dyn RegistryAccess,
+            hive: Hive,
+            reg_path: &str,
+            runtime: Option<&BaseRuntime>,
+            view: RegistryView,
+        ) -> Result<Option<Option<String>>, Error> {
+            match runtime {
+                Some(runtime) if registry.has_view(view) => {
+                    let previous = registry
+                        .get_string(hive, view, reg_path, ACTIVE_RUNTIME)
+                        .ok();
+                    registry
+                        .set_string(
+                            hive,
+                            view,
+                            reg_path,
+                            ACTIVE_RUNTIME,
+                            runtime.get_manifest_path().as_os_str(),
+                        )
+                        .map_err(convert_registry_write_error)?;
+                    Ok(Some(previous))
+                }
+                _ => Ok(None),
             }
-            Ok(())
         }
+        let registry = &*self.registry;
+        let hive = if options.per_user {
+            Hive::CurrentUser
+        } else {
+            Hive::LocalMachine
+        };
         let key = make_prefix_key();
-        try_set_active(&key, &self.base64, make_prefix_key_flags_64())?;
-        try_set_active(&key, &self.base32, make_prefix_key_flags_32())?;
+        let [(base64, _), (base32, _)] = self.runtimes_by_view();
+        let written_64 = try_set_active(registry, hive, &key, base64, RegistryView::Bits64)?;
+        if let Err(e) = try_set_active(registry, hive, &key, base32, RegistryView::Bits32) {
+            // Don't leave things half-switched: put the 64-bit value back the way it was.
+            if let Some(previous) = written_64 {
+                let view = RegistryView::Bits64;
+                let restored = match previous {
+                    Some(value) => {
+                        registry.set_string(hive, view, &key, ACTIVE_RUNTIME, value.as_ref())
+                    }
+                    None => registry.delete_value(hive, view, &key, ACTIVE_RUNTIME),
+                };
+                if let Err(restore_err) = restored {
+                    log::warn!(
+                        "Got an error trying to restore the previous 64-bit active runtime: {}",
+                        restore_err
+                    );
+                }
+            }
+            return Err(e);
+        }
+        if !options.per_user {
+            // A per-user setting would take precedence over what we just wrote, so remove it.
+            for (runtime, view) in self.runtimes_by_view() {
+                if runtime.is_some() && registry.has_view(view) {
+                    if let Err(e) =
+                        delete_active_runtime_value(registry, Hive::CurrentUser, &key, view)
+                    {
+                        log::warn!(
+                            "Got an error trying to remove the per-user active runtime: {}",
+                            e
+                        );
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
+    fn preview_make_active_with_options(
+        &self,
+        options: &MakeActiveOptions,
+    ) -> Result<MakeActivePlan, Error> {
+        // Must match what make_active_with_options does
+        let registry = &*self.registry;
+        let hive = if options.per_user {
+            Hive::CurrentUser
+        } else {
+            Hive::LocalMachine
+        };
+        let key = make_prefix_key();
+        let views = self.runtimes_by_view();
+        let mut steps = vec![];
+        for (runtime, view) in views {
+            if let Some(runtime) = runtime.filter(|_| registry.has_view(view)) {
+                steps.push(format!(
+                    "Set {}\\{}\\{} = {} ({} registry view)",
+                    hive.name(),
+                    key,
+                    ACTIVE_RUNTIME,
+                    runtime.get_manifest_path().display(),
+                    view.description()
+                ));
+            }
+        }
+        if !options.per_user {
+            for (runtime, view) in views {
+                if runtime.is_some()
+                    && registry.has_view(view)
+                    && get_active_runtime_in_hive(registry, Hive::CurrentUser, &key, view).is_some()
+                {
+                    steps.push(format!(
+                        "Remove {}\\{}\\{}, which would take precedence ({} registry view)",
+                        Hive::CurrentUser.name(),
+                        key,
+                        ACTIVE_RUNTIME,
+                        view.description()
+                    ));
+                }
+            }
+        }
+        Ok(MakeActivePlan { steps })
+    }
+
+    fn get_runtime_version(&self) -> Option<String> {
+        self.version
+            .get_or_init(|| {
+                self.runtimes()
+                    .find_map(BaseRuntime::get_declared_version)
+                    .or_else(|| {
+                        self.runtimes().find_map(|r| {
+                            special_case_runtime_version(
+                                &*self.registry,
+                                r.get_normalized_manifest_path(),
+                            )
+                        })
+                    })
+            })
+            .clone()
+    }
+
+    fn get_negotiate_function_name(&self) -> Option<String> {
+        self.runtimes()
+            .find_map(BaseRuntime::get_negotiate_function_name)
+            .map(str::to_owned)
+    }
+
+    fn get_arch_description(&self) -> String {
+        self.arch_description
+            .get_or_init(|| {
+                self.runtimes()
+                    .map(BaseRuntime::describe_arch)
+                    .unique()
+                    .join(", ")
+            })
+            .clone()
+    }
+
+    fn is_arch_description_resolved(&self) -> bool {
+        self.arch_description.get().is_some() && self.host_compatible.get().is_some()
+    }
+
+    fn is_compatible_with_host(&self) -> bool {
+        // Usable if any app here can load one of its libraries
+        *self.host_compatible.get_or_init(|| {
+            self.runtimes()
+                .any(|base| library_usable_here(&*self.registry, base))
+        })
+    }
+
+    fn export_active_runtime_to(&self, dir: &Path) -> Result<PathBuf, Error> {
+        // Symlinks need special privileges on Windows, so always copy.
+        // A file can only name one manifest: prefer the 64-bit one, as XR_RUNTIME_JSON does.
+        let base = self
+            .runtime_for_view(RegistryView::Bits64)
+            .or_else(|| self.runtimes().next())
+            .ok_or(Error::EnumerationError(
+                EnumerationErrorKind::NoManifestPaths,
+            ))?;
+        fs::create_dir_all(dir).map_err(|e| write_error(dir, e))?;
+        let path = dir.join(ACTIVE_RUNTIME_FILENAME);
+        base.copy_manifest_to(&path, |e| write_error(&path, e))?;
+        Ok(path)
+    }
+
     fn get_runtime_name(&self) -> String {
+        // Prefer a declared name from either manifest over heuristics on the first one
         self.runtimes()
-            .map(|r| r.get_runtime_name())
-            .next()
-            .expect("At least one of the runtimes will be Some")
+            .find_map(|r| r.get_declared_runtime_name())
+            .map(|s| s.to_owned())
+            .unwrap_or_else(|| {
+                self.runtimes()
+                    .map(|r| r.get_runtime_name())
+                    .next()
+                    .expect("At least one of the runtimes will be Some")
+            })
     }
 
     fn get_manifests(&self) -> Vec<&Path> {
@@ -156,70 +658,118 @@ impl PlatformRuntime for WindowsRuntime {
         self.runtimes().map(|r| r.resolve_library_path()).collect()
     }
 
-    fn describe(&self) -> String {
+    fn verify(&self) -> VerifyResult {
+        combine_results(self.runtimes().map(verify_base_runtime))
+    }
+
+    fn open_negotiate_check(&self) -> VerifyResult {
+        combine_results(self.runtimes().map(negotiate_check_base_runtime))
+    }
+
+    fn library_path_kind(&self) -> LibraryPathKind {
         self.runtimes()
-            .map(|r| r.describe_manifest(r.get_manifest_path()))
-            .join("\n")
+            .map(|r| r.classify_library_path())
+            .next()
+            .expect("At least one of the runtimes will be Some")
+    }
+
+    fn describe(&self) -> String {
+        self.runtimes().map(|r| r.describe()).join("\n")
     }
 }
 
 /// Little helper for accumulating runtimes and coalescing their different bitnesses.
-#[derive(Default)]
 struct RuntimeCollection {
+    registry: Arc<dyn RegistryAccess>,
     runtimes: Vec<WindowsRuntime>,
     used_manifests: HashSet<PathBuf>,
+    /// Manifests that could not be loaded, and have been reported as such.
+    failed_manifests: HashSet<PathBuf>,
 }
 
 impl RuntimeCollection {
-    fn try_add(&mut self, path64: Option<&Path>, path32: Option<&Path>) -> Result<(), Error> {
-        let mut has_path = false;
-        if let Some(p) = path64 {
-            has_path = true;
-            if self.used_manifests.contains(p) {
-                return Ok(());
-            }
+    fn new(registry: Arc<dyn RegistryAccess>) -> Self {
+        Self {
+            registry,
+            runtimes: vec![],
+            used_manifests: HashSet::new(),
+            failed_manifests: HashSet::new(),
         }
-        if let Some(p) = path32 {
-            has_path = true;
-            if self.used_manifests.contains(p) {
-                return Ok(());
+    }
+
+    /// Add a runtime with the given manifests for each registry view, normally all from one
+    /// directory, skipping any already used.
+    ///
+    /// Returns an error for each manifest that could not be loaded, unless it was already
+    /// reported, so each broken manifest is reported exactly once: the working ones are still
+    /// added. Manifests for an architecture already taken become another runtime.
+    fn try_add(&mut self, manifests: &[(RegistryView, &Path)]) -> Vec<ManifestError> {
+        let mut errors = vec![];
+        let mut bases = vec![];
+        // The same manifest may be listed for a view more than once, e.g. in both hives
+        let mut seen = HashSet::new();
+        for &(view, path) in manifests {
+            let normalized = normalize_path(path);
+            if self.used_manifests.contains(&normalized)
+                || self.failed_manifests.contains(&normalized)
+                || !seen.insert((view, normalized.clone()))
+            {
+                log::debug!("Skipping already-seen manifest {}", path.display());
+                continue;
+            }
+            match BaseRuntime::new(path) {
+                Ok(base) => bases.push((view, base)),
+                Err(e) => {
+                    self.failed_manifests.insert(normalized);
+                    errors.push(ManifestError(path.to_owned(), e));
+                }
             }
         }
-        if !has_path {
-            return Err(Error::EnumerationError(
-                "Tried to add a runtime with no manifest paths!".to_string(),
-            ));
+        let mut runtimes: Vec<BTreeMap<RuntimeArchAbi, BaseRuntime>> = vec![];
+        for (arch, base) in assign_arches(bases) {
+            self.used_manifests
+                .insert(normalize_path(base.get_manifest_path()));
+            match runtimes.iter_mut().find(|r| !r.contains_key(&arch)) {
+                Some(runtime) => {
+                    runtime.insert(arch, base);
+                }
+                None => runtimes.push(BTreeMap::from([(arch, base)])),
+            }
         }
-        let runtime = WindowsRuntime::new(path64, path32)?;
-        self.runtimes.push(runtime);
-        if let Some(p) = path64 {
-            self.used_manifests.insert(p.to_owned());
+        self.runtimes.extend(
+            runtimes
+                .into_iter()
+                .map(|bases| WindowsRuntime::from_bases(self.registry.clone(), bases)),
+        );
+        errors
+    }
+
+    fn try_add_varjo(&mut self) -> Vec<ManifestError> {
+        if !cfg!(target_pointer_width = "64") {
+            return vec![];
         }
-        if let Some(p) = path32 {
-            self.used_manifests.insert(p.to_owned());
+        let path = varjo_manifest_path();
+        let path = path.as_deref().filter(|&p| p.exists());
+        match path {
+            Some(path) => self.try_add(&[(RegistryView::Bits64, path)]),
+            None => vec![],
         }
-        Ok(())
     }
 
-    fn try_add_varjo(&mut self) -> Result<(), ManifestError> {
+    fn try_add_steamvr(&mut self) -> Vec<ManifestError> {
+        // SteamVR normally registers itself, but not always (e.g. after moving the Steam library)
         if !cfg!(target_pointer_width = "64") {
-            return Ok(());
+            return vec![];
         }
-        let path = SpecialFolder::ProgramFiles.get().map(|p| {
-            p.join("Varjo")
-                .join("varjo-openxr")
-                .join("VarjoOpenXR.json")
-        });
+        let path = steamvr_manifest_path(&*self.registry);
         let path = path.as_deref().filter(|&p| p.exists());
-        if let Some(path) = path {
-            self.try_add(Some(path), None)
-                .map_err(|e| ManifestError(path.to_owned(), e))
-        } else {
-            Ok(())
+        match path {
+            Some(path) => self.try_add(&[(RegistryView::Bits64, path)]),
+            None => vec![],
         }
     }
 
-    fn try_add_winmr(&mut self) -> Result<(), ManifestError> {
+    fn try_add_winmr(&mut self) -> Vec<ManifestError> {
         // Manually add winmr because it will be some revisions of windows before they can put it in AvailableRuntimes
         let (winmr64, winmr32) = (
             system_dir_64().map(|d| d.join(WINMR_JSON_NAME)),
@@ -227,102 +777,389 @@ impl RuntimeCollection {
         );
 
         // Only use paths that exist
-        let (winmr64, winmr32) = (
-            winmr64.as_deref().filter(|&p| p.exists()),
-            winmr32.as_deref().filter(|&p| p.exists()),
-        );
+        let manifests = [
+            (RegistryView::Bits64, winmr64.as_deref()),
+            (RegistryView::Bits32, winmr32.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(view, path)| Some((view, path.filter(|&p| p.exists())?)))
+        .collect_vec();
 
-        if winmr64.is_some() || winmr32.is_some() {
-            self.try_add(winmr64, winmr32).map_err(|e| {
-                ManifestError(winmr64.unwrap_or_else(|| winmr32.unwrap()).to_owned(), e)
-            })
-        } else {
-            Ok(())
-        }
-    }
-}
-
-impl From<RuntimeCollection> for Vec<WindowsRuntime> {
-    fn from(val: RuntimeCollection) -> Self {
-        val.runtimes
+        self.try_add(&manifests)
     }
 }
 
 pub struct WindowsActiveRuntimeData {
     active_64: Option<PathBuf>,
     active_32: Option<PathBuf>,
+    /// The hives the above were found in
+    hive_64: Option<Hive>,
+    hive_32: Option<Hive>,
+    /// Normalized versions of the above, for comparisons
+    normalized_64: Option<PathBuf>,
+    normalized_32: Option<PathBuf>,
+    /// The normalized path of the manifest named by `XR_RUNTIME_JSON`, if set to an existing file.
+    /// The loader uses it for every architecture, in preference to the registry.
+    env_override: Option<PathBuf>,
 }
 
-fn check_active(active_runtime_manifest: &Option<PathBuf>, runtime: &Option<BaseRuntime>) -> bool {
-    match (active_runtime_manifest.as_deref(), runtime) {
-        (Some(active_manifest), Some(r)) => r.get_manifest_path() == active_manifest,
-        _ => false,
+impl WindowsActiveRuntimeData {
+    fn new(registry: &dyn RegistryAccess) -> Self {
+        Self::read(registry, env::var_os(XR_RUNTIME_JSON))
     }
-}
 
-impl WindowsActiveRuntimeData {
-    fn new() -> Self {
+    /// Read the active runtimes from the registry, given the value of `XR_RUNTIME_JSON`.
+    fn read(registry: &dyn RegistryAccess, xr_runtime_json: Option<OsString>) -> Self {
         let reg_prefix = make_prefix_key();
-        let active_64 = get_active_runtime_manifest_path(&reg_prefix, make_prefix_key_flags_64());
-        let active_32 = get_active_runtime_manifest_path(&reg_prefix, make_prefix_key_flags_32());
+        let (hive_64, active_64) =
+            get_active_runtime_location(registry, &reg_prefix, RegistryView::Bits64).unzip();
+        let (hive_32, active_32) =
+            get_active_runtime_location(registry, &reg_prefix, RegistryView::Bits32).unzip();
+        let env_override = xr_runtime_json
+            .filter(|v| !v.is_empty())
+            .map(|v| normalize_path(Path::new(&v)))
+            .filter(|p| p.is_file());
         Self {
+            normalized_64: active_64.as_deref().map(normalize_path),
+            normalized_32: active_32.as_deref().map(normalize_path),
             active_64,
             active_32,
+            hive_64,
+            hive_32,
+            env_override,
         }
     }
 
+    fn get_override(&self) -> Option<ActiveRuntimeOverride> {
+        let effective = self.env_override.as_ref()?;
+        if self.normalized_64.as_ref() == Some(effective)
+            || self.normalized_32.as_ref() == Some(effective)
+        {
+            return None;
+        }
+        Some(ActiveRuntimeOverride {
+            effective: effective.clone(),
+            configured: self
+                .active_64
+                .iter()
+                .chain(self.active_32.iter())
+                .cloned()
+                .collect(),
+        })
+    }
+
+    fn is_env_override(&self, runtime: &WindowsRuntime) -> bool {
+        self.env_override.as_deref().is_some_and(|env_override| {
+            runtime
+                .runtimes()
+                .any(|r| r.get_normalized_manifest_path() == env_override)
+        })
+    }
+
+    fn is_active_manifest(&self, manifest: &Path) -> bool {
+        let manifest = Some(normalize_path(manifest));
+        self.normalized_64 == manifest || self.normalized_32 == manifest
+    }
+
     fn check_runtime(&self, runtime: &WindowsRuntime) -> ActiveState {
-        let active_64 = check_active(&self.active_64, &runtime.base64);
-        let active_32 = check_active(&self.active_32, &runtime.base32);
+        let active_64 =
+            runtime.is_active_in_view(self.normalized_64.as_deref(), RegistryView::Bits64);
+        let active_32 =
+            runtime.is_active_in_view(self.normalized_32.as_deref(), RegistryView::Bits32);
+
+        let configured = ActiveState::from_active_64_and_32(active_64, active_32);
+        if self.env_override.is_none() {
+            return configured;
+        }
+        // The loader uses XR_RUNTIME_JSON in preference to the registry
+        match (self.is_env_override(runtime), configured.is_active()) {
+            (true, true) => ActiveState::ActiveIndependentRuntime,
+            (true, false) => ActiveState::ActiveFromEnvironment,
+            (false, true) => ActiveState::OverriddenByEnvironment,
+            (false, false) => ActiveState::NotActive,
+        }
+    }
+
+    fn describe_reason(&self, runtime: &WindowsRuntime) -> Option<String> {
+        let configured = self.describe_configured_reason(runtime);
+        let environment = ConfigSource::Environment;
+        match (self.is_env_override(runtime), configured) {
+            (true, None) => Some(format!("Selected by {}", environment)),
+            (true, Some(configured)) => Some(format!(
+                "Selected by {}, and also by the {}",
+                environment, configured
+            )),
+            (false, Some(configured)) if self.env_override.is_some() => Some(format!(
+                "Selected by the {}, but {} takes precedence",
+                configured, environment
+            )),
+            (false, Some(configured)) => Some(format!("Selected by the {}", configured)),
+            (false, None) => None,
+        }
+    }
 
-        ActiveState::from_active_64_and_32(active_64, active_32)
+    /// Which registry values make this runtime active, if any.
+    fn describe_configured_reason(&self, runtime: &WindowsRuntime) -> Option<String> {
+        let values = [
+            (&self.normalized_64, self.hive_64, RegistryView::Bits64),
+            (&self.normalized_32, self.hive_32, RegistryView::Bits32),
+        ]
+        .into_iter()
+        .filter(|(active, _, view)| runtime.is_active_in_view(active.as_deref(), *view))
+        .filter_map(|(_, hive, view)| {
+            Some(format!(
+                "{} {} {} value",
+                hive?.name(),
+                view.description(),
+                ACTIVE_RUNTIME
+            ))
+        })
+        .collect_vec();
+        if values.is_empty() {
+            return None;
+        }
+        Some(values.join(" and the "))
     }
 }
 
-pub struct WindowsPlatform;
+/// The `ActiveRuntime` values at some point in time, for every hive and registry view,
+/// so they can be put back later with `WindowsPlatform::restore_active_runtime`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveRuntimeSnapshot {
+    /// Hive, registry view, and the value (`None` if it was not set)
+    values: Vec<(Hive, RegistryView, Option<String>)>,
+}
+
+pub struct WindowsPlatform {
+    registry: Arc<dyn RegistryAccess>,
+}
 
 impl WindowsPlatform {
     fn new() -> Self {
-        Self
+        Self::with_registry(Arc::new(WindowsRegistry))
+    }
+
+    fn with_registry(registry: Arc<dyn RegistryAccess>) -> Self {
+        Self { registry }
+    }
+
+    /// The runtime the loader would use, given the active runtime data.
+    fn active_runtime_from(
+        &self,
+        data: WindowsActiveRuntimeData,
+    ) -> Result<Option<WindowsRuntime>, Error> {
+        // The loader uses XR_RUNTIME_JSON in preference to the registry
+        if let Some(ActiveRuntimeOverride { effective, .. }) = data.get_override() {
+            return self.load_extra_runtime(&effective).map(Some);
+        }
+        // The two values may name unrelated runtimes: like when enumerating, only treat them as
+        // one runtime if their manifests are in the same directory.
+        let (active_64, active_32) = match (data.active_64.as_deref(), data.active_32.as_deref()) {
+            (Some(path64), Some(path32)) if path64.parent() != path32.parent() => {
+                (Some(path64), None)
+            }
+            (None, None) => return Ok(None),
+            paths => paths,
+        };
+        let manifests = [
+            (RegistryView::Bits64, active_64),
+            (RegistryView::Bits32, active_32),
+        ]
+        .into_iter()
+        .filter_map(|(view, path)| Some((view, path?)))
+        .collect_vec();
+        WindowsRuntime::new(self.registry.clone(), &manifests).map(Some)
+    }
+
+    /// Record the current active runtime settings, in both hives and both registry views.
+    pub fn snapshot_active_runtime(&self) -> ActiveRuntimeSnapshot {
+        let registry = &*self.registry;
+        let prefix = make_prefix_key();
+        let mut values = vec![];
+        for view in available_views(registry) {
+            for hive in Hive::ALL {
+                let value = registry
+                    .get_string(hive, view, &prefix, ACTIVE_RUNTIME)
+                    .ok();
+                values.push((hive, view, value));
+            }
+        }
+        ActiveRuntimeSnapshot { values }
+    }
+
+    /// Remove `AvailableRuntimes` registry values whose manifest file no longer exists,
+    /// in both hives and both registry views, returning the manifest paths removed.
+    ///
+    /// Disabled entries are removed too. Changing the system-wide hive needs elevation.
+    pub fn cleanup_stale_available_runtimes(&self) -> Result<Vec<PathBuf>, Error> {
+        let registry = &*self.registry;
+        let key_path = subkey(&make_prefix_key(), AVAILABLE_RUNTIMES);
+        let mut removed = vec![];
+        for view in available_views(registry) {
+            for hive in Hive::ALL {
+                let Ok(values) = registry.get_dword_values(hive, view, &key_path) else {
+                    continue;
+                };
+                let stale = values
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .filter(|name| !Path::new(name).exists());
+                for name in stale {
+                    match registry.delete_value(hive, view, &key_path, &name) {
+                        Ok(()) => removed.push_unique(PathBuf::from(name)),
+                        // Already gone, e.g. the same value seen through the other view
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(convert_registry_write_error(e)),
+                    }
+                }
+            }
+        }
+        Ok(removed)
     }
-}
 
-fn maybe_runtime(regkey: &RegKey, kv: (String, RegValue)) -> Option<PathBuf> {
-    let (val_name, _) = kv;
-    let v: u32 = regkey.get_value(&val_name).ok()?;
-    if v == 0 {
-        return Some(Path::new(&val_name).to_owned());
+    /// Put the active runtime settings back the way they were in `snapshot`.
+    ///
+    /// Values that were not set when the snapshot was taken are deleted, rather than set empty.
+    pub fn restore_active_runtime(&self, snapshot: &ActiveRuntimeSnapshot) -> Result<(), Error> {
+        let registry = &*self.registry;
+        let prefix = make_prefix_key();
+        for &(hive, view, ref value) in &snapshot.values {
+            let current = registry
+                .get_string(hive, view, &prefix, ACTIVE_RUNTIME)
+                .ok();
+            if &current == value {
+                // Unchanged, so avoid needing write access
+                continue;
+            }
+            match value {
+                Some(value) => {
+                    registry
+                        .set_string(hive, view, &prefix, ACTIVE_RUNTIME, value.as_ref())
+                        .map_err(convert_registry_write_error)?;
+                }
+                None => {
+                    delete_active_runtime_value(registry, hive, &prefix, view)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Would making a runtime active fail because this process is not running as administrator?
+    ///
+    /// Checks without changing anything.
+    pub fn needs_elevation_to_set_active(&self) -> bool {
+        let Some(view) = available_views(&*self.registry).next() else {
+            return false;
+        };
+        !self
+            .registry
+            .can_write(Hive::LocalMachine, view, &make_prefix_key())
     }
-    None
 }
 
-fn enumerate_reg_runtimes(base_key: &Path, reg_flags: u32) -> Vec<PathBuf> {
-    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-    hklm.open_subkey_with_flags(
-        base_key.to_str().unwrap(),
-        reg_flags | KEY_READ | KEY_QUERY_VALUE,
-    )
-    .map(|avail| {
-        let manifest_files = avail.enum_values().filter_map(|x| {
-            let x = x.ok()?;
-            maybe_runtime(&avail, x)
-        });
-        manifest_files.collect()
-    })
-    .unwrap_or_default()
+/// Enumerate the enabled runtime manifests registered as available, skipping any whose file is
+/// gone: those are usually left behind by an uninstaller, and would be an error on every launch.
+///
+/// Also returns an error for each registry key that exists but could not be read.
+fn enumerate_available_runtimes(
+    registry: &dyn RegistryAccess,
+    view: RegistryView,
+) -> (Vec<PathBuf>, Vec<ManifestError>) {
+    let (manifests, errors) = enumerate_reg_runtimes(
+        registry,
+        &subkey(&make_prefix_key(), AVAILABLE_RUNTIMES),
+        view,
+    );
+    let manifests = manifests
+        .into_iter()
+        .filter(|p| {
+            let exists = p.exists();
+            if !exists {
+                log::info!(
+                    "Skipping registered runtime with missing manifest {}",
+                    p.display()
+                );
+            }
+            exists
+        })
+        .collect();
+    (manifests, errors)
+}
+
+/// Enumerate the enabled manifests registered under a key, in both the per-user and system-wide hives.
+///
+/// A key that does not exist just has none, but one that can't be read is an error.
+/// Each value is named for a manifest, and is a DWORD that is 0 when it is enabled.
+fn enumerate_reg_runtimes(
+    registry: &dyn RegistryAccess,
+    base_key: &str,
+    view: RegistryView,
+) -> (Vec<PathBuf>, Vec<ManifestError>) {
+    let mut manifests = vec![];
+    let mut errors = vec![];
+    for hive in Hive::ALL {
+        match registry.get_dword_values(hive, view, base_key) {
+            Ok(values) => manifests.extend(
+                values
+                    .into_iter()
+                    .filter(|(_, value)| *value == Some(0))
+                    .map(|(name, _)| PathBuf::from(name)),
+            ),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                let key_name = subkey(hive.name(), base_key);
+                log::warn!("Could not read registry key {}: {}", key_name, e);
+                errors.push(ManifestError(
+                    PathBuf::new(),
+                    Error::EnumerationError(EnumerationErrorKind::RegistryUnreadable(key_name)),
+                ));
+            }
+        }
+    }
+    (manifests, errors)
 }
 
 /// Returns any non-fatal errors
 fn manually_add_runtimes(collection: &mut RuntimeCollection) -> Vec<ManifestError> {
-    let mut nonfatal_errors = vec![];
+    let mut nonfatal_errors = collection.try_add_varjo();
+    nonfatal_errors.extend(collection.try_add_winmr());
+    nonfatal_errors.extend(collection.try_add_steamvr());
+    nonfatal_errors
+}
 
-    if let Err(e) = collection.try_add_varjo() {
-        nonfatal_errors.push(e);
-    }
-    if let Err(e) = collection.try_add_winmr() {
-        nonfatal_errors.push(e);
+trait PushUnique<T> {
+    fn push_unique(&mut self, val: T);
+}
+
+impl<T> PushUnique<T> for Vec<T>
+where
+    T: Eq,
+{
+    fn push_unique(&mut self, val: T) {
+        let contains = self.contains(&val);
+        if !contains {
+            self.push(val);
+        }
     }
-    nonfatal_errors
+}
+
+/// Which registry views a manually added manifest belongs in: 32-bit, 64-bit, or both.
+#[cfg(feature = "arch-detect")]
+fn extra_manifest_bitness(path: &Path) -> Result<(bool, bool), ManifestError> {
+    Ok(match get_runtime_bitness(path)? {
+        RuntimeBitness::Universal => (true, true),
+        RuntimeBitness::BitWidth32 => (true, false),
+        RuntimeBitness::BitWidth64 => (false, true),
+    })
+}
+
+/// Without inspecting the library we can't tell, so assume it matches this build.
+#[cfg(not(feature = "arch-detect"))]
+fn extra_manifest_bitness(path: &Path) -> Result<(bool, bool), ManifestError> {
+    BaseRuntime::new(path).map_err(|e| ManifestError(path.to_owned(), e))?;
+    let is_64 = cfg!(target_pointer_width = "64");
+    Ok((!is_64, is_64))
 }
 
 fn process_extra_manifests(
@@ -332,98 +1169,307 @@ fn process_extra_manifests(
     let mut paths64 = vec![];
     let mut nonfatal_errors = vec![];
     for path in extra_paths {
-        match get_runtime_bitness(&path) {
-            Ok(bitness) => match bitness {
-                RuntimeBitness::Universal => {
+        match extra_manifest_bitness(&path) {
+            Ok((is_32, is_64)) => {
+                if is_32 {
                     paths32.push(path.clone());
+                }
+                if is_64 {
                     paths64.push(path);
                 }
-                RuntimeBitness::BitWidth32 => paths32.push(path),
-                RuntimeBitness::BitWidth64 => paths64.push(path),
-            },
+            }
             Err(e) => nonfatal_errors.push(e),
         }
     }
     (paths32, paths64, nonfatal_errors)
 }
 
+/// Group the manifests found in each registry view by directory, to try adding each group
+/// together as one runtime, in order of first appearance.
+///
+/// A runtime's manifests for different architectures are normally side by side.
+/// This does not touch the registry or the filesystem.
+fn group_manifests(
+    manifests64: Vec<PathBuf>,
+    manifests32: Vec<PathBuf>,
+) -> Vec<Vec<(RegistryView, PathBuf)>> {
+    let mut groups: Vec<Vec<(RegistryView, PathBuf)>> = vec![];
+    let manifests = manifests64
+        .into_iter()
+        .map(|path| (RegistryView::Bits64, path))
+        .chain(
+            manifests32
+                .into_iter()
+                .map(|path| (RegistryView::Bits32, path)),
+        );
+    for (view, path) in manifests {
+        let group = groups
+            .iter_mut()
+            .find(|group| group[0].1.parent() == path.parent());
+        match group {
+            Some(group) => group.push((view, path)),
+            None => groups.push(vec![(view, path)]),
+        }
+    }
+    groups
+}
+
+/// Loads runtimes one directory of manifests at a time, skipping duplicates.
+///
+/// Reading the registry is quick, so the manifests are listed up front, and only loaded as needed.
+struct RuntimeScan {
+    collection: RuntimeCollection,
+    active_data: WindowsActiveRuntimeData,
+    /// The groups of manifests still to try adding together.
+    groups: std::vec::IntoIter<Vec<(RegistryView, PathBuf)>>,
+    /// Whether the runtimes we might not see otherwise have been added yet.
+    added_manually: bool,
+    /// Runtimes and errors found, but not yet returned.
+    found: VecDeque<Result<WindowsRuntime, ManifestError>>,
+}
+
+impl RuntimeScan {
+    fn new(
+        registry: Arc<dyn RegistryAccess>,
+        extra_paths: Box<dyn '_ + Iterator<Item = PathBuf>>,
+    ) -> Self {
+        let [(mut manifests64, mut unreadable_keys), (mut manifests32, errors32)] =
+            RegistryView::ALL.map(|view| {
+                if registry.has_view(view) {
+                    enumerate_available_runtimes(&*registry, view)
+                } else {
+                    Default::default()
+                }
+            });
+        unreadable_keys.extend(errors32);
+
+        // Include the active runtimes, in case they are not registered as available
+        let active_data = WindowsActiveRuntimeData::new(&*registry);
+        if let Some(p) = &active_data.active_64 {
+            manifests64.push_unique(p.clone());
+        }
+        if let Some(p) = &active_data.active_32 {
+            manifests32.push_unique(p.clone());
+        }
+
+        // handle extra paths
+        let (extra32, extra64, errs) = process_extra_manifests(extra_paths);
+        for path in extra32 {
+            manifests32.push_unique(path);
+        }
+        for path in extra64 {
+            manifests64.push_unique(path);
+        }
+        // Don't report these again if they are also registered
+        let mut collection = RuntimeCollection::new(registry);
+        collection
+            .failed_manifests
+            .extend(errs.iter().map(|e| normalize_path(&e.0)));
+
+        Self {
+            collection,
+            active_data,
+            groups: group_manifests(manifests64, manifests32).into_iter(),
+            added_manually: false,
+            found: unreadable_keys.into_iter().chain(errs).map(Err).collect(),
+        }
+    }
+
+    /// Queue up the runtimes just added to the collection, then the given errors.
+    fn queue_found(&mut self, errors: impl IntoIterator<Item = ManifestError>) {
+        self.found
+            .extend(self.collection.runtimes.drain(..).map(Ok));
+        for ManifestError(path, e) in errors {
+            log::warn!(
+                "Error creating runtime object for runtime with manifest {}: {}",
+                path.display(),
+                e
+            );
+            // Make sure a broken active runtime is not silently absent
+            let e = if self.active_data.is_active_manifest(&path) {
+                Error::ActiveRuntimeManifestUnusable(Box::new(e))
+            } else {
+                e
+            };
+            self.found.push_back(Err(ManifestError(path, e)));
+        }
+    }
+
+    /// Load the next runtime, or report the next manifest that could not be loaded,
+    /// calling `progress` with each manifest path before it is examined.
+    fn next_with_progress(
+        &mut self,
+        progress: &mut dyn FnMut(&Path) -> ControlFlow<()>,
+    ) -> Result<Option<Result<WindowsRuntime, ManifestError>>, Error> {
+        loop {
+            if let Some(result) = self.found.pop_front() {
+                return Ok(Some(result));
+            }
+            if let Some(group) = self.groups.next() {
+                if let Some((_, path)) = group.first() {
+                    check_progress(progress, path)?;
+                }
+                let manifests = group
+                    .iter()
+                    .map(|(view, path)| (*view, path.as_path()))
+                    .collect_vec();
+                let errors = self.collection.try_add(&manifests);
+                self.queue_found(errors);
+            } else if !self.added_manually {
+                // Finally, try adding ones we might not see otherwise
+                self.added_manually = true;
+                let errors = manually_add_runtimes(&mut self.collection);
+                self.queue_found(errors);
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+impl Iterator for RuntimeScan {
+    type Item = Result<WindowsRuntime, ManifestError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Only a request to stop can make this fail
+        self.next_with_progress(&mut |_| ControlFlow::Continue(()))
+            .ok()
+            .flatten()
+    }
+}
+
 impl Platform for WindowsPlatform {
     type PlatformRuntimeType = WindowsRuntime;
 
-    fn find_available_runtimes(
+    fn find_available_runtimes_with_progress(
         &self,
         extra_paths: Box<dyn '_ + Iterator<Item = PathBuf>>,
+        progress: &mut dyn FnMut(&Path) -> ControlFlow<()>,
     ) -> Result<(Vec<Self::PlatformRuntimeType>, Vec<ManifestError>), Error> {
-        let mut collection = RuntimeCollection::default();
-
+        let mut scan = RuntimeScan::new(self.registry.clone(), extra_paths);
+        let mut runtimes = vec![];
         let mut nonfatal_errors = vec![];
+        while let Some(result) = scan.next_with_progress(progress)? {
+            match result {
+                Ok(runtime) => runtimes.push(runtime),
+                Err(e) => nonfatal_errors.push(e),
+            }
+        }
+        Ok((runtimes, nonfatal_errors))
+    }
 
-        let avail_runtimes_key_path = make_prefix_key().join(AVAILABLE_RUNTIMES);
-
-        let mut manifests64 = match make_prefix_key_flags_64() {
-            Some(flags) => enumerate_reg_runtimes(&avail_runtimes_key_path, flags),
-            None => Default::default(),
-        };
-
-        let mut manifests32 = match make_prefix_key_flags_32() {
-            Some(flags) => enumerate_reg_runtimes(&avail_runtimes_key_path, flags),
-            None => Default::default(),
-        };
+    fn find_available_runtimes_iter<'a>(
+        &'a self,
+        extra_paths: Box<dyn 'a + Iterator<Item = PathBuf>>,
+    ) -> Box<dyn 'a + Iterator<Item = Result<Self::PlatformRuntimeType, ManifestError>>> {
+        Box::new(RuntimeScan::new(self.registry.clone(), extra_paths))
+    }
 
-        {
-            // handle extra paths
+    fn load_extra_runtime(&self, manifest: &Path) -> Result<Self::PlatformRuntimeType, Error> {
+        let (is_32, is_64) = extra_manifest_bitness(manifest).map_err(|ManifestError(_, e)| e)?;
+        let manifests = [(RegistryView::Bits64, is_64), (RegistryView::Bits32, is_32)]
+            .into_iter()
+            .filter(|(_, found)| *found)
+            .map(|(view, _)| (view, manifest))
+            .collect_vec();
+        WindowsRuntime::new(self.registry.clone(), &manifests)
+    }
 
-            let (extra32, extra64, mut errs) = process_extra_manifests(extra_paths);
-            for path in extra32 {
-                manifests32.push_unique(path);
+    fn find_available_api_layers(
+        &self,
+    ) -> Result<(Vec<ApiLayerManifest>, Vec<ManifestError>), Error> {
+        let mut known_manifests: HashSet<PathBuf> = HashSet::default();
+        let mut layers = vec![];
+        let mut nonfatal_errors = vec![];
+        for kind in [ApiLayerKind::Implicit, ApiLayerKind::Explicit] {
+            let key_path = subkey(
+                &subkey(&make_prefix_key(), API_LAYERS_KEY),
+                kind.registry_key_name(),
+            );
+            // Same value format as the available runtimes key
+            let mut manifests = vec![];
+            for view in available_views(&*self.registry) {
+                let (found, mut errors) = enumerate_reg_runtimes(&*self.registry, &key_path, view);
+                manifests.extend(found);
+                nonfatal_errors.append(&mut errors);
             }
-            for path in extra64 {
-                manifests64.push_unique(path);
+            for path in manifests {
+                if !known_manifests.insert(normalize_path(&path)) {
+                    continue;
+                }
+                match ApiLayerManifest::new(&path, kind) {
+                    Ok(layer) => layers.push(layer),
+                    Err(e) => nonfatal_errors.push(ManifestError(path, e)),
+                }
             }
-            nonfatal_errors.append(&mut errs);
         }
+        Ok((layers, nonfatal_errors))
+    }
 
-        let manifest_32_by_parent_dir: HashMap<&Path, &Path, RandomState> = HashMap::from_iter(
-            manifests32
-                .iter()
-                .filter_map(|p| p.parent().map(|parent| (parent, p.as_path()))),
-        );
+    type PlatformActiveData = WindowsActiveRuntimeData;
 
-        let mut push_err = |e: Error, path: &Path| {
-            eprintln!(
-                "Error creating runtime object for runtime with manifest {}: {}",
-                path.display(),
-                e
-            );
-            nonfatal_errors.push(ManifestError(path.to_owned(), e));
-        };
+    fn capabilities(&self) -> PlatformCapabilities {
+        PlatformCapabilities {
+            supports_clear: true,
+            // Only when both registry views are there, i.e. on 64-bit Windows
+            has_split_bitness: available_views(&*self.registry).count() == RegistryView::ALL.len(),
+            // The active runtime is written under HKEY_LOCAL_MACHINE
+            may_need_elevation: true,
+            supports_env_override: true,
+            // Runtimes are registered, not found by searching directories
+            supports_extra_search_dirs: false,
+            file_manager_selects_file: true,
+        }
+    }
 
-        // Handle all 64-bit runtimes, matching with a 32-bit one if applicable
-        for path in manifests64.iter() {
-            let parent = path.parent().expect("every file has a parent");
-            let counterpart_32 = manifest_32_by_parent_dir.get(parent);
-            if let Err(e) = collection.try_add(Some(path), counterpart_32.map(|p| p.as_ref())) {
-                push_err(e, &path);
+    fn get_search_locations(&self) -> Vec<String> {
+        let prefix = make_prefix_key();
+        let avail_runtimes_key_path = subkey(&prefix, AVAILABLE_RUNTIMES);
+        let mut locations = vec![];
+        for view in available_views(&*self.registry) {
+            for hive in Hive::ALL {
+                locations.push(format!(
+                    "{}\\{} ({} registry view)",
+                    hive.name(),
+                    avail_runtimes_key_path,
+                    view.description()
+                ));
+                locations.push(format!(
+                    "{}\\{} ({} registry view, {} value)",
+                    hive.name(),
+                    prefix,
+                    view.description(),
+                    ACTIVE_RUNTIME
+                ));
             }
         }
-        // Handle remaining 32-bit ones
-        for path in manifests32.iter() {
-            // we don't care about errors right now
-            if let Err(e) = collection.try_add(None, Some(path)) {
-                push_err(e, &path);
-            }
+        // These are the ones we add manually
+        if cfg!(target_pointer_width = "64") {
+            locations.extend(varjo_manifest_path().map(|p| p.display().to_string()));
+            locations
+                .extend(steamvr_manifest_path(&*self.registry).map(|p| p.display().to_string()));
         }
+        locations.extend(
+            system_dir_64()
+                .into_iter()
+                .chain(system_dir_32())
+                .map(|d| d.join(WINMR_JSON_NAME).display().to_string()),
+        );
+        locations
+    }
 
-        // Finally, try adding ones we might not see otherwise
-        nonfatal_errors.extend(manually_add_runtimes(&mut collection));
-
-        Ok((collection.into(), nonfatal_errors))
+    fn get_config_directory(&self) -> Option<PathBuf> {
+        // Active runtime is stored in the registry
+        None
     }
 
-    type PlatformActiveData = WindowsActiveRuntimeData;
+    fn active_runtime_is_system_wide(&self) -> bool {
+        // By default the active runtime is written under HKEY_LOCAL_MACHINE
+        true
+    }
 
     fn get_active_runtime_manifests(&self) -> Vec<PathBuf> {
-        let data = WindowsActiveRuntimeData::new();
+        let data = WindowsActiveRuntimeData::new(&*self.registry);
         // OK to move out of data because we just created it for this purpose
         data.active_64
             .into_iter()
@@ -431,8 +1477,85 @@ impl Platform for WindowsPlatform {
             .collect()
     }
 
+    fn get_active_runtime_candidates(&self) -> Vec<(ConfigLocation, ConfigSource)> {
+        let prefix = make_prefix_key();
+        // The loader uses XR_RUNTIME_JSON in preference to the registry
+        let env_override = env::var_os(XR_RUNTIME_JSON)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .filter(|p| p.is_file())
+            .map(|p| (ConfigLocation::File(p), ConfigSource::Environment));
+        // Each registry view is used by apps of the matching bitness
+        let registry = &*self.registry;
+        let registry = available_views(registry)
+            .flat_map(|view| {
+                Hive::ALL
+                    .into_iter()
+                    .filter_map(|hive| {
+                        get_active_runtime_in_hive(registry, hive, &prefix, view).map(|_| {
+                            (
+                                ConfigLocation::RegistryKey(subkey(hive.name(), &prefix)),
+                                hive_config_source(hive),
+                            )
+                        })
+                    })
+                    .collect_vec()
+            })
+            .unique();
+        env_override.into_iter().chain(registry).collect()
+    }
+
+    fn diagnose_active_runtime(&self) -> ActiveRuntimeDiagnosis {
+        let prefix = make_prefix_key();
+        if let Some((hive, target)) = RegistryView::ALL
+            .into_iter()
+            .filter_map(|view| get_active_runtime_location(&*self.registry, &prefix, view))
+            .find(|(_, p)| !p.exists())
+        {
+            return ActiveRuntimeDiagnosis::Dangling {
+                link: ConfigLocation::RegistryKey(subkey(hive.name(), &prefix)),
+                target,
+            };
+        }
+        let data = WindowsActiveRuntimeData::new(&*self.registry);
+        match data.active_64.or(data.active_32) {
+            Some(path) => ActiveRuntimeDiagnosis::Valid(path),
+            None => ActiveRuntimeDiagnosis::NotConfigured,
+        }
+    }
+
+    fn get_active_runtime(&self) -> Result<Option<Self::PlatformRuntimeType>, Error> {
+        self.active_runtime_from(WindowsActiveRuntimeData::new(&*self.registry))
+    }
+
+    fn watch_active_runtime(&self, callback: WatchCallback) -> Result<ActiveRuntimeWatcher, Error> {
+        // Registry change notification would need unsafe code, which this crate does not allow,
+        // so just poll.
+        let registry = self.registry.clone();
+        ActiveRuntimeWatcher::polling(
+            move || WindowsPlatform::with_registry(registry.clone()).snapshot_active_runtime(),
+            callback,
+        )
+    }
+
+    fn clear_active_runtime(&self) -> Result<bool, Error> {
+        let prefix = make_prefix_key();
+        let mut cleared = false;
+        for view in available_views(&*self.registry) {
+            for hive in Hive::ALL {
+                cleared |= delete_active_runtime_value(&*self.registry, hive, &prefix, view)?;
+            }
+        }
+        Ok(cleared)
+    }
+
     fn get_active_data(&self) -> Self::PlatformActiveData {
-        WindowsActiveRuntimeData::new()
+        WindowsActiveRuntimeData::new(&*self.registry)
+    }
+
+    fn active_data_is_stale(&self, active_data: &Self::PlatformActiveData) -> bool {
+        let current = WindowsActiveRuntimeData::new(&*self.registry);
+        current.active_64 != active_data.active_64 || current.active_32 != active_data.active_32
     }
 
     fn get_runtime_active_state(
@@ -442,8 +1565,631 @@ impl Platform for WindowsPlatform {
     ) -> ActiveState {
         active_data.check_runtime(runtime)
     }
+
+    fn describe_active_reason(
+        &self,
+        runtime: &Self::PlatformRuntimeType,
+        active_data: &Self::PlatformActiveData,
+    ) -> Option<String> {
+        active_data.describe_reason(runtime)
+    }
+
+    fn get_active_runtime_override(
+        &self,
+        active_data: &Self::PlatformActiveData,
+    ) -> Option<ActiveRuntimeOverride> {
+        active_data.get_override()
+    }
 }
 
 pub fn make_platform() -> WindowsPlatform {
     WindowsPlatform::new()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{registry::MemoryRegistry, test_util::write_manifest};
+    use std::ffi::OsStr;
+    use tempfile::TempDir;
+
+    /// Register a manifest as available, and enabled.
+    fn register(registry: &MemoryRegistry, hive: Hive, view: RegistryView, manifest: &Path) {
+        let key = subkey(&make_prefix_key(), AVAILABLE_RUNTIMES);
+        registry.set_dword(hive, view, &key, &manifest.to_string_lossy(), 0);
+    }
+
+    fn set_active_value(registry: &MemoryRegistry, hive: Hive, view: RegistryView, value: &str) {
+        registry
+            .set_string(
+                hive,
+                view,
+                &make_prefix_key(),
+                ACTIVE_RUNTIME,
+                OsStr::new(value),
+            )
+            .unwrap();
+    }
+
+    fn active_value(registry: &MemoryRegistry, hive: Hive, view: RegistryView) -> Option<String> {
+        registry
+            .get_string(hive, view, &make_prefix_key(), ACTIVE_RUNTIME)
+            .ok()
+    }
+
+    /// Find runtimes, ignoring any real ones we add without looking in the registry.
+    fn find_runtimes_in(
+        registry: &Arc<MemoryRegistry>,
+        dir: &Path,
+    ) -> (Vec<WindowsRuntime>, Vec<ManifestError>) {
+        let platform = WindowsPlatform::with_registry(registry.clone());
+        let (runtimes, errors) = platform
+            .find_available_runtimes(Box::new(std::iter::empty()))
+            .unwrap();
+        let runtimes = runtimes
+            .into_iter()
+            .filter(|r| r.get_manifests().iter().all(|m| m.starts_with(dir)))
+            .collect();
+        (runtimes, errors)
+    }
+
+    #[test]
+    fn group_manifests_by_directory() {
+        let paired64 = Path::new("C:\\Runtime").join("runtime64.json");
+        let paired32 = Path::new("C:\\Runtime").join("runtime32.json");
+        let native = Path::new("C:\\Runtime").join("runtime_arm64.json");
+        let only64 = Path::new("C:\\Other64").join("runtime.json");
+        let only32 = Path::new("C:\\Other32").join("runtime.json");
+        let manifests64 = vec![paired64.clone(), only64.clone(), native.clone()];
+        let manifests32 = vec![only32.clone(), paired32.clone()];
+        use RegistryView::*;
+        assert_eq!(
+            group_manifests(manifests64, manifests32),
+            vec![
+                vec![(Bits64, paired64), (Bits64, native), (Bits32, paired32)],
+                vec![(Bits64, only64)],
+                vec![(Bits32, only32)],
+            ]
+        );
+    }
+
+    #[test]
+    fn scan_pairs_views_by_directory() {
+        let temp = TempDir::new().unwrap();
+        let paired64 = write_manifest(
+            &temp.path().join("paired"),
+            "runtime64.json",
+            "runtime.dll",
+            None,
+        );
+        let paired32 = write_manifest(
+            &temp.path().join("paired"),
+            "runtime32.json",
+            "runtime.dll",
+            None,
+        );
+        let only32 = write_manifest(
+            &temp.path().join("only32"),
+            "runtime32.json",
+            "runtime.dll",
+            None,
+        );
+        let registry = Arc::new(MemoryRegistry::default());
+        register(
+            &registry,
+            Hive::LocalMachine,
+            RegistryView::Bits64,
+            &paired64,
+        );
+        register(
+            &registry,
+            Hive::LocalMachine,
+            RegistryView::Bits32,
+            &paired32,
+        );
+        register(&registry, Hive::LocalMachine, RegistryView::Bits32, &only32);
+
+        let (runtimes, errors) = find_runtimes_in(&registry, temp.path());
+        assert!(errors.is_empty(), "{:?}", errors);
+        let manifests = runtimes.iter().map(|r| r.get_manifests()).collect_vec();
+        assert_eq!(
+            manifests,
+            vec![
+                vec![paired64.as_path(), paired32.as_path()],
+                vec![only32.as_path()],
+            ]
+        );
+    }
+
+    #[test]
+    fn scan_finds_each_manifest_once() {
+        let temp = TempDir::new().unwrap();
+        let manifest = write_manifest(temp.path(), "runtime.json", "runtime.dll", None);
+        let registry = Arc::new(MemoryRegistry::default());
+        for hive in Hive::ALL {
+            register(&registry, hive, RegistryView::Bits64, &manifest);
+        }
+        // Also active, which is included in case it is not registered
+        set_active_value(
+            &registry,
+            Hive::LocalMachine,
+            RegistryView::Bits64,
+            &manifest.to_string_lossy(),
+        );
+
+        let (runtimes, errors) = find_runtimes_in(&registry, temp.path());
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(runtimes.len(), 1);
+        assert_eq!(runtimes[0].get_manifests(), vec![manifest.as_path()]);
+    }
+
+    #[test]
+    fn broken_manifest_is_reported_once() {
+        let temp = TempDir::new().unwrap();
+        let broken = temp.path().join("broken.json");
+        fs::write(&broken, "{").unwrap();
+        let registry = Arc::new(MemoryRegistry::default());
+        for hive in Hive::ALL {
+            for view in RegistryView::ALL {
+                register(&registry, hive, view, &broken);
+            }
+        }
+
+        let (runtimes, errors) = find_runtimes_in(&registry, temp.path());
+        assert!(runtimes.is_empty());
+        let errors = errors
+            .iter()
+            .filter(|ManifestError(path, _)| path.starts_with(temp.path()))
+            .collect_vec();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].0, broken);
+    }
+
+    #[test]
+    fn stale_available_runtimes() {
+        let temp = TempDir::new().unwrap();
+        let manifest = write_manifest(temp.path(), "runtime.json", "runtime.dll", None);
+        let stale = temp.path().join("uninstalled.json");
+        let registry = Arc::new(MemoryRegistry::default());
+        for view in RegistryView::ALL {
+            register(&registry, Hive::LocalMachine, view, &manifest);
+            register(&registry, Hive::LocalMachine, view, &stale);
+        }
+
+        // Skipped quietly, rather than an error every time
+        let (runtimes, errors) = find_runtimes_in(&registry, temp.path());
+        assert_eq!(runtimes.len(), 1);
+        assert!(
+            errors.iter().all(|e| !e.0.starts_with(temp.path())),
+            "{:?}",
+            errors
+        );
+
+        let platform = WindowsPlatform::with_registry(registry.clone());
+        registry.make_read_only(Hive::LocalMachine, RegistryView::Bits64);
+        assert!(matches!(
+            platform.cleanup_stale_available_runtimes(),
+            Err(Error::ElevationRequired)
+        ));
+
+        let registry = Arc::new(MemoryRegistry::default());
+        for view in RegistryView::ALL {
+            register(&registry, Hive::LocalMachine, view, &manifest);
+            register(&registry, Hive::LocalMachine, view, &stale);
+        }
+        let platform = WindowsPlatform::with_registry(registry.clone());
+        assert_eq!(
+            platform.cleanup_stale_available_runtimes().unwrap(),
+            vec![stale]
+        );
+        let key = subkey(&make_prefix_key(), AVAILABLE_RUNTIMES);
+        for view in RegistryView::ALL {
+            let names = registry
+                .get_dword_values(Hive::LocalMachine, view, &key)
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect_vec();
+            assert_eq!(names, vec![manifest.to_string_lossy().into_owned()]);
+        }
+    }
+
+    #[test]
+    fn disabled_runtimes_are_skipped() {
+        let temp = TempDir::new().unwrap();
+        let manifest = write_manifest(temp.path(), "runtime.json", "runtime.dll", None);
+        let registry = Arc::new(MemoryRegistry::default());
+        let key = subkey(&make_prefix_key(), AVAILABLE_RUNTIMES);
+        registry.set_dword(
+            Hive::LocalMachine,
+            RegistryView::Bits64,
+            &key,
+            &manifest.to_string_lossy(),
+            1,
+        );
+
+        let (runtimes, _) = find_runtimes_in(&registry, temp.path());
+        assert!(runtimes.is_empty());
+    }
+
+    #[test]
+    fn active_runtime_pairs_views_by_directory() {
+        let temp = TempDir::new().unwrap();
+        let paired64 = write_manifest(
+            &temp.path().join("paired"),
+            "runtime64.json",
+            "runtime.dll",
+            None,
+        );
+        let paired32 = write_manifest(
+            &temp.path().join("paired"),
+            "runtime32.json",
+            "runtime.dll",
+            None,
+        );
+        let other32 = write_manifest(
+            &temp.path().join("other"),
+            "runtime32.json",
+            "runtime.dll",
+            None,
+        );
+        let registry = Arc::new(MemoryRegistry::default());
+        let platform = WindowsPlatform::with_registry(registry.clone());
+        let set_active = |view, manifest: &Path| {
+            set_active_value(
+                &registry,
+                Hive::LocalMachine,
+                view,
+                &manifest.to_string_lossy(),
+            )
+        };
+        let active_manifests = || {
+            platform.get_active_runtime().unwrap().map(|r| {
+                r.get_manifests()
+                    .iter()
+                    .map(|p| p.to_path_buf())
+                    .collect_vec()
+            })
+        };
+        assert_eq!(active_manifests(), None);
+
+        set_active(RegistryView::Bits64, &paired64);
+        set_active(RegistryView::Bits32, &paired32);
+        assert_eq!(active_manifests(), Some(vec![paired64.clone(), paired32]));
+
+        // Different runtimes are active for each: report the 64-bit one
+        set_active(RegistryView::Bits32, &other32);
+        assert_eq!(active_manifests(), Some(vec![paired64]));
+    }
+
+    #[test]
+    fn xr_runtime_json_overrides_registry() {
+        let temp = TempDir::new().unwrap();
+        let configured = write_manifest(temp.path(), "configured.json", "runtime.dll", None);
+        // The bitness of a manually chosen manifest comes from its library
+        let library = env::current_exe().unwrap();
+        let forced = write_manifest(temp.path(), "forced.json", &library.to_string_lossy(), None);
+        let registry = Arc::new(MemoryRegistry::default());
+        set_active_value(
+            &registry,
+            Hive::LocalMachine,
+            RegistryView::Bits64,
+            &configured.to_string_lossy(),
+        );
+        let platform = WindowsPlatform::with_registry(registry.clone());
+        let configured_runtime =
+            WindowsRuntime::new(registry.clone(), &[(RegistryView::Bits64, &configured)]).unwrap();
+        let forced_runtime = platform.load_extra_runtime(&forced).unwrap();
+
+        for unset in [None, Some(OsString::new())] {
+            let data = WindowsActiveRuntimeData::read(&*registry, unset);
+            assert_eq!(data.get_override(), None);
+            assert_eq!(
+                data.check_runtime(&configured_runtime),
+                ActiveState::Active64
+            );
+            assert_eq!(data.check_runtime(&forced_runtime), ActiveState::NotActive);
+            let active = platform.active_runtime_from(data).unwrap().unwrap();
+            assert_eq!(active.get_manifests(), vec![configured.as_path()]);
+        }
+
+        let read_forced =
+            || WindowsActiveRuntimeData::read(&*registry, Some(forced.clone().into()));
+        let data = read_forced();
+        assert_eq!(
+            data.get_override().map(|o| o.effective),
+            Some(normalize_path(&forced))
+        );
+        assert_eq!(
+            data.check_runtime(&configured_runtime),
+            ActiveState::OverriddenByEnvironment
+        );
+        assert_eq!(
+            data.check_runtime(&forced_runtime),
+            ActiveState::ActiveFromEnvironment
+        );
+        assert!(data
+            .describe_reason(&configured_runtime)
+            .is_some_and(|reason| reason.contains(XR_RUNTIME_JSON)));
+        let active = platform
+            .active_runtime_from(read_forced())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            active.get_manifests(),
+            vec![normalize_path(&forced).as_path()]
+        );
+    }
+
+    #[cfg(feature = "arch-detect")]
+    #[test]
+    fn native_and_emulated_manifests_are_one_runtime() {
+        use crate::test_util::{write_library, EM_AARCH64, EM_X86_64};
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let manifest_for = |name: &str, machine: Option<u16>| {
+            let library = dir.join(format!("{}.dll", name));
+            if let Some(machine) = machine {
+                write_library(&library, machine);
+            }
+            write_manifest(
+                dir,
+                &format!("{}.json", name),
+                &library.to_string_lossy(),
+                None,
+            )
+        };
+        let x64 = manifest_for("runtime_x64", Some(EM_X86_64));
+        let arm64 = manifest_for("runtime_arm64", Some(EM_AARCH64));
+        let x86 = manifest_for("runtime_x86", None);
+        let registry = Arc::new(MemoryRegistry::default());
+        register(&registry, Hive::LocalMachine, RegistryView::Bits64, &x64);
+        register(&registry, Hive::LocalMachine, RegistryView::Bits64, &arm64);
+        register(&registry, Hive::LocalMachine, RegistryView::Bits32, &x86);
+
+        let (runtimes, errors) = find_runtimes_in(&registry, dir);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(runtimes.len(), 1);
+        let runtime = &runtimes[0];
+        assert_eq!(
+            runtime.bases.keys().copied().collect_vec(),
+            vec![
+                RuntimeArchAbi::X86_64,
+                RuntimeArchAbi::I686,
+                RuntimeArchAbi::Aarch64
+            ]
+        );
+
+        // Each host makes its native one active in the shared 64-bit view
+        for (host, expected) in [("ARM64", &arm64), ("AMD64", &x64)] {
+            registry
+                .set_string(
+                    Hive::LocalMachine,
+                    RegistryView::Bits64,
+                    SYSTEM_ENVIRONMENT_KEY,
+                    "PROCESSOR_ARCHITECTURE",
+                    OsStr::new(host),
+                )
+                .unwrap();
+            assert_eq!(
+                runtime
+                    .runtime_for_view(RegistryView::Bits64)
+                    .map(BaseRuntime::get_manifest_path),
+                Some(expected.as_path()),
+                "{}",
+                host
+            );
+        }
+        assert_eq!(
+            runtime
+                .runtime_for_view(RegistryView::Bits32)
+                .map(BaseRuntime::get_manifest_path),
+            Some(x86.as_path())
+        );
+
+        // Active if either is set, e.g. by the emulated runtime's own installer
+        for active in [&x64, &arm64] {
+            set_active_value(
+                &registry,
+                Hive::LocalMachine,
+                RegistryView::Bits64,
+                &active.to_string_lossy(),
+            );
+            let data = WindowsActiveRuntimeData::read(&*registry, None);
+            assert_eq!(data.check_runtime(runtime), ActiveState::Active64);
+        }
+    }
+
+    #[test]
+    fn declared_name_wins_over_heuristic() {
+        let temp = TempDir::new().unwrap();
+        // Only the 64-bit library path matches a heuristic
+        let manifest64 = write_manifest(temp.path(), "steamxr64.json", "steamxr_win64.dll", None);
+        let manifest32 = write_manifest(temp.path(), "runtime32.json", "runtime32.dll", None);
+        let mut declared = crate::RuntimeManifest::read(&manifest32).unwrap();
+        declared.set_name(Some("Declared Runtime"));
+        declared.write(&manifest32).unwrap();
+
+        let registry = Arc::new(MemoryRegistry::default());
+        let runtime = WindowsRuntime::new(
+            registry.clone(),
+            &[
+                (RegistryView::Bits64, &manifest64),
+                (RegistryView::Bits32, &manifest32),
+            ],
+        )
+        .unwrap();
+        assert_eq!(runtime.get_runtime_name(), "Declared Runtime");
+
+        // Without a declared name, the heuristic applies to the 64-bit manifest
+        let runtime =
+            WindowsRuntime::new(registry, &[(RegistryView::Bits64, &manifest64)]).unwrap();
+        assert_eq!(runtime.get_runtime_name(), "SteamVR");
+    }
+
+    #[test]
+    fn elevation_needed_when_system_registry_is_read_only() {
+        let registry = Arc::new(MemoryRegistry::default());
+        let platform = WindowsPlatform::with_registry(registry.clone());
+        assert!(!platform.needs_elevation_to_set_active());
+
+        // Only the system-wide settings matter
+        registry.make_read_only(Hive::CurrentUser, RegistryView::Bits64);
+        assert!(!platform.needs_elevation_to_set_active());
+        registry.make_read_only(Hive::LocalMachine, RegistryView::Bits64);
+        assert!(platform.needs_elevation_to_set_active());
+    }
+
+    #[test]
+    fn windows_build_version_from_registry() {
+        let registry = MemoryRegistry::default();
+        assert_eq!(windows_build_version(&registry), None);
+
+        let set = |name: &str, value: &str| {
+            registry
+                .set_string(
+                    Hive::LocalMachine,
+                    RegistryView::Bits64,
+                    WINDOWS_VERSION_KEY,
+                    name,
+                    OsStr::new(value),
+                )
+                .unwrap();
+        };
+        set("CurrentBuild", "22631");
+        assert_eq!(
+            windows_build_version(&registry).as_deref(),
+            Some("Windows build 22631")
+        );
+        registry.set_dword(
+            Hive::LocalMachine,
+            RegistryView::Bits64,
+            WINDOWS_VERSION_KEY,
+            "UBR",
+            4169,
+        );
+        assert_eq!(
+            windows_build_version(&registry).as_deref(),
+            Some("Windows build 22631.4169")
+        );
+    }
+
+    #[test]
+    fn installed_program_version_from_uninstall_entry() {
+        let registry = MemoryRegistry::default();
+        assert_eq!(installed_program_version(&registry, "Varjo Base"), None);
+
+        let add_program = |view, id: &str, name: &str, version: &str| {
+            let key = subkey(UNINSTALL_KEY, id);
+            for (value_name, value) in [("DisplayName", name), ("DisplayVersion", version)] {
+                registry
+                    .set_string(
+                        Hive::LocalMachine,
+                        view,
+                        &key,
+                        value_name,
+                        OsStr::new(value),
+                    )
+                    .unwrap();
+            }
+        };
+        add_program(RegistryView::Bits64, "{1234}", "Something Else", "1.0");
+        assert_eq!(installed_program_version(&registry, "Varjo Base"), None);
+
+        // 32-bit programs are listed in the other view
+        add_program(RegistryView::Bits32, "VarjoBase", "Varjo Base", "4.5.0.1");
+        assert_eq!(
+            installed_program_version(&registry, "Varjo Base").as_deref(),
+            Some("4.5.0.1")
+        );
+        assert_eq!(
+            installed_program_version(&registry, "Something Else").as_deref(),
+            Some("1.0")
+        );
+    }
+
+    /// A runtime with both a 64-bit and a 32-bit manifest, and a registry to make it active in.
+    fn make_runtime(temp: &TempDir) -> (Arc<MemoryRegistry>, WindowsRuntime) {
+        let manifest64 = write_manifest(temp.path(), "runtime64.json", "runtime.dll", None);
+        let manifest32 = write_manifest(temp.path(), "runtime32.json", "runtime.dll", None);
+        let registry = Arc::new(MemoryRegistry::default());
+        let runtime = WindowsRuntime::new(
+            registry.clone(),
+            &[
+                (RegistryView::Bits64, &manifest64),
+                (RegistryView::Bits32, &manifest32),
+            ],
+        )
+        .unwrap();
+        (registry, runtime)
+    }
+
+    #[test]
+    fn make_active_system_wide() {
+        let temp = TempDir::new().unwrap();
+        let (registry, runtime) = make_runtime(&temp);
+        for view in RegistryView::ALL {
+            set_active_value(&registry, Hive::CurrentUser, view, "C:\\old.json");
+        }
+
+        runtime.make_active().unwrap();
+        for (base, view) in runtime.runtimes_by_view() {
+            let manifest = base.unwrap().get_manifest_path();
+            assert_eq!(
+                active_value(&registry, Hive::LocalMachine, view).as_deref(),
+                manifest.to_str()
+            );
+            // Would have taken precedence
+            assert_eq!(active_value(&registry, Hive::CurrentUser, view), None);
+        }
+    }
+
+    #[test]
+    fn make_active_restores_64_bit_value_on_failure() {
+        let temp = TempDir::new().unwrap();
+        let (registry, runtime) = make_runtime(&temp);
+        set_active_value(
+            &registry,
+            Hive::LocalMachine,
+            RegistryView::Bits64,
+            "C:\\old.json",
+        );
+        registry.make_read_only(Hive::LocalMachine, RegistryView::Bits32);
+
+        assert!(matches!(
+            runtime.make_active(),
+            Err(Error::ElevationRequired)
+        ));
+        assert_eq!(
+            active_value(&registry, Hive::LocalMachine, RegistryView::Bits64).as_deref(),
+            Some("C:\\old.json")
+        );
+    }
+
+    #[test]
+    fn make_active_removes_new_64_bit_value_on_failure() {
+        let temp = TempDir::new().unwrap();
+        let (registry, runtime) = make_runtime(&temp);
+        registry.make_read_only(Hive::LocalMachine, RegistryView::Bits32);
+
+        assert!(runtime.make_active().is_err());
+        assert_eq!(
+            active_value(&registry, Hive::LocalMachine, RegistryView::Bits64),
+            None
+        );
+    }
+
+    #[test]
+    fn make_active_without_64_bit_view() {
+        let temp = TempDir::new().unwrap();
+        let (registry, runtime) = make_runtime(&temp);
+        registry.remove_view(RegistryView::Bits64);
+
+        runtime.make_active().unwrap();
+        assert_eq!(
+            active_value(&registry, Hive::LocalMachine, RegistryView::Bits64),
+            None
+        );
+        assert!(active_value(&registry, Hive::LocalMachine, RegistryView::Bits32).is_some());
+    }
+}
@@ -0,0 +1,44 @@
+// Copyright 2022-2024, Collabora, Ltd.
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Decoding of the embedded application icon, independent of any particular GUI toolkit.
+
+/// A decoded icon image: 8-bit RGBA pixels in row-major order, plus dimensions.
+pub struct DecodedIcon {
+    pub rgba: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Decode PNG data into RGBA pixels.
+///
+/// Returns `None` (after printing a warning) if the data cannot be decoded.
+pub fn decode_png_icon(icon_data: &[u8]) -> Option<DecodedIcon> {
+    let image = image::load_from_memory_with_format(icon_data, image::ImageFormat::Png)
+        .map_err(|e| log::warn!("Could not decode icon: {e}"))
+        .ok()?;
+    let image = image.into_rgba8();
+    let (width, height) = image.dimensions();
+    Some(DecodedIcon {
+        rgba: image.into_raw(),
+        width,
+        height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_embedded_icon() {
+        let icon = decode_png_icon(crate::ICON_48).unwrap();
+        assert_eq!((icon.width, icon.height), (48, 48));
+        assert_eq!(icon.rgba.len(), 48 * 48 * 4);
+    }
+
+    #[test]
+    fn invalid_data_is_not_an_icon() {
+        assert!(decode_png_icon(b"not a png").is_none());
+    }
+}
@@ -4,63 +4,199 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 #![forbid(unsafe_code)]
 
-use std::{path::PathBuf, sync::Arc};
+mod icon;
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
 
 use eframe::{
     egui::{self, TextStyle},
     epaint::Color32,
 };
 
+use icon::decode_png_icon;
 use itertools::Itertools;
 use xrpicker::{
-    make_platform, platform::PlatformRuntime, AppState, Error, PersistentAppState, Platform,
+    make_platform,
+    platform::{
+        ActiveRuntimeDiagnosis, ActiveRuntimeOverride, ConfigLocation, ConfigSource,
+        MakeActiveOptions, PlatformCapabilities, PlatformRuntime,
+    },
+    ActiveRuntimeWatcher, ActiveState, AppState, Error, PersistentAppState, Platform,
+    ThemePreference, XR_RUNTIME_JSON,
 };
 
 // const ICON_32: &[u8; 542] = include_bytes!("../assets/icon/icon32.png");
 const ICON_48: &[u8; 727] = include_bytes!("../assets/icon/icon48.png");
 
-fn load_icon(icon_data: &[u8]) -> Arc<egui::IconData> {
-    let image = image::load_from_memory_with_format(icon_data, image::ImageFormat::Png)
-        .ok()
-        .unwrap();
-    let image = image.into_rgba8();
-    let (width, height) = image.dimensions();
-    let rgba = image.into_raw();
-    Arc::new(egui::IconData {
-        rgba,
-        width,
-        height,
+fn load_icon(icon_data: &[u8]) -> Option<egui::IconData> {
+    decode_png_icon(icon_data).map(|icon| egui::IconData {
+        rgba: icon.rgba,
+        width: icon.width,
+        height: icon.height,
     })
 }
 
+/// A column the runtime grid can be sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortColumn {
+    Name,
+    State,
+}
+
+/// How the runtime grid is sorted for display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RuntimeSort {
+    column: SortColumn,
+    descending: bool,
+}
+
+impl RuntimeSort {
+    /// Sort by `column`: ascending at first, flipping the direction if we already sort by it.
+    fn toggled(current: Option<RuntimeSort>, column: SortColumn) -> RuntimeSort {
+        match current {
+            Some(sort) if sort.column == column => RuntimeSort {
+                column,
+                descending: !sort.descending,
+            },
+            _ => RuntimeSort {
+                column,
+                descending: false,
+            },
+        }
+    }
+
+    /// The indicator to show next to the header of `column`.
+    fn indicator(current: Option<RuntimeSort>, column: SortColumn) -> &'static str {
+        match current {
+            Some(sort) if sort.column == column && sort.descending => " ▼",
+            Some(sort) if sort.column == column => " ▲",
+            _ => "",
+        }
+    }
+}
+
+/// Orders active states so that the most definitely active runtimes come first.
+fn active_state_sort_rank(state: ActiveState) -> u8 {
+    match state {
+        ActiveState::ActiveFromEnvironment => 0,
+        ActiveState::ActiveIndependentRuntime => 1,
+        ActiveState::Active64and32 => 2,
+        ActiveState::Active64 => 3,
+        ActiveState::Active32 => 4,
+        ActiveState::OverriddenByEnvironment => 5,
+        ActiveState::NotActive => 6,
+    }
+}
+
+/// GUI state that only lasts for this session, unlike `PersistentAppState`.
+#[derive(Debug, Default)]
+struct TransientState {
+    /// Only runtimes whose name or description contain this (ignoring case) are shown.
+    filter: String,
+    /// How to sort the runtime grid, if at all.
+    sort: Option<RuntimeSort>,
+    /// The `uniqueness_key()` of a runtime waiting for confirmation before being made active.
+    pending_make_active: Option<Vec<PathBuf>>,
+    /// A runtime to move in the list (from, to), requested with the arrow buttons.
+    pending_move: Option<(usize, usize)>,
+    /// The `uniqueness_key()` of a runtime just made active, to record in `PersistentAppState`.
+    activated: Option<Vec<PathBuf>>,
+    /// Set by the active runtime watcher when something else may have changed the active runtime.
+    changed_externally: Arc<AtomicBool>,
+    /// The runtime whose name is being edited, if any.
+    renaming: Option<RenameState>,
+    /// Why the last manifest the user tried to add was rejected, until dismissed.
+    add_manifest_error: Option<String>,
+}
+
+/// A runtime name being edited in place.
+#[derive(Debug)]
+struct RenameState {
+    /// The `uniqueness_key()` of the runtime being renamed.
+    key: Vec<PathBuf>,
+    /// The name as edited so far.
+    name: String,
+    /// Whether editing is finished and the name should be saved.
+    done: bool,
+}
+
 struct PickerApp<T: Platform> {
     platform: T,
     state: Option<Result<AppState<T>, Error>>,
     persistent_state: PersistentAppState,
-    fixed_theme: bool,
+    transient_state: TransientState,
+    /// The theme preference last applied to the context, if any.
+    applied_theme: Option<ThemePreference>,
+    restored_window_geometry: bool,
+    /// Only kept so that we keep watching for active runtime changes.
+    _watcher: Option<ActiveRuntimeWatcher>,
 }
 
 impl<T: Platform> PickerApp<T> {
     fn new(platform: T, cc: &eframe::CreationContext<'_>) -> Self {
-        let persistent_state = cc
-            .storage
-            .and_then(|storage| eframe::get_value::<PersistentAppState>(storage, eframe::APP_KEY))
-            .unwrap_or_default();
-        let state = Some(AppState::new_with_persistent_state(
-            &platform,
-            &persistent_state,
-        ));
+        let mut persistent_state = load_persistent_data(cc.storage);
+        // Inspecting runtime libraries can be slow, so that happens after the window is up
+        let state = AppState::new_lazy_with_persistent_state(&platform, &persistent_state);
+        if let Ok(state) = &state {
+            persistent_state.migrate_runtime_keys(&state.runtimes);
+        }
+        let state = Some(state);
+
+        let transient_state = TransientState::default();
+        let changed_externally = Arc::clone(&transient_state.changed_externally);
+        let ctx = cc.egui_ctx.clone();
+        let watcher = platform
+            .watch_active_runtime(Box::new(move || {
+                changed_externally.store(true, Ordering::Relaxed);
+                ctx.request_repaint();
+            }))
+            .inspect_err(|e| log::warn!("Could not watch for active runtime changes: {}", e))
+            .ok();
 
         PickerApp {
             platform,
             state,
             persistent_state,
-            fixed_theme: false,
+            transient_state,
+            applied_theme: None,
+            restored_window_geometry: false,
+            _watcher: watcher,
         }
     }
 
     fn store_persistent_data(&self, storage: &mut dyn eframe::Storage) {
         eframe::set_value(storage, eframe::APP_KEY, &self.persistent_state);
+        // eframe swallows serialization errors, so read it back to see if it actually worked.
+        let stored = eframe::get_value::<PersistentAppState>(storage, eframe::APP_KEY);
+        if stored.as_ref() != Some(&self.persistent_state) {
+            log::warn!("Could not save settings, they will not persist to the next session");
+        }
+    }
+}
+
+/// Load our persistent state from eframe storage, falling back to defaults if storage is
+/// unavailable or holds something we cannot deserialize.
+fn load_persistent_data(storage: Option<&dyn eframe::Storage>) -> PersistentAppState {
+    let Some(storage) = storage else {
+        log::warn!("No storage available, settings will not persist");
+        return PersistentAppState::default();
+    };
+    match eframe::get_value::<PersistentAppState>(storage, eframe::APP_KEY) {
+        Some(state) => state,
+        None => {
+            if storage.get_string(eframe::APP_KEY).is_some() {
+                log::warn!("Stored settings could not be read, using defaults");
+            }
+            PersistentAppState::default()
+        }
     }
 }
 
@@ -84,6 +220,7 @@ trait GuiView<T: Platform> {
         platform: &T,
         ctx: &egui::Context,
         persistent_state: &mut PersistentAppState,
+        transient_state: &mut TransientState,
     ) -> Result<AppState<T>, Error>;
 }
 
@@ -93,11 +230,13 @@ impl<T: Platform> GuiView<T> for Error {
         platform: &T,
         ctx: &egui::Context,
         persistent_state: &mut PersistentAppState,
+        _transient_state: &mut TransientState,
     ) -> Result<AppState<T>, Error> {
         egui::TopBottomPanel::bottom("about").show(ctx, add_about_contents);
         let repopulate = egui::CentralPanel::default()
             .show(ctx, |ui| {
-                ui.heading(format!("ERROR! {:?}", self));
+                ui.heading(format!("ERROR! {}", self));
+                ui.label(egui::RichText::new(format!("{:?}", self)).small());
                 if ui.button("Refresh").clicked() {
                     return true;
                 }
@@ -117,10 +256,221 @@ trait EguiAppState<T: Platform> {
     /// Add the non-fatal errors from manifest parsing to the UI
     fn add_non_fatal_errors_listing(&self, ui: &mut egui::Ui);
 
+    /// Adds a read-only listing of API layers, if there are any.
+    fn add_api_layer_listing(&self, ui: &mut egui::Ui);
+
+    /// Adds a read-only listing of the directories searched for runtimes, if there are any.
+    fn add_search_summary_listing(&self, ui: &mut egui::Ui);
+
     /// Adds a grid with the runtimes to the given `egui::Ui`, handling "make active" button presses.
     ///
-    /// Returns an error (in which case that becomes the new state), or a boolean indicating whether to refresh.
-    fn add_runtime_grid(&self, platform: &T, ui: &mut egui::Ui) -> Result<bool, Error>;
+    /// Only runtimes matching the filter are shown, in the chosen sort order.
+    ///
+    /// Returns an error, in which case that becomes the new state. Runtimes made active are
+    /// noted in `transient_state`.
+    fn add_runtime_grid(
+        &self,
+        platform: &T,
+        persistent_state: &PersistentAppState,
+        transient_state: &mut TransientState,
+        ui: &mut egui::Ui,
+    ) -> Result<(), Error>;
+}
+
+/// Adds a glyph marking a runtime as active, colored for contrast with the current theme.
+fn add_active_marker(ui: &mut egui::Ui) {
+    let color = if ui.visuals().dark_mode {
+        Color32::LIGHT_GREEN
+    } else {
+        Color32::DARK_GREEN
+    };
+    ui.label(egui::RichText::new("✔").color(color))
+        .on_hover_text("Active");
+}
+
+/// Show the given file in the file manager: selected in its folder where the platform supports
+/// that, otherwise by opening its folder.
+fn show_in_folder(path: &Path, capabilities: PlatformCapabilities) -> io::Result<()> {
+    if capabilities.file_manager_selects_file {
+        std::process::Command::new("explorer")
+            .arg("/select,")
+            .arg(path)
+            .spawn()
+            .map(|_| ())
+    } else {
+        let dir = path.parent().unwrap_or(path);
+        opener::open(dir).map_err(io::Error::other)
+    }
+}
+
+/// Adds a text box for editing a runtime's name: Enter or clicking away saves, Escape cancels.
+fn add_rename_editor(ui: &mut egui::Ui, rename: &mut RenameState) {
+    let response = ui.add(
+        egui::TextEdit::singleline(&mut rename.name)
+            .hint_text("Leave blank for the runtime's own name"),
+    );
+    if !response.has_focus() && !response.lost_focus() {
+        response.request_focus();
+    }
+    if response.lost_focus() {
+        if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+            // No longer matches any runtime, so nothing gets saved
+            rename.key.clear();
+        }
+        rename.done = true;
+    }
+}
+
+/// Adds the actions for a runtime's context menu, which mostly operate on its first manifest.
+fn add_runtime_context_menu<R: PlatformRuntime>(
+    ui: &mut egui::Ui,
+    capabilities: PlatformCapabilities,
+    runtime: &R,
+    display_name: &str,
+    renaming: &mut Option<RenameState>,
+) {
+    if ui.button("Rename…").clicked() {
+        *renaming = Some(RenameState {
+            key: runtime.uniqueness_key(),
+            name: display_name.to_owned(),
+            done: false,
+        });
+        ui.close_menu();
+    }
+    let Some(manifest) = runtime.get_manifests().first().map(|p| p.to_path_buf()) else {
+        ui.label("No manifest");
+        return;
+    };
+    if ui.button("Copy manifest path").clicked() {
+        ui.output_mut(|o| o.copied_text = manifest.display().to_string());
+        ui.close_menu();
+    }
+    if ui.button("Open containing folder").clicked() {
+        if let Err(e) = show_in_folder(&manifest, capabilities) {
+            log::error!("Could not show {} in its folder: {}", manifest.display(), e);
+        }
+        ui.close_menu();
+    }
+    // Like launching, this only names one manifest: the 64-bit one, for a runtime with both
+    if let Some(command) = runtime
+        .env_override_shell_command()
+        .filter(|_| capabilities.supports_env_override)
+    {
+        if ui
+            .button("Copy command to use in a terminal")
+            .on_hover_text(format!(
+                "Programs started from a terminal after running this use this runtime, \
+                without changing the active runtime:\n{}",
+                command
+            ))
+            .clicked()
+        {
+            ui.output_mut(|o| o.copied_text = command);
+            ui.close_menu();
+        }
+    }
+}
+
+/// Make a runtime active, logging any error.
+fn make_active<R: PlatformRuntime>(runtime: &R, options: &MakeActiveOptions) -> Result<(), Error> {
+    runtime.make_active_with_options(options).map_err(|e| {
+        log::error!("error in make_active: {:?}", e);
+        e
+    })
+}
+
+/// Describe roughly how long ago something happened, e.g. "3 days ago".
+fn describe_time_since(when: SystemTime) -> String {
+    // A time in the future means the clock changed: treat it as just now
+    let secs = when.elapsed().map(|d| d.as_secs()).unwrap_or_default();
+    let (count, unit) = match secs {
+        0..=59 => return "just now".to_owned(),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{} {}{} ago", count, unit, plural)
+}
+
+/// Shows a window asking whether to make the named runtime active.
+///
+/// Returns `Some(true)` if confirmed, `Some(false)` if cancelled, and `None` if not answered yet.
+fn confirm_make_active_window(
+    ctx: &egui::Context,
+    runtime_name: &str,
+    may_need_elevation: bool,
+) -> Option<bool> {
+    egui::Window::new("Change active runtime")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "Set {} as the active OpenXR runtime? This affects all applications.",
+                runtime_name
+            ));
+            if may_need_elevation {
+                ui.label("This may need the app to be run as administrator.");
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Make active").clicked() {
+                    return Some(true);
+                }
+                if ui.button("Cancel").clicked() {
+                    return Some(false);
+                }
+                None
+            })
+            .inner
+        })
+        .and_then(|response| response.inner)
+        .flatten()
+}
+
+/// Adds a clickable table header that sorts the runtime grid by `column`.
+fn add_sortable_header(
+    ui: &mut egui::Ui,
+    text: &str,
+    column: SortColumn,
+    sort: &mut Option<RuntimeSort>,
+) {
+    let text = format!("{}{}", text, RuntimeSort::indicator(*sort, column));
+    if ui
+        .add(egui::Button::new(egui::RichText::new(text).size(TABLE_HEADER_TEXT_SIZE)).frame(false))
+        .on_hover_text("Sort by this column")
+        .clicked()
+    {
+        *sort = Some(RuntimeSort::toggled(*sort, column));
+    }
+}
+
+/// Does the runtime's name (as displayed) or description contain `filter`, ignoring case?
+///
+/// An empty filter matches everything.
+fn runtime_matches_filter<R: PlatformRuntime>(
+    runtime: &R,
+    display_name: &str,
+    filter: &str,
+) -> bool {
+    let filter = filter.trim().to_lowercase();
+    filter.is_empty()
+        || display_name.to_lowercase().contains(&filter)
+        || runtime.describe().to_lowercase().contains(&filter)
+}
+
+/// Pick a program and run it with the given runtime selected just for it, via `XR_RUNTIME_JSON`.
+fn launch_with_runtime<R: PlatformRuntime>(runtime: &R) {
+    let Some(program) = rfd::FileDialog::new()
+        .set_title("Choose a program to launch")
+        .pick_file()
+    else {
+        return;
+    };
+    // For a runtime with both, this uses the 64-bit manifest, so 32-bit programs can't load it
+    if let Err(e) = runtime.command_with_runtime(program.as_os_str()).spawn() {
+        log::error!("Could not launch {}: {}", program.display(), e);
+    }
 }
 
 impl<T: Platform> EguiAppState<T> for AppState<T> {
@@ -138,48 +488,281 @@ impl<T: Platform> EguiAppState<T> for AppState<T> {
         );
     }
 
-    fn add_runtime_grid(&self, platform: &T, ui: &mut egui::Ui) -> Result<bool, Error> {
-        // The closure this calls returns true if we should refresh the list
-        egui::containers::ScrollArea::horizontal()
+    fn add_runtime_grid(
+        &self,
+        platform: &T,
+        persistent_state: &PersistentAppState,
+        transient_state: &mut TransientState,
+        ui: &mut egui::Ui,
+    ) -> Result<(), Error> {
+        let options = &persistent_state.make_active_options();
+        let capabilities = platform.capabilities();
+        egui::containers::ScrollArea::both()
             .show(ui, |ui| {
-                egui::Grid::new("runtimes")
+                add_runtime_count_summary(ui, self, persistent_state, &transient_state.filter);
+                let result = egui::Grid::new("runtimes")
                     .striped(true)
                     .min_col_width(ui.spacing().interact_size.x * 2.0) // widen to avoid resizing based on default runtime
                     .min_row_height(ui.spacing().interact_size.y * 2.5)
-                    .num_columns(4)
-                    .show(ui, |ui| -> Result<bool, Error> {
-                        let mut repopulate = false;
+                    .num_columns(5)
+                    .show(ui, |ui| -> Result<(), Error> {
                         ui.label(""); // for button
-                        ui.label(egui::RichText::new("Runtime Name").size(TABLE_HEADER_TEXT_SIZE));
-                        ui.label(egui::RichText::new("State").size(TABLE_HEADER_TEXT_SIZE));
+                        add_sortable_header(
+                            ui,
+                            "Runtime Name",
+                            SortColumn::Name,
+                            &mut transient_state.sort,
+                        );
+                        add_sortable_header(
+                            ui,
+                            "State",
+                            SortColumn::State,
+                            &mut transient_state.sort,
+                        );
+                        let arch_header =
+                            ui.label(egui::RichText::new("Arch").size(TABLE_HEADER_TEXT_SIZE));
+                        if capabilities.has_split_bitness {
+                            arch_header.on_hover_text(
+                                "64-bit and 32-bit apps each have their own active runtime",
+                            );
+                        }
                         ui.label(egui::RichText::new("Details").size(TABLE_HEADER_TEXT_SIZE));
                         ui.end_row();
 
-                        for runtime in &self.runtimes {
-                            let runtime_active_state =
-                                platform.get_runtime_active_state(runtime, &self.active_data);
-                            if runtime_active_state.should_provide_make_active_button() {
-                                if ui.button("Make active").clicked() {
-                                    if let Err(e) = runtime.make_active() {
-                                        eprintln!("error in make_active: {:?}", e);
-                                        return Err(e);
+                        // Sort indices rather than the runtimes themselves, refreshing relies on their order.
+                        let active_states: Vec<ActiveState> = self
+                            .runtimes
+                            .iter()
+                            .map(|r| platform.get_runtime_active_state(r, &self.active_data))
+                            .collect();
+                        let mut order: Vec<usize> = (0..self.runtimes.len())
+                            .filter(|&i| {
+                                let runtime = &self.runtimes[i];
+                                runtime_matches_filter(
+                                    runtime,
+                                    &persistent_state.display_name(runtime),
+                                    &transient_state.filter,
+                                )
+                            })
+                            .collect();
+                        if let Some(sort) = transient_state.sort {
+                            order.sort_by(|&a, &b| {
+                                let ordering = match sort.column {
+                                    SortColumn::Name => persistent_state
+                                        .display_name(&self.runtimes[a])
+                                        .to_lowercase()
+                                        .cmp(
+                                            &persistent_state
+                                                .display_name(&self.runtimes[b])
+                                                .to_lowercase(),
+                                        ),
+                                    SortColumn::State => active_state_sort_rank(active_states[a])
+                                        .cmp(&active_state_sort_rank(active_states[b])),
+                                };
+                                if sort.descending {
+                                    ordering.reverse()
+                                } else {
+                                    ordering
+                                }
+                            });
+                        }
+
+                        // Moving rows only makes sense when they are shown in their stored order
+                        let reorderable =
+                            transient_state.sort.is_none() && transient_state.filter.trim().is_empty();
+                        let num_runtimes = self.runtimes.len();
+
+                        for i in order {
+                            let runtime = &self.runtimes[i];
+                            let runtime_active_state = active_states[i];
+                            let make_active_result = ui
+                                .vertical(|ui| -> Result<(), Error> {
+                                    if runtime_active_state.should_provide_make_active_button()
+                                        && ui.button("Make active").clicked()
+                                    {
+                                        if platform.active_runtime_is_system_wide() {
+                                            transient_state.pending_make_active =
+                                                Some(runtime.uniqueness_key());
+                                        } else {
+                                            make_active(runtime, options)?;
+                                            transient_state.activated =
+                                                Some(runtime.uniqueness_key());
+                                        }
                                     }
-                                    repopulate = true;
+                                    if ui
+                                        .button("Launch with this runtime…")
+                                        .on_hover_text(
+                                            "Run a program using this runtime, without changing the active runtime",
+                                        )
+                                        .clicked()
+                                    {
+                                        launch_with_runtime(runtime);
+                                    }
+                                    if reorderable {
+                                        ui.horizontal(|ui| {
+                                            if ui
+                                                .add_enabled(i > 0, egui::Button::new("⏶").small())
+                                                .on_hover_text("Move up")
+                                                .clicked()
+                                            {
+                                                transient_state.pending_move = Some((i, i - 1));
+                                            }
+                                            if ui
+                                                .add_enabled(
+                                                    i + 1 < num_runtimes,
+                                                    egui::Button::new("⏷").small(),
+                                                )
+                                                .on_hover_text("Move down")
+                                                .clicked()
+                                            {
+                                                transient_state.pending_move = Some((i, i + 1));
+                                            }
+                                        });
+                                    }
+                                    Ok(())
+                                })
+                                .inner;
+                            make_active_result?;
+                            ui.horizontal(|ui| {
+                                if runtime_active_state.is_active() {
+                                    add_active_marker(ui);
+                                }
+                                let key = runtime.uniqueness_key();
+                                match &mut transient_state.renaming {
+                                    Some(rename) if rename.key == key => {
+                                        add_rename_editor(ui, rename)
+                                    }
+                                    _ => {
+                                        let label = match runtime.get_runtime_version() {
+                                            Some(version) => format!(
+                                                "{} ({})",
+                                                persistent_state.display_name(runtime),
+                                                version
+                                            ),
+                                            None => persistent_state.display_name(runtime),
+                                        };
+                                        ui.label(label)
+                                            .interact(egui::Sense::click())
+                                            .on_hover_text("Right-click for more actions")
+                                            .context_menu(|ui| {
+                                                add_runtime_context_menu(
+                                                    ui,
+                                                    capabilities,
+                                                    runtime,
+                                                    &persistent_state.display_name(runtime),
+                                                    &mut transient_state.renaming,
+                                                )
+                                            });
+                                    }
+                                }
+                            });
+                            ui.vertical(|ui| {
+                                let state_label = ui.label(format!("{}", runtime_active_state));
+                                if let Some(reason) =
+                                    platform.describe_active_reason(runtime, &self.active_data)
+                                {
+                                    state_label.on_hover_text(reason);
+                                }
+                                if let Some(when) = persistent_state.last_activated(runtime) {
+                                    ui.weak(format!("Last made active {}", describe_time_since(when)));
                                 }
+                            });
+                            if !runtime.is_arch_description_resolved() {
+                                ui.weak("…");
+                            } else if runtime.is_compatible_with_host() {
+                                ui.label(runtime.get_arch_description());
                             } else {
-                                ui.label("");
+                                ui.colored_label(
+                                    ui.visuals().warn_fg_color,
+                                    runtime.get_arch_description(),
+                                )
+                                .on_hover_text("Apps on this computer can't load this runtime");
                             }
-                            ui.label(runtime.get_runtime_name());
-                            ui.label(format!("{}", runtime_active_state));
                             ui.label(runtime.describe());
                             ui.end_row();
                         }
-                        Ok(repopulate)
+
+                        // An active runtime that we could not load still belongs in the list
+                        for e in self
+                            .nonfatal_errors
+                            .iter()
+                            .filter(|e| e.is_for_active_runtime())
+                        {
+                            ui.label("");
+                            ui.horizontal(|ui| {
+                                add_active_marker(ui);
+                                ui.colored_label(
+                                    ui.visuals().warn_fg_color,
+                                    "Unusable active runtime",
+                                );
+                            });
+                            ui.label(format!("{}", ActiveState::ActiveIndependentRuntime));
+                            ui.label("");
+                            ui.label(format!("{}\n{}", e.0.display(), e.1));
+                            ui.end_row();
+                        }
+                        Ok(())
                     })
-                    .inner
+                    .inner;
+                self.add_api_layer_listing(ui);
+                self.add_search_summary_listing(ui);
+                result
             })
             .inner
     }
+
+    fn add_api_layer_listing(&self, ui: &mut egui::Ui) {
+        if self.api_layers.is_empty() {
+            return;
+        }
+        ui.separator();
+        egui::CollapsingHeader::new("API Layers").show(ui, |ui| {
+            egui::Grid::new("api_layers")
+                .striped(true)
+                .num_columns(4)
+                .show(ui, |ui| {
+                    ui.label(egui::RichText::new("Layer Name").size(TABLE_HEADER_TEXT_SIZE));
+                    ui.label(egui::RichText::new("Type").size(TABLE_HEADER_TEXT_SIZE));
+                    ui.label(egui::RichText::new("Version").size(TABLE_HEADER_TEXT_SIZE));
+                    ui.label(egui::RichText::new("Details").size(TABLE_HEADER_TEXT_SIZE));
+                    ui.end_row();
+
+                    for layer in &self.api_layers {
+                        let name = ui.label(layer.get_name());
+                        if let Some(description) = layer.get_description() {
+                            name.on_hover_text(description);
+                        }
+                        ui.label(layer.get_kind().to_string());
+                        ui.label(format!(
+                            "{} (API {})",
+                            layer.get_implementation_version(),
+                            layer.get_api_version()
+                        ));
+                        let details = match layer.get_disable_environment() {
+                            Some(var) => {
+                                format!("{}\nDisable by setting {}", layer.describe(), var)
+                            }
+                            None => layer.describe(),
+                        };
+                        ui.label(details);
+                        ui.end_row();
+                    }
+                });
+        });
+    }
+
+    fn add_search_summary_listing(&self, ui: &mut egui::Ui) {
+        if self.search_summary.is_empty() {
+            return;
+        }
+        ui.separator();
+        egui::CollapsingHeader::new("Search paths").show(ui, |ui| {
+            ui.label("Runtime manifests are found in these directories, in this order:");
+            for dir in &self.search_summary {
+                ui.label(dir.to_string());
+            }
+        });
+    }
 }
 
 /// The app-wide action to take, based on the options in the header.
@@ -193,12 +776,24 @@ enum HeaderAction {
     Browse,
     /// Forget the extra manifests we added
     Forget,
+    /// Browse for an extra directory to search for manifests
+    AddSearchDir,
+    /// Forget the extra search directories we added
+    ForgetSearchDirs,
+    /// Open the config directory in the file manager
+    OpenConfigFolder,
+    /// Return to having no active runtime
+    ClearActive,
+    /// Switch to the next theme preference
+    CycleTheme,
+    /// Close the app
+    Quit,
 }
 
 impl HeaderAction {
     /// Does this header action imply the need to refresh the runtime list?
-    fn should_refresh(&self, new_extra_paths: &[PathBuf]) -> bool {
-        if !new_extra_paths.is_empty() {
+    fn should_refresh(&self, added_extra_paths: bool) -> bool {
+        if added_extra_paths {
             return true;
         }
         match self {
@@ -206,19 +801,133 @@ impl HeaderAction {
             HeaderAction::Refresh => true,
             HeaderAction::Browse => false, // if we browsed successfully we would have a new path above
             HeaderAction::Forget => true,
+            HeaderAction::AddSearchDir => true,
+            HeaderAction::ForgetSearchDirs => true,
+            HeaderAction::OpenConfigFolder => false,
+            HeaderAction::ClearActive => true,
+            HeaderAction::CycleTheme => false,
+            HeaderAction::Quit => false,
         }
     }
 }
 
-/// Creates a top panel with a header and a refresh button.
-/// returns true if it should refresh
-fn header_with_browse_and_refresh_button(ctx: &egui::Context) -> HeaderAction {
+const QUIT_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Q);
+
+/// Adds the contents of the main menu, returning the action chosen if any.
+fn add_menu_contents(
+    ui: &mut egui::Ui,
+    capabilities: PlatformCapabilities,
+    has_config_dir: bool,
+    has_active_runtime: bool,
+    search_dirs: SearchDirMenu,
+) -> Option<HeaderAction> {
+    if capabilities.supports_clear
+        && ui
+            .add_enabled(
+                has_active_runtime,
+                egui::Button::new("Clear active runtime"),
+            )
+            .on_hover_text("Leave no runtime active")
+            .clicked()
+    {
+        ui.close_menu();
+        return Some(HeaderAction::ClearActive);
+    }
+    if ui
+        .add_enabled(has_config_dir, egui::Button::new("Open config folder"))
+        .clicked()
+    {
+        ui.close_menu();
+        return Some(HeaderAction::OpenConfigFolder);
+    }
+    if search_dirs.supported {
+        if ui
+            .button("Add search folder…")
+            .on_hover_text("Also look for runtime manifests in a folder")
+            .clicked()
+        {
+            ui.close_menu();
+            return Some(HeaderAction::AddSearchDir);
+        }
+        if ui
+            .add_enabled(
+                search_dirs.any_added,
+                egui::Button::new("Forget search folders"),
+            )
+            .clicked()
+        {
+            ui.close_menu();
+            return Some(HeaderAction::ForgetSearchDirs);
+        }
+    }
+    if ui
+        .add(egui::Button::new("Quit").shortcut_text(ui.ctx().format_shortcut(&QUIT_SHORTCUT)))
+        .clicked()
+    {
+        ui.close_menu();
+        return Some(HeaderAction::Quit);
+    }
+    None
+}
+
+/// What the menu should offer for extra search folders.
+#[derive(Debug, Clone, Copy)]
+struct SearchDirMenu {
+    /// Whether the platform finds runtimes by searching folders at all
+    supported: bool,
+    /// Whether any have been added, to forget
+    any_added: bool,
+}
+
+/// The glyph and description for the theme toggle button.
+fn theme_button_text(theme: ThemePreference) -> (&'static str, &'static str) {
+    match theme {
+        ThemePreference::System => ("🖥", "Theme: follow system"),
+        ThemePreference::Light => ("☀", "Theme: light"),
+        ThemePreference::Dark => ("🌙", "Theme: dark"),
+    }
+}
+
+/// Creates a top panel with a header, a menu, a refresh button, a theme toggle, and a filter box.
+fn header_with_browse_and_refresh_button(
+    ctx: &egui::Context,
+    capabilities: PlatformCapabilities,
+    has_config_dir: bool,
+    has_active_runtime: bool,
+    search_dirs: SearchDirMenu,
+    theme: ThemePreference,
+    filter: &mut String,
+) -> HeaderAction {
     egui::TopBottomPanel::top("header")
         .show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.heading("XR Runtime Picker for OpenXR™");
 
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if let Some(action) = ui
+                        .menu_button("☰", |ui| {
+                            add_menu_contents(
+                                ui,
+                                capabilities,
+                                has_config_dir,
+                                has_active_runtime,
+                                search_dirs,
+                            )
+                        })
+                        .inner
+                        .flatten()
+                    {
+                        return action;
+                    }
+                    let (theme_glyph, theme_description) = theme_button_text(theme);
+                    if ui
+                        .button(theme_glyph)
+                        .on_hover_text(format!("{} (click to change)", theme_description))
+                        .clicked()
+                    {
+                        return HeaderAction::CycleTheme;
+                    }
                     if ui
                         .button("🔃")
                         .on_hover_text("Refresh runtime list")
@@ -240,6 +949,11 @@ fn header_with_browse_and_refresh_button(ctx: &egui::Context) -> HeaderAction {
                     {
                         return HeaderAction::Forget;
                     }
+                    ui.add(
+                        egui::TextEdit::singleline(filter)
+                            .hint_text("Filter runtimes")
+                            .desired_width(ui.spacing().text_edit_width / 2.0),
+                    );
                     HeaderAction::Nothing
                 })
                 .inner
@@ -249,13 +963,123 @@ fn header_with_browse_and_refresh_button(ctx: &egui::Context) -> HeaderAction {
         .inner
 }
 
+/// Explain that the environment is overriding the runtime we would change.
+fn add_active_override_warning(ui: &mut egui::Ui, active_override: &ActiveRuntimeOverride) {
+    let configured = if active_override.configured.is_empty() {
+        "nothing".to_owned()
+    } else {
+        active_override
+            .configured
+            .iter()
+            .map(|p| p.display())
+            .join(", ")
+    };
+    ui.colored_label(
+        ui.visuals().warn_fg_color,
+        format!(
+            "Effective: {} ({}); configured file points at: {}",
+            active_override.effective.display(),
+            XR_RUNTIME_JSON,
+            configured
+        ),
+    );
+    ui.label(format!(
+        "Changes made here will not take effect until {} is unset.",
+        XR_RUNTIME_JSON
+    ));
+}
+
+/// Explain which active runtime setting the loader uses, and which ones it overrides.
+fn add_active_candidates_explanation(
+    ui: &mut egui::Ui,
+    candidates: &[(ConfigLocation, ConfigSource)],
+) {
+    let Some(((effective, effective_source), overridden)) = candidates.split_first() else {
+        return;
+    };
+    ui.label(format!(
+        "Active runtime comes from {} ({}), overriding:",
+        effective, effective_source
+    ));
+    for (location, source) in overridden {
+        ui.label(format!("    {} ({})", location, source));
+    }
+}
+
+/// Show a warning with a button to dismiss it, returning whether it was dismissed.
+fn add_dismissable_warning(ui: &mut egui::Ui, message: &str) -> bool {
+    ui.horizontal(|ui| {
+        ui.colored_label(ui.visuals().warn_fg_color, message);
+        ui.button("Dismiss").clicked()
+    })
+    .inner
+}
+
+/// Say how many runtimes there are, or explain what to do if there are none,
+/// so an empty grid does not look broken.
+fn add_runtime_count_summary<T: Platform>(
+    ui: &mut egui::Ui,
+    state: &AppState<T>,
+    persistent_state: &PersistentAppState,
+    filter: &str,
+) {
+    let total = state.runtimes.len();
+    if total == 0 {
+        ui.label("No OpenXR runtimes found. Install a runtime, or use 🗁 to add a manifest.");
+        if !state.nonfatal_errors.is_empty() {
+            ui.label("Some manifests could not be loaded: see the errors below.");
+        }
+        return;
+    }
+    let shown = state
+        .runtimes
+        .iter()
+        .filter(|r| runtime_matches_filter(*r, &persistent_state.display_name(*r), filter))
+        .count();
+    let plural = if total == 1 { "" } else { "s" };
+    if shown == total {
+        ui.label(format!("{} runtime{} found", total, plural));
+    } else {
+        ui.label(format!("Showing {} of {} runtime{}", shown, total, plural));
+    }
+}
+
+/// Explain that the active runtime reference points at a manifest that is gone.
+fn add_dangling_active_warning(ui: &mut egui::Ui, link: &ConfigLocation, target: &Path) {
+    ui.colored_label(
+        ui.visuals().warn_fg_color,
+        format!(
+            "Active runtime link is broken: {} points at {}, which no longer exists.",
+            link,
+            target.display()
+        ),
+    );
+}
+
+/// Explain that the active runtime reference is a symlink that leads back to itself.
+fn add_symlink_loop_warning(ui: &mut egui::Ui, link: &Path) {
+    ui.colored_label(
+        ui.visuals().warn_fg_color,
+        format!(
+            "Active runtime link is broken: {} is a symlink that leads back to itself.",
+            link.display()
+        ),
+    );
+}
+
 impl<T: Platform> GuiView<T> for AppState<T> {
     fn update(
         mut self,
         platform: &T,
         ctx: &egui::Context,
         persistent_state: &mut PersistentAppState,
+        transient_state: &mut TransientState,
     ) -> Result<AppState<T>, Error> {
+        // One runtime per frame, so the window stays responsive
+        if self.resolve_next_arch_description() {
+            ctx.request_repaint();
+        }
+
         egui::TopBottomPanel::bottom("about").show(ctx, add_about_contents);
 
         if !self.nonfatal_errors.is_empty() {
@@ -263,7 +1087,59 @@ impl<T: Platform> GuiView<T> for AppState<T> {
                 .show(ctx, |ui| self.add_non_fatal_errors_listing(ui));
         }
 
-        let header_action = header_with_browse_and_refresh_button(ctx);
+        let config_dir = platform.get_config_directory();
+        let has_active_runtime = self.runtimes.iter().any(|r| {
+            !matches!(
+                platform.get_runtime_active_state(r, &self.active_data),
+                ActiveState::NotActive
+            )
+        }) || self
+            .nonfatal_errors
+            .iter()
+            .any(|e| e.is_for_active_runtime());
+        let capabilities = platform.capabilities();
+        let header_action = header_with_browse_and_refresh_button(
+            ctx,
+            capabilities,
+            config_dir.is_some(),
+            has_active_runtime,
+            SearchDirMenu {
+                supported: capabilities.supports_extra_search_dirs,
+                any_added: !persistent_state.extra_search_dirs.is_empty(),
+            },
+            persistent_state.theme,
+            &mut transient_state.filter,
+        );
+
+        if let Some(warning) = platform.get_sandbox_warning() {
+            egui::TopBottomPanel::top("sandbox_warning").show(ctx, |ui| {
+                ui.colored_label(ui.visuals().warn_fg_color, warning)
+            });
+        }
+
+        if let Some(active_override) = platform.get_active_runtime_override(&self.active_data) {
+            egui::TopBottomPanel::top("active_override")
+                .show(ctx, |ui| add_active_override_warning(ui, &active_override));
+        }
+
+        // Only worth explaining if there is more than one setting competing
+        if self.active_candidates.len() > 1 {
+            egui::TopBottomPanel::top("active_candidates").show(ctx, |ui| {
+                add_active_candidates_explanation(ui, &self.active_candidates)
+            });
+        }
+
+        match &self.active_diagnosis {
+            ActiveRuntimeDiagnosis::Dangling { link, target } => {
+                egui::TopBottomPanel::top("dangling_active")
+                    .show(ctx, |ui| add_dangling_active_warning(ui, link, target));
+            }
+            ActiveRuntimeDiagnosis::SymlinkLoop { link } => {
+                egui::TopBottomPanel::top("dangling_active")
+                    .show(ctx, |ui| add_symlink_loop_warning(ui, link));
+            }
+            _ => {}
+        }
 
         let mut new_extra_paths = vec![];
 
@@ -272,7 +1148,7 @@ impl<T: Platform> GuiView<T> for AppState<T> {
             HeaderAction::Refresh => {}
             HeaderAction::Browse => {
                 if let Some(p) = rfd::FileDialog::new().pick_file() {
-                    println!("Got a new path from file dialog: {}", p.display());
+                    log::debug!("Got a new path from file dialog: {}", p.display());
                     new_extra_paths.push(p);
                 }
             }
@@ -281,6 +1157,44 @@ impl<T: Platform> GuiView<T> for AppState<T> {
                 // Must also clear runtimes because extra manifests that exist and are valid will show up here.
                 self.runtimes.clear();
             }
+            HeaderAction::AddSearchDir => {
+                if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                    log::debug!(
+                        "Got a new search folder from file dialog: {}",
+                        dir.display()
+                    );
+                    if !persistent_state.extra_search_dirs.contains(&dir) {
+                        persistent_state.extra_search_dirs.push(dir);
+                    }
+                }
+            }
+            HeaderAction::ForgetSearchDirs => {
+                persistent_state.extra_search_dirs.clear();
+                // Must also clear runtimes, or the ones found there would be kept by refreshing.
+                self.runtimes.clear();
+            }
+            HeaderAction::OpenConfigFolder => {
+                if let Some(dir) = &config_dir {
+                    // It might not exist yet if we have never set an active runtime
+                    if let Err(e) = fs::create_dir_all(dir)
+                        .and_then(|_| opener::open(dir).map_err(io::Error::other))
+                    {
+                        log::error!("Could not open config folder {}: {}", dir.display(), e);
+                    }
+                }
+            }
+            HeaderAction::ClearActive => {
+                if let Err(e) = platform.clear_active_runtime() {
+                    log::error!("error in clear_active_runtime: {:?}", e);
+                    return Err(e);
+                }
+            }
+            HeaderAction::CycleTheme => {
+                persistent_state.theme = persistent_state.theme.next();
+            }
+            HeaderAction::Quit => {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
         }
 
         // handle drag and drop
@@ -288,23 +1202,94 @@ impl<T: Platform> GuiView<T> for AppState<T> {
             if !i.raw.dropped_files.is_empty() {
                 for file in &i.raw.dropped_files {
                     if let Some(p) = &file.path {
-                        println!("Got a new path from drag and drop: {}", p.display());
+                        log::debug!("Got a new path from drag and drop: {}", p.display());
                         new_extra_paths.push(p.clone());
                     }
                 }
             }
         });
+        let added_extra_paths = !new_extra_paths.is_empty();
+        for path in new_extra_paths {
+            if let Err(e) = self.try_add_extra_manifest(platform, persistent_state, path.clone()) {
+                log::warn!("Could not add manifest {}: {}", path.display(), e);
+                transient_state.add_manifest_error =
+                    Some(format!("Could not add {}: {}", path.display(), e));
+            }
+        }
+
+        if let Some(message) = &transient_state.add_manifest_error {
+            let dismissed = egui::TopBottomPanel::top("add_manifest_error")
+                .show(ctx, |ui| add_dismissable_warning(ui, message))
+                .inner;
+            if dismissed {
+                transient_state.add_manifest_error = None;
+            }
+        }
 
         // Central panel must come last
-        let should_refresh = header_action.should_refresh(&new_extra_paths)
-            || egui::CentralPanel::default()
-                .show(ctx, |ui| self.add_runtime_grid(platform, ui))
-                .inner?; // get at the nested closure's return value (whether to repopulate), after handling errors.
+        let should_refresh = header_action.should_refresh(added_extra_paths);
+        if !should_refresh {
+            egui::CentralPanel::default()
+                .show(ctx, |ui| {
+                    self.add_runtime_grid(platform, persistent_state, transient_state, ui)
+                })
+                .inner?; // get at the nested closure's return value, to handle errors.
+        }
+
+        if transient_state.renaming.as_ref().is_some_and(|r| r.done) {
+            if let Some(rename) = transient_state.renaming.take() {
+                if !rename.key.is_empty() {
+                    persistent_state.set_custom_name(rename.key, &rename.name);
+                }
+            }
+        }
+
+        let mut made_active = false;
+        if let Some(key) = transient_state.activated.take() {
+            persistent_state.record_activation(key);
+            made_active = true;
+        }
+
+        if let Some((from, to)) = transient_state.pending_move.take() {
+            self.move_runtime(from, to);
+            persistent_state.runtime_order = self.runtime_order();
+        }
 
-        persistent_state.append_new_extra_paths(new_extra_paths);
+        if let Some(key) = &transient_state.pending_make_active {
+            match self.runtimes.iter().find(|r| &r.uniqueness_key() == key) {
+                Some(runtime) => {
+                    match confirm_make_active_window(
+                        ctx,
+                        &persistent_state.display_name(runtime),
+                        platform.capabilities().may_need_elevation,
+                    ) {
+                        Some(true) => {
+                            transient_state.pending_make_active = None;
+                            make_active(runtime, &persistent_state.make_active_options())?;
+                            persistent_state.record_activation(runtime.uniqueness_key());
+                            made_active = true;
+                        }
+                        Some(false) => transient_state.pending_make_active = None,
+                        None => {}
+                    }
+                }
+                // It went away in a refresh
+                None => transient_state.pending_make_active = None,
+            }
+        }
+        // Making a runtime active, here or elsewhere, usually only needs the active state re-read,
+        // which also keeps the list in the same order
+        let changed_externally = transient_state
+            .changed_externally
+            .swap(false, Ordering::Relaxed);
+        let needs_full_refresh =
+            (made_active || changed_externally) && !self.refresh_active_state(platform);
+        let should_refresh = should_refresh || needs_full_refresh;
 
         if should_refresh {
-            return self.refresh(platform, Some(persistent_state));
+            let state = self.refresh(platform, Some(persistent_state))?;
+            persistent_state.migrate_runtime_keys(&state.runtimes);
+            return Ok(state);
         }
         Ok(self)
     }
@@ -316,10 +1301,11 @@ impl<T: Platform> GuiView<T> for Result<AppState<T>, Error> {
         platform: &T,
         ctx: &egui::Context,
         persistent_state: &mut PersistentAppState,
+        transient_state: &mut TransientState,
     ) -> Result<AppState<T>, Error> {
         match self {
-            Ok(state) => state.update(platform, ctx, persistent_state),
-            Err(e) => e.update(platform, ctx, persistent_state),
+            Ok(state) => state.update(platform, ctx, persistent_state, transient_state),
+            Err(e) => e.update(platform, ctx, persistent_state, transient_state),
         }
     }
 }
@@ -328,36 +1314,125 @@ const HEADING_TEXT_SIZE: f32 = 24.0;
 const TABLE_HEADER_TEXT_SIZE: f32 = 18.0;
 const BODY_TEXT_SIZE: f32 = 14.0;
 
-/// Fix visual style for increase readability
-fn update_theme(ctx: &egui::Context) {
-    let mut visuals = egui::Visuals::dark();
+/// Apply the theme preference, fixing visual style for increased readability in both themes
+fn update_theme(ctx: &egui::Context, theme: ThemePreference) {
+    let mut dark = egui::Visuals::dark();
     // Increase contrast
-    visuals.override_text_color = Some(Color32::LIGHT_GRAY);
-    ctx.set_visuals(visuals);
-
-    let mut style = (*ctx.style()).clone();
-    // Increase body font size
-    style
-        .text_styles
-        .entry(TextStyle::Body)
-        .and_modify(|e| e.size = BODY_TEXT_SIZE);
-    // Increase heading text size too
-    style
-        .text_styles
-        .entry(TextStyle::Heading)
-        .and_modify(|e| e.size = HEADING_TEXT_SIZE);
-    ctx.set_style(style);
+    dark.override_text_color = Some(Color32::LIGHT_GRAY);
+    ctx.set_visuals_of(egui::Theme::Dark, dark);
+
+    let mut light = egui::Visuals::light();
+    light.override_text_color = Some(Color32::BLACK);
+    ctx.set_visuals_of(egui::Theme::Light, light);
+
+    ctx.all_styles_mut(|style| {
+        // Increase body font size
+        style
+            .text_styles
+            .entry(TextStyle::Body)
+            .and_modify(|e| e.size = BODY_TEXT_SIZE);
+        // Increase heading text size too
+        style
+            .text_styles
+            .entry(TextStyle::Heading)
+            .and_modify(|e| e.size = HEADING_TEXT_SIZE);
+    });
+
+    ctx.set_theme(match theme {
+        ThemePreference::System => egui::ThemePreference::System,
+        ThemePreference::Light => egui::ThemePreference::Light,
+        ThemePreference::Dark => egui::ThemePreference::Dark,
+    });
+}
+
+const DEFAULT_WINDOW_SIZE: [f32; 2] = [800.0, 256.0];
+
+/// Returns the saved window size if it is usable, clamped to the monitor if we know its size.
+fn sanitize_window_size(size: [f32; 2], monitor_size: Option<egui::Vec2>) -> Option<egui::Vec2> {
+    let size = egui::Vec2::from(size);
+    if !size.is_finite() || size.x < DEFAULT_WINDOW_SIZE[0] || size.y < DEFAULT_WINDOW_SIZE[1] {
+        return None;
+    }
+    Some(match monitor_size {
+        Some(monitor) => size.min(monitor),
+        None => size,
+    })
+}
+
+/// Returns the saved window position if it leaves the window at least partly on the monitor.
+fn sanitize_window_pos(
+    pos: [f32; 2],
+    size: egui::Vec2,
+    monitor_size: Option<egui::Vec2>,
+) -> Option<egui::Pos2> {
+    let pos = egui::Pos2::from(pos);
+    if !pos.is_finite() || pos.x + size.x <= 0.0 || pos.y + size.y <= 0.0 {
+        return None;
+    }
+    match monitor_size {
+        Some(monitor) if pos.x >= monitor.x || pos.y >= monitor.y => None,
+        _ => Some(pos),
+    }
+}
+
+impl<T: Platform> PickerApp<T> {
+    /// Move and resize the window to where it was last time, if that is sensible.
+    fn restore_window_geometry(&self, ctx: &egui::Context) {
+        let monitor_size = ctx.input(|i| i.viewport().monitor_size);
+        let Some(size) = self
+            .persistent_state
+            .window_size
+            .and_then(|size| sanitize_window_size(size, monitor_size))
+        else {
+            return;
+        };
+        ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(size));
+        if let Some(pos) = self
+            .persistent_state
+            .window_pos
+            .and_then(|pos| sanitize_window_pos(pos, size, monitor_size))
+        {
+            ctx.send_viewport_cmd(egui::ViewportCommand::OuterPosition(pos));
+        }
+    }
+
+    /// Remember the current window geometry so it can be saved.
+    fn record_window_geometry(&mut self, ctx: &egui::Context) {
+        let (inner, outer) = ctx.input(|i| (i.viewport().inner_rect, i.viewport().outer_rect));
+        if let Some(inner) = inner {
+            self.persistent_state.window_size = Some(inner.size().into());
+        }
+        if let Some(outer) = outer {
+            self.persistent_state.window_pos = Some(outer.min.into());
+        }
+    }
 }
 
 impl<T: Platform> eframe::App for PickerApp<T> {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if !self.fixed_theme {
-            update_theme(ctx);
-            self.fixed_theme = true;
+        if ctx.input_mut(|i| i.consume_shortcut(&QUIT_SHORTCUT)) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+
+        if self.applied_theme != Some(self.persistent_state.theme) {
+            update_theme(ctx, self.persistent_state.theme);
+            self.applied_theme = Some(self.persistent_state.theme);
+        }
+
+        if self.restored_window_geometry {
+            self.record_window_geometry(ctx);
+        } else {
+            self.restore_window_geometry(ctx);
+            self.restored_window_geometry = true;
         }
 
         if let Some(state_or_error) = self.state.take() {
-            let new_state = state_or_error.update(&self.platform, ctx, &mut self.persistent_state);
+            let new_state = state_or_error.update(
+                &self.platform,
+                ctx,
+                &mut self.persistent_state,
+                &mut self.transient_state,
+            );
             self.state.replace(new_state);
         } else {
             // unlikely/impossible to get here, but let's clean up nicely if we do.
@@ -365,7 +1440,8 @@ impl<T: Platform> eframe::App for PickerApp<T> {
         }
     }
 
-    // Do not save window size/position, it can get messed up.
+    // Do not save egui memory, the window size/position in it can get messed up.
+    // We save the window geometry ourselves in the persistent state instead.
     fn persist_egui_memory(&self) -> bool {
         false
     }
@@ -376,10 +1452,15 @@ impl<T: Platform> eframe::App for PickerApp<T> {
 }
 
 fn main() -> eframe::Result<()> {
+    env_logger::init();
+    let mut viewport = egui::ViewportBuilder::default().with_min_inner_size(DEFAULT_WINDOW_SIZE);
+    if let Some(icon) = load_icon(ICON_48) {
+        viewport = viewport.with_icon(icon);
+    }
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default()
-            .with_min_inner_size([800.0, 256.0])
-            .with_icon(load_icon(ICON_48)),
+        viewport,
+        // We handle this ourselves
+        persist_window: false,
         ..Default::default()
     };
     eframe::run_native(
@@ -388,3 +1469,55 @@ fn main() -> eframe::Result<()> {
         Box::new(|cc| Ok(Box::new(PickerApp::new(make_platform(), cc)))),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eframe::Storage;
+    use std::collections::HashMap;
+
+    /// Storage that only lives in memory.
+    #[derive(Default)]
+    struct MemoryStorage(HashMap<String, String>);
+
+    impl Storage for MemoryStorage {
+        fn get_string(&self, key: &str) -> Option<String> {
+            self.0.get(key).cloned()
+        }
+
+        fn set_string(&mut self, key: &str, value: String) {
+            self.0.insert(key.to_owned(), value);
+        }
+
+        fn flush(&mut self) {}
+    }
+
+    #[test]
+    fn load_persistent_data_falls_back_to_defaults() {
+        assert_eq!(load_persistent_data(None), PersistentAppState::default());
+
+        let mut storage = MemoryStorage::default();
+        assert_eq!(
+            load_persistent_data(Some(&storage)),
+            PersistentAppState::default()
+        );
+
+        storage.set_string(eframe::APP_KEY, "not our settings".to_owned());
+        assert_eq!(
+            load_persistent_data(Some(&storage)),
+            PersistentAppState::default()
+        );
+    }
+
+    #[test]
+    fn load_persistent_data_reads_stored_state() {
+        let state = PersistentAppState {
+            extra_paths: vec![PathBuf::from("/opt/runtime/openxr.json")],
+            arch_decorated: true,
+            ..Default::default()
+        };
+        let mut storage = MemoryStorage::default();
+        eframe::set_value(&mut storage, eframe::APP_KEY, &state);
+        assert_eq!(load_persistent_data(Some(&storage)), state);
+    }
+}